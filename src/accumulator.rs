@@ -0,0 +1,259 @@
+use crate::{
+    color::Color,
+    filter::Filter,
+    ui::pixel::{Pixel, PixelsData},
+};
+
+// Stops dark pixels, where any noise is a large fraction of the brightness, from never converging
+const RELATIVE_ERROR_LUMINANCE_FLOOR: f32 = 0.05;
+
+// Sum of a batch of samples for one pixel, the squared luminances let the accumulator estimate
+// how noisy the pixel still is
+#[derive(Clone, Copy)]
+pub struct SampleSums {
+    pub color: Color,
+    pub luminance_squared: f32,
+    pub count: u32,
+}
+
+impl SampleSums {
+    pub fn zero() -> SampleSums {
+        SampleSums {
+            color: Color::zero(),
+            luminance_squared: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn add(&mut self, color: Color) {
+        let luminance = color.luminance();
+        self.color += color;
+        self.luminance_squared += luminance * luminance;
+        self.count += 1;
+    }
+}
+
+// Samples splatted onto a pixel through the reconstruction filter, the pixel's colour is the
+// weighted average
+#[derive(Clone, Copy)]
+pub struct WeightedColor {
+    pub color: Color,
+    pub weight: f32,
+}
+
+impl WeightedColor {
+    pub fn zero() -> WeightedColor {
+        WeightedColor {
+            color: Color::zero(),
+            weight: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, other: &WeightedColor) {
+        self.color += other.color;
+        self.weight += other.weight;
+    }
+}
+
+// The part of the image one chunk's samples can reach, its pixels grown by the filter's margin on
+// every side and cut off at the image edges. Chunks splat into their own tile so they can be
+// rendered in parallel, then the tiles are added to the accumulator in order.
+pub struct FilmTile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: Vec<WeightedColor>,
+}
+
+impl FilmTile {
+    pub fn new(
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        margin: u32,
+        image_width: u32,
+        image_height: u32,
+    ) -> FilmTile {
+        let start_x = x.saturating_sub(margin);
+        let start_y = y.saturating_sub(margin);
+        let end_x = (x + width).saturating_add(margin).min(image_width);
+        let end_y = (y + height).saturating_add(margin).min(image_height);
+
+        FilmTile {
+            x: start_x,
+            y: start_y,
+            width: end_x - start_x,
+            height: end_y - start_y,
+            pixels: vec![WeightedColor::zero(); ((end_x - start_x) * (end_y - start_y)) as usize],
+        }
+    }
+
+    // Adds a sample taken at (`x`, `y`), in pixels from the image's top left corner, to every
+    // pixel of the tile the filter reaches
+    pub fn splat(&mut self, filter: &Filter, x: f32, y: f32, color: Color) {
+        let (first_x, last_x) = filter.pixel_range(x);
+        let (first_y, last_y) = filter.pixel_range(y);
+
+        let first_x = first_x.max(self.x as i64);
+        let first_y = first_y.max(self.y as i64);
+        let last_x = last_x.min((self.x + self.width) as i64 - 1);
+        let last_y = last_y.min((self.y + self.height) as i64 - 1);
+
+        for pixel_y in first_y..=last_y {
+            for pixel_x in first_x..=last_x {
+                let weight = filter.evaluate(pixel_x as f32 + 0.5 - x, pixel_y as f32 + 0.5 - y);
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let index = (pixel_y - self.y as i64) * self.width as i64 + pixel_x - self.x as i64;
+                let pixel = &mut self.pixels[index as usize];
+                pixel.color += color * weight;
+                pixel.weight += weight;
+            }
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> &WeightedColor {
+        &self.pixels[((y - self.y) * self.width + x - self.x) as usize]
+    }
+}
+
+// Running total of every sample taken for each pixel, so later passes refine the image instead of
+// starting over. Whatever has been added so far always averages out to a valid image.
+pub struct Accumulator {
+    width: u32,
+    height: u32,
+    // Each pixel's own samples, which adaptive sampling judges the pixel's noise by
+    sums: Vec<SampleSums>,
+    // Every sample that landed on each pixel through the filter, which is what gets displayed
+    film: Vec<WeightedColor>,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Accumulator {
+        Accumulator {
+            width,
+            height,
+            sums: vec![SampleSums::zero(); (width * height) as usize],
+            film: vec![WeightedColor::zero(); (width * height) as usize],
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn add_samples(&mut self, x: u32, y: u32, samples: &SampleSums) {
+        let index = self.index(x, y);
+        let sums = &mut self.sums[index];
+        sums.color += samples.color;
+        sums.luminance_squared += samples.luminance_squared;
+        sums.count += samples.count;
+    }
+
+    pub fn add_tile(&mut self, tile: &FilmTile) {
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                let index = self.index(x, y);
+                self.film[index].add(tile.get(x, y));
+            }
+        }
+    }
+
+    pub fn sample_count(&self, x: u32, y: u32) -> u32 {
+        self.sums[self.index(x, y)].count
+    }
+
+    // Standard error of the pixel's mean luminance relative to that luminance, infinite until
+    // there are enough samples to tell
+    pub fn relative_error(&self, x: u32, y: u32) -> f32 {
+        let sums = &self.sums[self.index(x, y)];
+
+        if sums.count < 2 {
+            return f32::INFINITY;
+        }
+
+        let count = sums.count as f32;
+        let mean = sums.color.luminance() / count;
+        let variance =
+            ((sums.luminance_squared / count - mean * mean) * count / (count - 1.0)).max(0.0);
+
+        (variance / count).sqrt() / mean.max(RELATIVE_ERROR_LUMINANCE_FLOOR)
+    }
+
+    // Weighted average of the samples so far plus ones that haven't been added yet
+    pub fn color_with(&self, x: u32, y: u32, pending: &WeightedColor) -> Color {
+        let index = self.index(x, y);
+        let mut film = self.film[index];
+        film.add(pending);
+
+        // Filters with negative lobes can leave a pixel with hardly any weight, its own samples
+        // are a better guess then
+        if film.weight > 1.0e-4 {
+            return film.color / film.weight;
+        }
+
+        let sums = &self.sums[index];
+        if sums.count == 0 {
+            return Color::zero();
+        }
+
+        sums.color / sums.count as f32
+    }
+
+    pub fn color(&self, x: u32, y: u32) -> Color {
+        self.color_with(x, y, &WeightedColor::zero())
+    }
+
+    pub fn pixel_data(&self) -> PixelsData {
+        (0..self.height)
+            .map(|y| {
+                (0..self.width)
+                    .map(|x| Pixel::new(x, y, self.color(x, y)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Samples taken by every pixel, row by row from the top left
+    pub fn sample_counts(&self) -> Vec<f32> {
+        self.sums.iter().map(|sums| sums.count as f32).collect()
+    }
+
+    // Samples taken per pixel as black through red and yellow to white, white being
+    // `max_samples` or the most samples any pixel has if that is zero
+    pub fn sample_heatmap(&self, max_samples: u32) -> PixelsData {
+        let max_samples = match max_samples {
+            0 => self.sums.iter().map(|sums| sums.count).max().unwrap_or(0),
+            max_samples => max_samples,
+        }
+        .max(1);
+
+        (0..self.height)
+            .map(|y| {
+                (0..self.width)
+                    .map(|x| {
+                        let heat = self.sample_count(x, y) as f32 / max_samples as f32 * 3.0;
+                        let color = Color::new(
+                            heat.clamp(0.0, 1.0),
+                            (heat - 1.0).clamp(0.0, 1.0),
+                            (heat - 2.0).clamp(0.0, 1.0),
+                        );
+                        Pixel::new(x, y, color)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
@@ -12,8 +12,22 @@ impl Aabb {
     pub fn new(minimum: Vec3A, maximum: Vec3A) -> Aabb {
         Aabb { minimum, maximum }
     }
+    pub fn surface_area(&self) -> f32 {
+        let size = (self.maximum - self.minimum).max(Vec3A::ZERO);
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+    pub fn centroid(&self) -> Vec3A {
+        (self.minimum + self.maximum) * 0.5
+    }
+    // Covers all of space, for shapes that go on forever
+    pub fn infinite() -> Aabb {
+        Aabb::new(Vec3A::splat(f32::NEG_INFINITY), Vec3A::splat(f32::INFINITY))
+    }
+    pub fn is_finite(&self) -> bool {
+        self.minimum.is_finite() && self.maximum.is_finite()
+    }
     pub fn does_ray_collide(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
-        let inverse_ray_direction = ray.direction.recip();
+        let inverse_ray_direction = ray.inverse_direction;
         let min_origin = self.minimum - ray.origin;
         let max_origin = self.maximum - ray.origin;
 
@@ -25,10 +39,10 @@ impl Aabb {
             std::mem::swap(&mut tx0, &mut tx1);
         }
 
-        let tx_min = if tx0 > t_min { tx0 } else { t_min };
-        let tx_max = if tx1 < t_max { tx1 } else { t_max };
+        let t_min = if tx0 > t_min { tx0 } else { t_min };
+        let t_max = if tx1 < t_max { tx1 } else { t_max };
 
-        if tx_max <= tx_min {
+        if t_max < t_min {
             return false;
         }
 
@@ -40,10 +54,10 @@ impl Aabb {
             std::mem::swap(&mut ty0, &mut ty1);
         }
 
-        let ty_min = if ty0 > t_min { ty0 } else { t_min };
-        let ty_max = if ty1 < t_max { ty1 } else { t_max };
+        let t_min = if ty0 > t_min { ty0 } else { t_min };
+        let t_max = if ty1 < t_max { ty1 } else { t_max };
 
-        if ty_max <= ty_min {
+        if t_max < t_min {
             return false;
         }
 
@@ -55,14 +69,10 @@ impl Aabb {
             std::mem::swap(&mut tz0, &mut tz1);
         }
 
-        let tz_min = if tz0 > t_min { tz0 } else { t_min };
-        let tz_max = if tz1 < t_max { tz1 } else { t_max };
+        let t_min = if tz0 > t_min { tz0 } else { t_min };
+        let t_max = if tz1 < t_max { tz1 } else { t_max };
 
-        if tz_max <= tz_min {
-            return false;
-        }
-
-        true
+        t_max >= t_min
     }
 }
 
@@ -81,29 +91,107 @@ pub fn build_surrounding_bounding_box(box_a: Aabb, box_b: Aabb) -> Aabb {
     Aabb::new(minimum, maximum)
 }
 
+#[allow(dead_code)]
 pub trait BoundingBox {
     fn get_bounding_box(&self, frame_start_time: f32, frame_end_time: f32) -> Aabb;
 }
 
 pub mod bvh {
-    use std::{cmp::Ordering, sync::Arc};
+    use std::{cmp::Ordering, fmt, sync::Arc};
 
     use glam::Vec3A;
-    use uuid::Uuid;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
 
     use crate::{
-        maths::random_usize_between,
         ray::{Ray, RayCollision}, collider::Collider,
     };
 
     use super::{build_surrounding_bounding_box, Aabb};
 
     type ArcCollidable = Arc<Collider>;
+    type AabbComparator = Box<dyn FnMut(&ArcCollidable, &ArcCollidable) -> Ordering>;
+
+    const SAH_BIN_COUNT: usize = 16;
+    const SAH_TRAVERSAL_COST: f32 = 1.0;
+    const SAH_INTERSECTION_COST: f32 = 1.0;
+    // Leaves are only kept when the heuristic says splitting costs more, up to this many primitives
+    const SAH_MAX_LEAF_PRIMITIVES: usize = 8;
+    // Traversal keeps a fixed size stack, trees deeper than this put what's left in one leaf
+    const MAX_TREE_DEPTH: usize = 64;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum BVHBuildStrategy {
+        // Splits on a random axis at the median child, the axes come from the render seed
+        RandomMedian,
+        // Binned surface area heuristic, picks the cheapest axis and split position
+        SurfaceAreaHeuristic,
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct BVHStats {
+        pub node_count: usize,
+        pub primitive_count: usize,
+        pub max_depth: usize,
+        pub cost: f32,
+    }
+
+    impl fmt::Display for BVHStats {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "Nodes: {} Primitives: {} Depth: {} Cost: {:.2}",
+                self.node_count, self.primitive_count, self.max_depth, self.cost
+            )
+        }
+    }
+
+    // Intermediate tree produced by the builders before it is flattened
+    enum BuildNode {
+        Interior {
+            aabb: Aabb,
+            left: Box<BuildNode>,
+            right: Box<BuildNode>,
+        },
+        Leaf {
+            aabb: Aabb,
+            primitives: Vec<ArcCollidable>,
+        },
+    }
 
+    impl BuildNode {
+        fn aabb(&self) -> Aabb {
+            match self {
+                BuildNode::Interior { aabb, .. } => *aabb,
+                BuildNode::Leaf { aabb, .. } => *aabb,
+            }
+        }
+    }
+
+    // 32 bytes so two nodes share a cache line. Leaves have a `primitive_count` and their
+    // primitives start at `offset`, interior nodes have their first child directly after them
+    // and their second child at `offset`
+    #[derive(Clone, Copy)]
+    struct FlatNode {
+        minimum: [f32; 3],
+        maximum: [f32; 3],
+        offset: u32,
+        primitive_count: u16,
+        axis: u8,
+    }
+
+    impl FlatNode {
+        fn aabb(&self) -> Aabb {
+            Aabb::new(Vec3A::from(self.minimum), Vec3A::from(self.maximum))
+        }
+    }
+
+    // A bounding volume hierarchy stored as a flat array of nodes in depth first order
     pub struct BVHNode {
-        uuid: Uuid,
-        left: ArcCollidable,
-        right: ArcCollidable,
+        nodes: Vec<FlatNode>,
+        primitives: Vec<ArcCollidable>,
+        // Shapes without finite bounds, like planes, would stretch every node above them across
+        // all of space, so they are kept out of the tree and tested against every ray instead
+        unbounded: Vec<ArcCollidable>,
         pub aabb: Aabb,
     }
 
@@ -115,18 +203,19 @@ pub mod bvh {
         shape.get_bounding_box(frame_start_time, frame_end_time).minimum
     }
 
-    fn sort_aabb_by_x(
+    fn sort_aabb_by_axis(
+        axis: usize,
         frame_start_time: f32,
         frame_end_time: f32,
-    ) -> Box<dyn FnMut(&ArcCollidable, &ArcCollidable) -> Ordering> {
+    ) -> AabbComparator {
         Box::new(move |a, b| {
-            let ax = bounding_box_minimum_for_shape(a, frame_start_time, frame_end_time).x;
-            let bx = bounding_box_minimum_for_shape(b, frame_start_time, frame_end_time).x;
-            if ax < bx {
+            let a_min = bounding_box_minimum_for_shape(a, frame_start_time, frame_end_time)[axis];
+            let b_min = bounding_box_minimum_for_shape(b, frame_start_time, frame_end_time)[axis];
+            if a_min < b_min {
                 return Ordering::Less;
             }
 
-            if ax == bx {
+            if a_min == b_min {
                 return Ordering::Equal;
             }
 
@@ -134,171 +223,508 @@ pub mod bvh {
         })
     }
 
-    fn sort_aabb_by_y(
+    fn make_leaf(
+        primitives: Vec<ArcCollidable>,
         frame_start_time: f32,
         frame_end_time: f32,
-    ) -> Box<dyn FnMut(&ArcCollidable, &ArcCollidable) -> Ordering> {
-        Box::new(move |a, b| {
-            let ay = bounding_box_minimum_for_shape(a, frame_start_time, frame_end_time).y;
-            let by = bounding_box_minimum_for_shape(b, frame_start_time, frame_end_time).y;
-            if ay < by {
-                return Ordering::Less;
-            }
-
-            if ay == by {
-                return Ordering::Equal;
-            }
-
-            Ordering::Greater
-        })
+    ) -> BuildNode {
+        let aabb = primitives
+            .iter()
+            .map(|primitive| primitive.get_bounding_box(frame_start_time, frame_end_time))
+            .reduce(build_surrounding_bounding_box)
+            .expect("No children given");
+
+        BuildNode::Leaf { aabb, primitives }
     }
 
-    fn sort_aabb_by_z(
-        frame_start_time: f32,
-        frame_end_time: f32,
-    ) -> Box<dyn FnMut(&ArcCollidable, &ArcCollidable) -> Ordering> {
-        Box::new(move |a, b| {
-            let az = bounding_box_minimum_for_shape(a, frame_start_time, frame_end_time).z;
-            let bz = bounding_box_minimum_for_shape(b, frame_start_time, frame_end_time).z;
-            if az < bz {
-                return Ordering::Less;
-            }
-
-            if az == bz {
-                return Ordering::Equal;
-            }
-
-            Ordering::Greater
-        })
+    fn make_interior(left: BuildNode, right: BuildNode) -> BuildNode {
+        BuildNode::Interior {
+            aabb: build_surrounding_bounding_box(left.aabb(), right.aabb()),
+            left: Box::new(left),
+            right: Box::new(right),
+        }
     }
 
     fn sub_divide_children_into_node(
-        children: Vec<ArcCollidable>,
+        mut children: Vec<ArcCollidable>,
         frame_start_time: f32,
         frame_end_time: f32,
-    ) -> BVHNode {
+        depth: usize,
+        rng: &mut StdRng,
+    ) -> BuildNode {
         if children.is_empty() {
             panic!("No children given",);
         }
 
-        let mut left: Option<&ArcCollidable> = children.get(0);
-        let mut right: Option<&ArcCollidable> = children.get(0);
-
-        let mut comparator = match random_usize_between(0, 2) {
-            0 => sort_aabb_by_x(frame_start_time, frame_end_time),
-            1 => sort_aabb_by_y(frame_start_time, frame_end_time),
-            2 => sort_aabb_by_z(frame_start_time, frame_end_time),
-            _ => panic!("Unexpected axis value"),
-        };
+        if children.len() <= 2 || depth >= MAX_TREE_DEPTH {
+            return make_leaf(children, frame_start_time, frame_end_time);
+        }
 
-        match children.len().cmp(&2) {
-            Ordering::Greater => {
-                let mut sorted_children = children.clone();
-                sorted_children.sort_by(comparator);
+        let comparator = sort_aabb_by_axis(
+            rng.gen_range(0..=2),
+            frame_start_time,
+            frame_end_time,
+        );
+
+        children.sort_by(comparator);
+
+        let mid = children.len() / 2;
+        let right_children = children.split_off(mid);
+
+        let left = sub_divide_children_into_node(
+            children,
+            frame_start_time,
+            frame_end_time,
+            depth + 1,
+            rng,
+        );
+        let right = sub_divide_children_into_node(
+            right_children,
+            frame_start_time,
+            frame_end_time,
+            depth + 1,
+            rng,
+        );
+
+        make_interior(left, right)
+    }
 
-                let mid = sorted_children.len() / 2;
-                let mut left_children = sorted_children;
-                let right_children = left_children.split_off(mid);
+    struct BuildPrimitive {
+        collider: ArcCollidable,
+        aabb: Aabb,
+        centroid: Vec3A,
+    }
 
-                let left_node =
-                    sub_divide_children_into_node(left_children, frame_start_time, frame_end_time);
-                let right_node =
-                    sub_divide_children_into_node(right_children, frame_start_time, frame_end_time);
+    fn bin_index(centroid: f32, minimum: f32, extent: f32) -> usize {
+        (((centroid - minimum) / extent * SAH_BIN_COUNT as f32) as usize).min(SAH_BIN_COUNT - 1)
+    }
 
-                let box_left = left_node.get_bounding_box(frame_start_time, frame_end_time);
-                let box_right = right_node.get_bounding_box(frame_start_time, frame_end_time);
+    // Returns the axis, the last bin on the left and the cost of the cheapest split
+    fn find_surface_area_heuristic_split(
+        primitives: &[BuildPrimitive],
+        aabb: &Aabb,
+        centroid_minimum: Vec3A,
+        centroid_extent: Vec3A,
+    ) -> Option<(usize, usize, f32)> {
+        let parent_area = aabb.surface_area();
+        let mut best: Option<(usize, usize, f32)> = None;
+
+        for axis in 0..3 {
+            if centroid_extent[axis] <= 0.0 {
+                continue;
+            }
 
-                let aabb = build_surrounding_bounding_box(box_left, box_right);
+            let mut bin_counts = [0_usize; SAH_BIN_COUNT];
+            let mut bin_bounds: [Option<Aabb>; SAH_BIN_COUNT] = [None; SAH_BIN_COUNT];
 
-                return BVHNode::new(
-                    Uuid::new_v4(),
-                    Arc::new(Collider::BVHNodeCollider(left_node)),
-                    Arc::new(Collider::BVHNodeCollider(right_node)),
-                    aabb,
+            for primitive in primitives {
+                let bin = bin_index(
+                    primitive.centroid[axis],
+                    centroid_minimum[axis],
+                    centroid_extent[axis],
                 );
+                bin_counts[bin] += 1;
+                bin_bounds[bin] = Some(match bin_bounds[bin] {
+                    Some(bounds) => build_surrounding_bounding_box(bounds, primitive.aabb),
+                    None => primitive.aabb,
+                });
             }
-            Ordering::Equal => match comparator(&children[0], &children[1]) {
-                Ordering::Greater => {
-                    left = children.get(1);
-                    right = children.get(0);
+
+            let mut right_areas = [0.0; SAH_BIN_COUNT];
+            let mut right_counts = [0_usize; SAH_BIN_COUNT];
+            let mut right_bounds: Option<Aabb> = None;
+            let mut right_count = 0;
+
+            for bin in (1..SAH_BIN_COUNT).rev() {
+                if let Some(bounds) = bin_bounds[bin] {
+                    right_bounds = Some(match right_bounds {
+                        Some(right) => build_surrounding_bounding_box(right, bounds),
+                        None => bounds,
+                    });
                 }
-                _ => {
-                    left = children.get(0);
-                    right = children.get(1);
+                right_count += bin_counts[bin];
+                right_areas[bin] = right_bounds.map_or(0.0, |bounds| bounds.surface_area());
+                right_counts[bin] = right_count;
+            }
+
+            let mut left_bounds: Option<Aabb> = None;
+            let mut left_count = 0;
+
+            for split in 0..(SAH_BIN_COUNT - 1) {
+                if let Some(bounds) = bin_bounds[split] {
+                    left_bounds = Some(match left_bounds {
+                        Some(left) => build_surrounding_bounding_box(left, bounds),
+                        None => bounds,
+                    });
+                }
+                left_count += bin_counts[split];
+
+                if left_count == 0 || right_counts[split + 1] == 0 {
+                    continue;
                 }
-            },
-            _ => {}
-        }
 
-        match left {
-            None => panic!("Left node is null"),
-            Some(actual_left) => match right {
-                None => panic!("Left node is null"),
-                Some(actual_right) => {
-                    let box_left = actual_left.get_bounding_box(frame_start_time, frame_end_time);
-                    let box_right = actual_right.get_bounding_box(frame_start_time, frame_end_time);
-
-                    let aabb = build_surrounding_bounding_box(box_left, box_right);
-
-                    BVHNode::new(
-                        Uuid::new_v4(),
-                        actual_left.clone(),
-                        actual_right.clone(),
-                        aabb,
-                    )
+                let left_area = left_bounds.map_or(0.0, |bounds| bounds.surface_area());
+                let cost = SAH_TRAVERSAL_COST
+                    + SAH_INTERSECTION_COST
+                        * (left_area * left_count as f32
+                            + right_areas[split + 1] * right_counts[split + 1] as f32)
+                        / parent_area;
+
+                if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, split, cost));
                 }
-            },
+            }
         }
+
+        best
     }
 
-    impl BVHNode {
-        pub fn new(uuid: Uuid, left: ArcCollidable, right: ArcCollidable, aabb: Aabb) -> BVHNode {
-            BVHNode {
-                uuid,
-                left,
-                right,
-                aabb,
+    fn build_surface_area_heuristic_node(
+        mut primitives: Vec<BuildPrimitive>,
+        depth: usize,
+    ) -> BuildNode {
+        if primitives.is_empty() {
+            panic!("No children given");
+        }
+
+        let aabb = primitives
+            .iter()
+            .skip(1)
+            .fold(primitives[0].aabb, |aabb, primitive| {
+                build_surrounding_bounding_box(aabb, primitive.aabb)
+            });
+
+        let into_leaf = |primitives: Vec<BuildPrimitive>| BuildNode::Leaf {
+            aabb,
+            primitives: primitives
+                .into_iter()
+                .map(|primitive| primitive.collider)
+                .collect(),
+        };
+
+        if primitives.len() <= 2 || depth >= MAX_TREE_DEPTH {
+            return into_leaf(primitives);
+        }
+
+        let mut centroid_minimum = primitives[0].centroid;
+        let mut centroid_maximum = primitives[0].centroid;
+        for primitive in primitives.iter() {
+            centroid_minimum = centroid_minimum.min(primitive.centroid);
+            centroid_maximum = centroid_maximum.max(primitive.centroid);
+        }
+        let centroid_extent = centroid_maximum - centroid_minimum;
+
+        let split = find_surface_area_heuristic_split(
+            &primitives,
+            &aabb,
+            centroid_minimum,
+            centroid_extent,
+        );
+
+        let right_primitives = match split {
+            // Testing every primitive in a leaf is cheaper than the best split
+            Some((_, _, cost))
+                if primitives.len() <= SAH_MAX_LEAF_PRIMITIVES
+                    && cost >= primitives.len() as f32 * SAH_INTERSECTION_COST =>
+            {
+                return into_leaf(primitives);
+            }
+            Some((axis, split_bin, _)) => {
+                let (left, right): (Vec<BuildPrimitive>, Vec<BuildPrimitive>) =
+                    primitives.into_iter().partition(|primitive| {
+                        bin_index(
+                            primitive.centroid[axis],
+                            centroid_minimum[axis],
+                            centroid_extent[axis],
+                        ) <= split_bin
+                    });
+                primitives = left;
+                right
+            }
+            // Every centroid is in the same place, so fall back to halving the list
+            None => {
+                let mid = primitives.len() / 2;
+                primitives.split_off(mid)
+            }
+        };
+
+        make_interior(
+            build_surface_area_heuristic_node(primitives, depth + 1),
+            build_surface_area_heuristic_node(right_primitives, depth + 1),
+        )
+    }
+
+    // Children are ordered so the first child is nearer the negative end of the split axis
+    fn flatten(node: BuildNode, nodes: &mut Vec<FlatNode>, primitives: &mut Vec<ArcCollidable>) {
+        let index = nodes.len();
+        let aabb = node.aabb();
+
+        nodes.push(FlatNode {
+            minimum: aabb.minimum.into(),
+            maximum: aabb.maximum.into(),
+            offset: 0,
+            primitive_count: 0,
+            axis: 0,
+        });
+
+        match node {
+            BuildNode::Leaf {
+                primitives: leaf_primitives,
+                ..
+            } => {
+                assert!(
+                    leaf_primitives.len() <= u16::MAX as usize,
+                    "Too many primitives in BVH leaf"
+                );
+                nodes[index].offset = primitives.len() as u32;
+                nodes[index].primitive_count = leaf_primitives.len() as u16;
+                primitives.extend(leaf_primitives);
+            }
+            BuildNode::Interior { left, right, .. } => {
+                let centroid_offset = right.aabb().centroid() - left.aabb().centroid();
+                let abs_offset = centroid_offset.abs();
+                let axis = if abs_offset.x >= abs_offset.y && abs_offset.x >= abs_offset.z {
+                    0
+                } else if abs_offset.y >= abs_offset.z {
+                    1
+                } else {
+                    2
+                };
+
+                let (first, second) = if centroid_offset[axis] < 0.0 {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+
+                nodes[index].axis = axis as u8;
+                flatten(*first, nodes, primitives);
+                nodes[index].offset = nodes.len() as u32;
+                flatten(*second, nodes, primitives);
             }
         }
+    }
 
+    fn collect_stats(
+        nodes: &[FlatNode],
+        index: usize,
+        depth: usize,
+        root_area: f32,
+        stats: &mut BVHStats,
+    ) {
+        let node = &nodes[index];
+        let area_ratio = if root_area > 0.0 {
+            node.aabb().surface_area() / root_area
+        } else {
+            1.0
+        };
+
+        stats.max_depth = stats.max_depth.max(depth);
+
+        if node.primitive_count > 0 {
+            stats.cost += SAH_INTERSECTION_COST * area_ratio * node.primitive_count as f32;
+        } else {
+            stats.cost += SAH_TRAVERSAL_COST * area_ratio;
+            collect_stats(nodes, index + 1, depth + 1, root_area, stats);
+            collect_stats(nodes, node.offset as usize, depth + 1, root_area, stats);
+        }
+    }
+
+    impl BVHNode {
         pub fn build_tree(
             children: Vec<ArcCollidable>,
             frame_start_time: f32,
             frame_end_time: f32,
+            strategy: BVHBuildStrategy,
+            seed: u64,
         ) -> BVHNode {
-            sub_divide_children_into_node(children, frame_start_time, frame_end_time)
+            let (children, unbounded): (Vec<_>, Vec<_>) =
+                children.into_iter().partition(|collider| {
+                    collider
+                        .get_bounding_box(frame_start_time, frame_end_time)
+                        .is_finite()
+                });
+
+            if children.is_empty() {
+                return BVHNode {
+                    nodes: Vec::new(),
+                    primitives: Vec::new(),
+                    unbounded,
+                    aabb: Aabb::new(Vec3A::ZERO, Vec3A::ZERO),
+                };
+            }
+
+            let root = match strategy {
+                BVHBuildStrategy::RandomMedian => sub_divide_children_into_node(
+                    children,
+                    frame_start_time,
+                    frame_end_time,
+                    1,
+                    &mut StdRng::seed_from_u64(seed),
+                ),
+                BVHBuildStrategy::SurfaceAreaHeuristic => {
+                    let primitives = children
+                        .into_iter()
+                        .map(|collider| {
+                            let aabb = collider.get_bounding_box(frame_start_time, frame_end_time);
+                            BuildPrimitive {
+                                collider,
+                                aabb,
+                                centroid: aabb.centroid(),
+                            }
+                        })
+                        .collect();
+                    build_surface_area_heuristic_node(primitives, 1)
+                }
+            };
+
+            let aabb = root.aabb();
+            let mut nodes = Vec::new();
+            let mut primitives = Vec::new();
+            flatten(root, &mut nodes, &mut primitives);
+
+            BVHNode {
+                nodes,
+                primitives,
+                unbounded,
+                aabb,
+            }
         }
+
+        // Everything in the tree, `aabb` leaves out the unbounded shapes
+        pub fn bounds(&self) -> Aabb {
+            if self.unbounded.is_empty() {
+                self.aabb
+            } else {
+                Aabb::infinite()
+            }
+        }
+
+        pub fn stats(&self) -> BVHStats {
+            let mut stats = BVHStats {
+                node_count: self.nodes.len(),
+                primitive_count: self.primitives.len() + self.unbounded.len(),
+                max_depth: 0,
+                cost: 0.0,
+            };
+            if !self.nodes.is_empty() {
+                collect_stats(&self.nodes, 0, 1, self.aabb.surface_area(), &mut stats);
+            }
+            stats
+        }
+
         pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
-            let collides = self.aabb.does_ray_collide(ray, t_min, t_max);
+            let direction_is_negative = [
+                ray.inverse_direction.x < 0.0,
+                ray.inverse_direction.y < 0.0,
+                ray.inverse_direction.z < 0.0,
+            ];
+
+            let mut closest_collision: Option<RayCollision> = None;
+            let mut closest_time = t_max;
+
+            // Tested first so anything they hit can cull the tree
+            for primitive in &self.unbounded {
+                if let Some(collision) = primitive.collide_ray(ray, t_min, closest_time) {
+                    closest_time = collision.time();
+                    closest_collision = Some(collision);
+                }
+            }
 
-            if collides {
-                let left_collision = self.left.collide_ray(ray, t_min, t_max);
+            if self.nodes.is_empty() {
+                return closest_collision;
+            }
 
-                let mut new_t_max = t_max;
-                let left_collision_copy = left_collision.as_ref();
-                match left_collision_copy {
-                    None => {}
-                    Some(left_collision) => {
-                        new_t_max = left_collision.time();
+            let mut stack = [0_u32; MAX_TREE_DEPTH];
+            let mut stack_size = 0;
+            let mut node_index = 0;
+
+            loop {
+                let node = &self.nodes[node_index];
+
+                if node.aabb().does_ray_collide(ray, t_min, closest_time) {
+                    if node.primitive_count > 0 {
+                        let start = node.offset as usize;
+                        let end = start + node.primitive_count as usize;
+
+                        for primitive in &self.primitives[start..end] {
+                            if let Some(collision) = primitive.collide_ray(ray, t_min, closest_time)
+                            {
+                                closest_time = collision.time();
+                                closest_collision = Some(collision);
+                            }
+                        }
+                    } else {
+                        // Visit the child nearest the ray origin first so the far child can be
+                        // culled by anything it hits
+                        let (near, far) = if direction_is_negative[node.axis as usize] {
+                            (node.offset, node_index as u32 + 1)
+                        } else {
+                            (node_index as u32 + 1, node.offset)
+                        };
+                        stack[stack_size] = far;
+                        stack_size += 1;
+                        node_index = near as usize;
+                        continue;
                     }
                 }
-                let right_collision = self.right.collide_ray(ray, t_min, new_t_max);
 
-                match right_collision {
-                    None => {
-                        return left_collision;
-                    }
-                    Some(right_collision) => {
-                        return Some(right_collision);
-                    }
+                if stack_size == 0 {
+                    break;
                 }
+                stack_size -= 1;
+                node_index = stack[stack_size] as usize;
             }
 
-            None
+            closest_collision
         }
 
-        pub fn get_bounding_box(&self, _frame_start_time: f32, _frame_end_time: f32) -> Aabb {
-            self.aabb
+        // Calls `visit` with every primitive in a leaf the ray passes through, in no particular
+        // order, for queries that need more than the closest hit
+        pub fn for_each_along_ray(
+            &self,
+            ray: &Ray,
+            t_min: f32,
+            t_max: f32,
+            mut visit: impl FnMut(&Collider),
+        ) {
+            for primitive in &self.unbounded {
+                visit(primitive);
+            }
+
+            if self.nodes.is_empty() {
+                return;
+            }
+
+            let mut stack = [0_u32; MAX_TREE_DEPTH];
+            let mut stack_size = 0;
+            let mut node_index = 0;
+
+            loop {
+                let node = &self.nodes[node_index];
+
+                if node.aabb().does_ray_collide(ray, t_min, t_max) {
+                    if node.primitive_count > 0 {
+                        let start = node.offset as usize;
+                        let end = start + node.primitive_count as usize;
+
+                        for primitive in &self.primitives[start..end] {
+                            visit(primitive);
+                        }
+                    } else {
+                        stack[stack_size] = node.offset;
+                        stack_size += 1;
+                        node_index += 1;
+                        continue;
+                    }
+                }
+
+                if stack_size == 0 {
+                    break;
+                }
+                stack_size -= 1;
+                node_index = stack[stack_size] as usize;
+            }
         }
     }
 }
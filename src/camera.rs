@@ -1,7 +1,7 @@
-use glam::Vec3A;
+use glam::{Vec2, Vec3A};
 
 use crate::{
-    maths::{random_f32_between, random_point_in_unit_disk},
+    maths::sample_unit_disk,
     ray::Ray,
 };
 
@@ -66,6 +66,7 @@ fn recalculate_camera(settings: CameraSettings) -> Camera {
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         width: u32,
         height: u32,
@@ -94,6 +95,14 @@ impl Camera {
     pub fn screen_height(&self) -> u32 {
         self.settings.screen_height
     }
+    // Same view rendered at a different output size
+    pub fn resized(&self, width: u32, height: u32) -> Camera {
+        let mut settings = self.settings;
+        settings.screen_width = width;
+        settings.screen_height = height;
+        settings.aspect_ratio = width as f32 / height as f32;
+        recalculate_camera(settings)
+    }
     // pub fn set_camera_position(&mut self, camera_position: Vec3A) {
     //     let mut settings = self.settings;
     //     settings.camera_position = camera_position;
@@ -104,8 +113,9 @@ impl Camera {
     //     settings.look_at_position = look_at;
     //     *self = recalculate_camera(settings);
     // }
-    pub fn make_ray(&self, u: f32, v: f32) -> Ray {
-        let random_disk = self.metadata.lens_radius * random_point_in_unit_disk();
+    // `lens` picks the point on the aperture and `time` when the shutter is open, both from 0 to 1
+    pub fn make_ray(&self, u: f32, v: f32, lens: Vec2, time: f32) -> Ray {
+        let random_disk = self.metadata.lens_radius * sample_unit_disk(lens);
         let offset =
             self.metadata.camera_u * random_disk.x + self.metadata.camera_v * random_disk.y;
 
@@ -116,7 +126,7 @@ impl Camera {
                 + self.metadata.vertical * v
                 - self.settings.camera_position
                 - offset,
-            random_f32_between(0.0, self.settings.shutter),
+            time * self.settings.shutter,
         )
     }
 }
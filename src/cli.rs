@@ -0,0 +1,465 @@
+use std::fmt;
+
+use crate::{
+    file::{ImageFormat, OutputSettings},
+    filter::{Filter, FilterKind, FILTER_NAMES},
+    sampler::{SamplerKind, SAMPLER_NAMES},
+    scene_file::SceneDescription,
+};
+
+pub const DEFAULT_SCENE_PATH: &str = "scenes/random_balls.scene";
+pub const DEFAULT_THREAD_COUNT: u32 = 12;
+
+pub const USAGE: &str = "Usage: rust-raytracer [OPTIONS] [SCENE]
+
+Renders SCENE, scenes/random_balls.scene by default. Options override the
+matching settings from the scene file.
+
+Options:
+  -r, --resolution <WIDTHxHEIGHT>  Size of the rendered image, e.g. 1920x1080
+  -s, --spp <LIST>                 Samples per pixel side for each pass, e.g. 1,2,4
+  -d, --max-depth <N>              Maximum number of bounces per ray, at least 1
+  -c, --chunk-size <N>             Width and height of the pixel blocks given to each thread
+  -t, --threads <N>                Number of render threads [default: 12]
+      --seed <N>                   Seed for the random numbers used while rendering
+      --sampler <NAME>             independent, stratified, halton or sobol
+      --filter <NAME>              Pixel filter, box, tent, gaussian, mitchell or lanczos
+      --filter-radius <PIXELS>     Radius of the pixel filter, from 0.5 to 16
+      --adaptive <THRESHOLD>       Sample each pixel until its relative error is below THRESHOLD
+      --min-samples <N>            Samples every pixel takes before adaptive sampling can stop it
+      --max-samples <N>            Most samples adaptive sampling takes for a pixel
+  -o, --output <PATH>              Output image, a .png, .exr or .pfm file
+      --heatmap <PATH>             Also save an image of how many samples each pixel took
+      --scene <PATH>               Scene file to render, instead of passing SCENE
+      --headless                   Render without opening a window, progress is printed instead
+  -h, --help                       Print this help";
+
+#[derive(Debug)]
+pub enum CliError {
+    MissingValue {
+        flag: String,
+    },
+    InvalidValue {
+        flag: String,
+        value: String,
+        expected: &'static str,
+    },
+    UnknownFlag(String),
+    UnexpectedArgument(String),
+    InvalidSettings(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::MissingValue { flag } => write!(f, "{} needs a value", flag),
+            CliError::InvalidValue {
+                flag,
+                value,
+                expected,
+            } => write!(
+                f,
+                "invalid value '{}' for {}, expected {}",
+                value, flag, expected
+            ),
+            CliError::UnknownFlag(flag) => write!(f, "unknown option '{}'", flag),
+            CliError::InvalidSettings(message) => write!(f, "{}", message),
+            CliError::UnexpectedArgument(argument) => {
+                write!(
+                    f,
+                    "unexpected argument '{}', only one scene can be rendered",
+                    argument
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+#[derive(Clone, Debug)]
+pub struct CliOptions {
+    pub scene_path: String,
+    pub resolution: Option<(u32, u32)>,
+    pub samples_per_pixel_side_values: Option<Vec<u32>>,
+    pub max_ray_depth: Option<u32>,
+    pub chunk_size: Option<u32>,
+    pub threads: u32,
+    pub seed: Option<u64>,
+    pub sampler: Option<SamplerKind>,
+    pub filter: Option<FilterKind>,
+    pub filter_radius: Option<f32>,
+    pub adaptive_threshold: Option<f32>,
+    pub adaptive_min_samples: Option<u32>,
+    pub adaptive_max_samples: Option<u32>,
+    pub output_path: Option<(String, ImageFormat)>,
+    pub heatmap_path: Option<(String, ImageFormat)>,
+    pub headless: bool,
+}
+
+pub enum CliCommand {
+    Help,
+    Render(CliOptions),
+}
+
+fn invalid(flag: &str, value: &str, expected: &'static str) -> CliError {
+    CliError::InvalidValue {
+        flag: String::from(flag),
+        value: String::from(value),
+        expected,
+    }
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<u32, CliError> {
+    match value.parse::<u32>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(invalid(flag, value, "a whole number above zero")),
+    }
+}
+
+fn parse_resolution(flag: &str, value: &str) -> Result<(u32, u32), CliError> {
+    let expected = "WIDTHxHEIGHT with both above zero, e.g. 1920x1080";
+
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| invalid(flag, value, expected))?;
+
+    match (width.parse::<u32>(), height.parse::<u32>()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid(flag, value, expected)),
+    }
+}
+
+fn parse_samples(flag: &str, value: &str) -> Result<Vec<u32>, CliError> {
+    value
+        .split(',')
+        .map(|samples| match samples.parse::<u32>() {
+            Ok(samples) if samples > 0 => Ok(samples),
+            _ => Err(invalid(
+                flag,
+                value,
+                "a comma separated list of whole numbers above zero, e.g. 1,2,4",
+            )),
+        })
+        .collect()
+}
+
+fn parse_output(flag: &str, value: &str) -> Result<(String, ImageFormat), CliError> {
+    match ImageFormat::from_path(value) {
+        Some(format) => Ok((String::from(value), format)),
+        None => Err(invalid(flag, value, "a path ending in .png, .exr or .pfm")),
+    }
+}
+
+// Accepts `--flag value`, `--flag=value` and `-f value`
+pub fn parse_arguments(
+    arguments: impl IntoIterator<Item = String>,
+) -> Result<CliCommand, CliError> {
+    let mut arguments = arguments.into_iter();
+
+    let mut options = CliOptions {
+        scene_path: String::from(DEFAULT_SCENE_PATH),
+        resolution: None,
+        samples_per_pixel_side_values: None,
+        max_ray_depth: None,
+        chunk_size: None,
+        threads: DEFAULT_THREAD_COUNT,
+        seed: None,
+        sampler: None,
+        filter: None,
+        filter_radius: None,
+        adaptive_threshold: None,
+        adaptive_min_samples: None,
+        adaptive_max_samples: None,
+        output_path: None,
+        heatmap_path: None,
+        headless: false,
+    };
+    let mut scene_path: Option<String> = None;
+
+    while let Some(argument) = arguments.next() {
+        if !argument.starts_with('-') {
+            if scene_path.is_some() {
+                return Err(CliError::UnexpectedArgument(argument));
+            }
+            scene_path = Some(argument);
+            continue;
+        }
+
+        let (flag, inline_value) = match argument.split_once('=') {
+            Some((flag, value)) => (String::from(flag), Some(String::from(value))),
+            None => (argument, None),
+        };
+
+        if flag == "-h" || flag == "--help" {
+            return Ok(CliCommand::Help);
+        }
+        if flag == "--headless" {
+            if let Some(value) = inline_value {
+                return Err(invalid(&flag, &value, "no value"));
+            }
+            options.headless = true;
+            continue;
+        }
+
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| arguments.next())
+                .ok_or_else(|| CliError::MissingValue { flag: flag.clone() })
+        };
+
+        match flag.as_str() {
+            "-r" | "--resolution" => options.resolution = Some(parse_resolution(&flag, &value()?)?),
+            "-s" | "--spp" => {
+                options.samples_per_pixel_side_values = Some(parse_samples(&flag, &value()?)?)
+            }
+            "-d" | "--max-depth" => options.max_ray_depth = Some(parse_positive(&flag, &value()?)?),
+            "-c" | "--chunk-size" => options.chunk_size = Some(parse_positive(&flag, &value()?)?),
+            "-t" | "--threads" => options.threads = parse_positive(&flag, &value()?)?,
+            "--seed" => {
+                let value = value()?;
+                let seed = value
+                    .parse::<u64>()
+                    .map_err(|_| invalid(&flag, &value, "a whole number"))?;
+                options.seed = Some(seed);
+            }
+            "--sampler" => {
+                let value = value()?;
+                let sampler = SamplerKind::from_name(&value)
+                    .ok_or_else(|| invalid(&flag, &value, SAMPLER_NAMES))?;
+                options.sampler = Some(sampler);
+            }
+            "--filter" => {
+                let value = value()?;
+                let filter = FilterKind::from_name(&value)
+                    .ok_or_else(|| invalid(&flag, &value, FILTER_NAMES))?;
+                options.filter = Some(filter);
+            }
+            "--filter-radius" => {
+                let value = value()?;
+                let radius = value
+                    .parse::<f32>()
+                    .map_err(|_| invalid(&flag, &value, "a number of pixels"))?;
+                options.filter_radius = Some(radius);
+            }
+            "--adaptive" => {
+                let value = value()?;
+                let threshold = match value.parse::<f32>() {
+                    Ok(threshold) if threshold > 0.0 => threshold,
+                    _ => return Err(invalid(&flag, &value, "a number above zero, e.g. 0.02")),
+                };
+                options.adaptive_threshold = Some(threshold);
+            }
+            "--min-samples" => {
+                options.adaptive_min_samples = Some(parse_positive(&flag, &value()?)?)
+            }
+            "--max-samples" => {
+                options.adaptive_max_samples = Some(parse_positive(&flag, &value()?)?)
+            }
+            "-o" | "--output" => options.output_path = Some(parse_output(&flag, &value()?)?),
+            "--heatmap" => options.heatmap_path = Some(parse_output(&flag, &value()?)?),
+            "--scene" => {
+                let path = value()?;
+                if scene_path.is_some() {
+                    return Err(CliError::UnexpectedArgument(path));
+                }
+                scene_path = Some(path);
+            }
+            _ => return Err(CliError::UnknownFlag(flag)),
+        }
+    }
+
+    if let Some(scene_path) = scene_path {
+        options.scene_path = scene_path;
+    }
+
+    Ok(CliCommand::Render(options))
+}
+
+impl CliOptions {
+    // Flags win over whatever the scene file asked for
+    pub fn apply(&self, scene_description: &mut SceneDescription) -> Result<(), CliError> {
+        let settings = &mut scene_description.settings;
+
+        if let Some((width, height)) = self.resolution {
+            settings.image_width = width;
+            settings.image_height = height;
+            scene_description.camera = scene_description.camera.resized(width, height);
+        }
+        if let Some(samples_per_pixel_side_values) = &self.samples_per_pixel_side_values {
+            settings.samples_per_pixel_side_values = samples_per_pixel_side_values.clone();
+        }
+        if let Some(max_ray_depth) = self.max_ray_depth {
+            settings.render.max_ray_depth = max_ray_depth;
+        }
+        if let Some(chunk_size) = self.chunk_size {
+            settings.render.chunk_size = chunk_size;
+        }
+        if let Some(seed) = self.seed {
+            settings.render.seed = seed;
+        }
+        if let Some(sampler) = self.sampler {
+            settings.render.sampler = sampler;
+        }
+        // A new filter type starts from its own default radius unless one is given
+        if self.filter.is_some() || self.filter_radius.is_some() {
+            let mut filter = match self.filter {
+                Some(kind) => Filter::new(kind),
+                None => settings.render.filter,
+            };
+            filter.radius = self.filter_radius.unwrap_or(filter.radius);
+            filter.validate().map_err(CliError::InvalidSettings)?;
+            settings.render.filter = filter;
+        }
+        if self.adaptive_threshold.is_some()
+            || self.adaptive_min_samples.is_some()
+            || self.adaptive_max_samples.is_some()
+        {
+            let mut adaptive = settings.render.adaptive.unwrap_or_default();
+            adaptive.threshold = self.adaptive_threshold.unwrap_or(adaptive.threshold);
+            adaptive.min_samples = self.adaptive_min_samples.unwrap_or(adaptive.min_samples);
+            adaptive.max_samples = self.adaptive_max_samples.unwrap_or(adaptive.max_samples);
+            adaptive.validate().map_err(CliError::InvalidSettings)?;
+            settings.render.adaptive = Some(adaptive);
+        }
+        if let Some((path, format)) = &self.output_path {
+            settings.output.path = path.clone();
+            // Keep a half precision choice from the scene file when still writing OpenEXR
+            if !matches!(
+                (settings.output.format, format),
+                (ImageFormat::OpenExr(_), ImageFormat::OpenExr(_))
+            ) {
+                settings.output.format = *format;
+            }
+        }
+        if let Some((path, format)) = &self.heatmap_path {
+            settings.heatmap = Some(OutputSettings::heatmap(path.clone(), *format));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{parse_arguments, CliCommand, CliError, CliOptions};
+    use crate::scene_file::parse_scene;
+
+    const SCENE: &str = "camera position=0,1,5 look_at=0,0,0 fov=40
+material ground lambertian albedo=0.5,0.5,0.5
+sphere centre=0,0,0 radius=1 material=ground";
+
+    fn parse(arguments: &[&str]) -> Result<CliCommand, CliError> {
+        parse_arguments(arguments.iter().map(|argument| String::from(*argument)))
+    }
+
+    fn parse_options(arguments: &[&str]) -> CliOptions {
+        match parse(arguments) {
+            Ok(CliCommand::Render(options)) => options,
+            Ok(CliCommand::Help) => panic!("{:?} asked for help", arguments),
+            Err(error) => panic!("{:?} failed: {}", arguments, error),
+        }
+    }
+
+    #[test]
+    fn resolution() {
+        assert_eq!(
+            parse_options(&["-r", "640x480"]).resolution,
+            Some((640, 480))
+        );
+        assert_eq!(
+            parse_options(&["--resolution=1920x1080"]).resolution,
+            Some((1920, 1080))
+        );
+        assert!(matches!(
+            parse(&["-r", "640"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            parse(&["-r", "0x480"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn samples() {
+        assert_eq!(
+            parse_options(&["-s", "1,2,4"]).samples_per_pixel_side_values,
+            Some(vec![1, 2, 4])
+        );
+        assert!(matches!(
+            parse(&["-s", "1,0"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn scene_path_and_headless() {
+        let options = parse_options(&["scenes/cube.scene", "--headless"]);
+        assert_eq!(options.scene_path, "scenes/cube.scene");
+        assert!(options.headless);
+
+        assert!(matches!(
+            parse(&["a.scene", "b.scene"]),
+            Err(CliError::UnexpectedArgument(argument)) if argument == "b.scene"
+        ));
+    }
+
+    #[test]
+    fn missing_value() {
+        assert!(matches!(
+            parse(&["--headless", "-r"]),
+            Err(CliError::MissingValue { flag }) if flag == "-r"
+        ));
+    }
+
+    #[test]
+    fn unknown_flag() {
+        assert!(matches!(
+            parse(&["--resolutoin", "640x480"]),
+            Err(CliError::UnknownFlag(flag)) if flag == "--resolutoin"
+        ));
+    }
+
+    #[test]
+    fn help() {
+        assert!(matches!(parse(&["--help"]), Ok(CliCommand::Help)));
+        assert!(matches!(
+            parse(&["-r", "640x480", "-h"]),
+            Ok(CliCommand::Help)
+        ));
+    }
+
+    #[test]
+    fn max_depth_must_be_above_zero() {
+        assert_eq!(parse_options(&["--max-depth", "8"]).max_ray_depth, Some(8));
+        assert!(matches!(
+            parse(&["--max-depth", "0"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn filter_radius_is_validated() {
+        let mut scene_description = parse_scene(SCENE, Path::new("")).unwrap();
+
+        let options = parse_options(&["--filter", "tent", "--filter-radius", "2"]);
+        options.apply(&mut scene_description).unwrap();
+        assert_eq!(scene_description.settings.render.filter.radius, 2.0);
+
+        for radius in ["inf", "NaN", "1e10", "0.25"] {
+            let options = parse_options(&["--filter", "tent", "--filter-radius", radius]);
+            assert!(
+                matches!(
+                    options.apply(&mut scene_description),
+                    Err(CliError::InvalidSettings(_))
+                ),
+                "radius {} was accepted",
+                radius
+            );
+        }
+    }
+}
@@ -1,13 +1,27 @@
-use crate::{shape::Sphere, bounding_box::{bvh::BVHNode, Aabb}, ray::{Ray, RayCollision}};
+use glam::{Vec2, Vec3A};
 
+use crate::{csg::Csg, instance::Instance, shape::{Cone, Cuboid, Cylinder, Disk, Plane, Quad, Quadric, ShapeSample, Sphere, Torus, Triangle}, material::materials::Material, bounding_box::Aabb, ray::{Ray, RayCollision}};
+
+#[allow(dead_code)]
 pub trait Collidable {
     fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision>;
     fn get_bounding_box(&self, frame_start_time: f32, frame_end_time: f32) -> Aabb;
 }
 
+#[allow(clippy::enum_variant_names)]
 pub enum Collider {
     SphereCollider(Sphere),
-    BVHNodeCollider(BVHNode),
+    TriangleCollider(Triangle),
+    PlaneCollider(Plane),
+    QuadCollider(Quad),
+    DiskCollider(Disk),
+    CuboidCollider(Cuboid),
+    CylinderCollider(Cylinder),
+    ConeCollider(Cone),
+    TorusCollider(Torus),
+    QuadricCollider(Quadric),
+    InstanceCollider(Instance),
+    CsgCollider(Csg),
 }
 
 
@@ -15,13 +29,101 @@ impl Collider {
     pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
         match self {
             Collider::SphereCollider(sphere) => sphere.collide_ray(ray, t_min, t_max),
-            Collider::BVHNodeCollider(bvh_node) => bvh_node.collide_ray(ray, t_min, t_max),
+            Collider::TriangleCollider(triangle) => triangle.collide_ray(ray, t_min, t_max),
+            Collider::PlaneCollider(plane) => plane.collide_ray(ray, t_min, t_max),
+            Collider::QuadCollider(quad) => quad.collide_ray(ray, t_min, t_max),
+            Collider::DiskCollider(disk) => disk.collide_ray(ray, t_min, t_max),
+            Collider::CuboidCollider(cuboid) => cuboid.collide_ray(ray, t_min, t_max),
+            Collider::CylinderCollider(cylinder) => cylinder.collide_ray(ray, t_min, t_max),
+            Collider::ConeCollider(cone) => cone.collide_ray(ray, t_min, t_max),
+            Collider::TorusCollider(torus) => torus.collide_ray(ray, t_min, t_max),
+            Collider::QuadricCollider(quadric) => quadric.collide_ray(ray, t_min, t_max),
+            Collider::InstanceCollider(instance) => instance.collide_ray(ray, t_min, t_max),
+            Collider::CsgCollider(csg) => csg.collide_ray(ray, t_min, t_max),
         }
     }
     pub fn get_bounding_box(&self, frame_start_time: f32, frame_end_time: f32) -> Aabb {
         match self {
             Collider::SphereCollider(sphere) => sphere.get_bounding_box(frame_start_time, frame_end_time),
-            Collider::BVHNodeCollider(bvh_node) => bvh_node.get_bounding_box(frame_start_time, frame_end_time),
+            Collider::TriangleCollider(triangle) => triangle.get_bounding_box(frame_start_time, frame_end_time),
+            Collider::PlaneCollider(plane) => plane.get_bounding_box(frame_start_time, frame_end_time),
+            Collider::QuadCollider(quad) => quad.get_bounding_box(frame_start_time, frame_end_time),
+            Collider::DiskCollider(disk) => disk.get_bounding_box(frame_start_time, frame_end_time),
+            Collider::CuboidCollider(cuboid) => cuboid.get_bounding_box(frame_start_time, frame_end_time),
+            Collider::CylinderCollider(cylinder) => cylinder.get_bounding_box(frame_start_time, frame_end_time),
+            Collider::ConeCollider(cone) => cone.get_bounding_box(frame_start_time, frame_end_time),
+            Collider::TorusCollider(torus) => torus.get_bounding_box(frame_start_time, frame_end_time),
+            Collider::QuadricCollider(quadric) => quadric.get_bounding_box(frame_start_time, frame_end_time),
+            Collider::InstanceCollider(instance) => instance.get_bounding_box(frame_start_time, frame_end_time),
+            Collider::CsgCollider(csg) => csg.get_bounding_box(frame_start_time, frame_end_time),
+        }
+    }
+    pub fn material(&self) -> Option<&Material> {
+        match self {
+            Collider::SphereCollider(sphere) => Some(sphere.material.as_ref()),
+            Collider::TriangleCollider(triangle) => Some(triangle.material()),
+            Collider::PlaneCollider(plane) => Some(plane.material.as_ref()),
+            Collider::QuadCollider(quad) => Some(quad.material.as_ref()),
+            Collider::DiskCollider(disk) => Some(disk.material.as_ref()),
+            Collider::CuboidCollider(cuboid) => Some(cuboid.material()),
+            Collider::CylinderCollider(cylinder) => Some(cylinder.material.as_ref()),
+            Collider::ConeCollider(cone) => Some(cone.material.as_ref()),
+            Collider::TorusCollider(torus) => Some(torus.material.as_ref()),
+            Collider::QuadricCollider(quadric) => Some(quadric.material.as_ref()),
+            // Instances and combined shapes can hold any number of materials, so they're never
+            // sampled as lights
+            Collider::InstanceCollider(_) | Collider::CsgCollider(_) => None,
+        }
+    }
+    // Whether `sample_towards` can pick points on the shape, only those can be sampled as lights
+    pub fn can_sample(&self) -> bool {
+        match self {
+            Collider::SphereCollider(_)
+            | Collider::TriangleCollider(_)
+            | Collider::QuadCollider(_)
+            | Collider::DiskCollider(_)
+            | Collider::CuboidCollider(_) => true,
+            Collider::PlaneCollider(_)
+            | Collider::CylinderCollider(_)
+            | Collider::ConeCollider(_)
+            | Collider::TorusCollider(_)
+            | Collider::QuadricCollider(_)
+            | Collider::InstanceCollider(_)
+            | Collider::CsgCollider(_) => false,
+        }
+    }
+    pub fn sample_towards(&self, origin: Vec3A, time: f32, u: Vec2) -> Option<ShapeSample> {
+        match self {
+            Collider::SphereCollider(sphere) => sphere.sample_towards(origin, time, u),
+            Collider::TriangleCollider(triangle) => triangle.sample_towards(origin, time, u),
+            // Infinite planes can't be sampled, light from them is only found by scattering
+            Collider::PlaneCollider(_) => None,
+            Collider::QuadCollider(quad) => quad.sample_towards(origin, time, u),
+            Collider::DiskCollider(disk) => disk.sample_towards(origin, time, u),
+            Collider::CuboidCollider(cuboid) => cuboid.sample_towards(origin, time, u),
+            // Curved shapes aren't sampled either, so emissive ones only light what finds them
+            Collider::CylinderCollider(_)
+            | Collider::ConeCollider(_)
+            | Collider::TorusCollider(_)
+            | Collider::QuadricCollider(_)
+            | Collider::InstanceCollider(_)
+            | Collider::CsgCollider(_) => None,
+        }
+    }
+    pub fn pdf_towards(&self, origin: Vec3A, direction: Vec3A, time: f32) -> f32 {
+        match self {
+            Collider::SphereCollider(sphere) => sphere.pdf_towards(origin, direction, time),
+            Collider::TriangleCollider(triangle) => triangle.pdf_towards(origin, direction, time),
+            Collider::PlaneCollider(_) => 0.0,
+            Collider::QuadCollider(quad) => quad.pdf_towards(origin, direction, time),
+            Collider::DiskCollider(disk) => disk.pdf_towards(origin, direction, time),
+            Collider::CuboidCollider(cuboid) => cuboid.pdf_towards(origin, direction, time),
+            Collider::CylinderCollider(_)
+            | Collider::ConeCollider(_)
+            | Collider::TorusCollider(_)
+            | Collider::QuadricCollider(_)
+            | Collider::InstanceCollider(_)
+            | Collider::CsgCollider(_) => 0.0,
         }
     }
 }
@@ -1,6 +1,5 @@
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Range, Sub, SubAssign};
 
-use image::Rgba;
 use rand::Rng;
 
 #[derive(Clone, Copy, Debug)]
@@ -20,14 +19,6 @@ impl Color {
     pub fn one() -> Color {
         Color::new(1.0, 1.0, 1.0)
     }
-    pub fn rgba(self) -> Rgba<u8> {
-        image::Rgba([
-            (255.0 * self.r) as u8,
-            (255.0 * self.g) as u8,
-            (255.0 * self.b) as u8,
-            255_u8,
-        ])
-    }
     pub fn r(&self) -> &f32 {
         &self.r
     }
@@ -37,9 +28,14 @@ impl Color {
     pub fn b(&self) -> &f32 {
         &self.b
     }
-    pub fn random(range: Range<f32>) -> Color {
-        let mut rng = rand::thread_rng();
-
+    pub fn max_component(&self) -> f32 {
+        self.r.max(self.g).max(self.b)
+    }
+    // Rec. 709 weights, how bright the colour looks rather than how strong each channel is
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+    pub fn random(range: Range<f32>, rng: &mut impl Rng) -> Color {
         Color {
             r: rng.gen_range(range.clone()),
             b: rng.gen_range(range.clone()),
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use crate::{
+    bounding_box::{build_surrounding_bounding_box, Aabb},
+    collider::Collider,
+    ray::{Ray, RayCollision},
+};
+
+// How far past one surface the next is looked for, so the same surface isn't found again. The
+// relative part keeps the step above the rounding of `t` for hits far along the ray.
+const CSG_SURFACE_STEP: f32 = 1.0e-4;
+const CSG_RELATIVE_STEP: f32 = 1.0e-5;
+// Surfaces followed along one ray before giving up on finding the combined solid's
+const CSG_MAX_SURFACES: usize = 256;
+
+pub const CSG_OPERATION_NAMES: &str = "'union', 'intersection' or 'difference'";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsgOperation {
+    // Inside either shape
+    Union,
+    // Inside both shapes
+    Intersection,
+    // Inside the first shape but not the second
+    Difference,
+}
+
+impl CsgOperation {
+    pub fn from_name(name: &str) -> Option<CsgOperation> {
+        match name {
+            "union" => Some(CsgOperation::Union),
+            "intersection" => Some(CsgOperation::Intersection),
+            "difference" => Some(CsgOperation::Difference),
+            _ => None,
+        }
+    }
+    fn contains(&self, inside_left: bool, inside_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => inside_left || inside_right,
+            CsgOperation::Intersection => inside_left && inside_right,
+            CsgOperation::Difference => inside_left && !inside_right,
+        }
+    }
+}
+
+// Two closed shapes combined into one solid. Both are followed along the ray surface by surface,
+// keeping track of whether the ray is inside each of them, and the first surface where the ray
+// passes into or out of the combined solid is the hit.
+pub struct Csg {
+    pub operation: CsgOperation,
+    left: Arc<Collider>,
+    right: Arc<Collider>,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, left: Arc<Collider>, right: Arc<Collider>) -> Csg {
+        Csg {
+            operation,
+            left,
+            right,
+        }
+    }
+    pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
+        let step = CSG_SURFACE_STEP / ray.direction.length();
+
+        let mut left = self.left.collide_ray(ray, t_min, f32::INFINITY);
+        let mut right = self.right.collide_ray(ray, t_min, f32::INFINITY);
+        // Leaving a shape through the next surface means the ray starts inside it
+        let mut inside_left = left.as_ref().is_some_and(|hit| !hit.on_front_face());
+        let mut inside_right = right.as_ref().is_some_and(|hit| !hit.on_front_face());
+
+        for _ in 0..CSG_MAX_SURFACES {
+            let is_left = match (&left, &right) {
+                (Some(left), Some(right)) => left.time() <= right.time(),
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return None,
+            };
+            let surface = if is_left { &mut left } else { &mut right };
+            let mut hit = surface.take()?;
+
+            if hit.time() >= t_max {
+                return None;
+            }
+
+            let was_inside = self.operation.contains(inside_left, inside_right);
+            if is_left {
+                inside_left = hit.on_front_face();
+            } else {
+                inside_right = hit.on_front_face();
+            }
+            let is_inside = self.operation.contains(inside_left, inside_right);
+
+            if was_inside != is_inside {
+                hit.set_on_front_face(is_inside);
+                return Some(hit);
+            }
+
+            let shape = if is_left { &self.left } else { &self.right };
+            let t = hit.time() * (1.0 + CSG_RELATIVE_STEP) + step;
+            *surface = shape.collide_ray(ray, t, f32::INFINITY);
+        }
+        None
+    }
+    pub fn get_bounding_box(&self, frame_start_time: f32, frame_end_time: f32) -> Aabb {
+        let left = self.left.get_bounding_box(frame_start_time, frame_end_time);
+        let right = self
+            .right
+            .get_bounding_box(frame_start_time, frame_end_time);
+
+        match self.operation {
+            CsgOperation::Union => build_surrounding_bounding_box(left, right),
+            // Can be empty, which no ray gets through
+            CsgOperation::Intersection => Aabb::new(
+                left.minimum.max(right.minimum),
+                left.maximum.min(right.maximum),
+            ),
+            CsgOperation::Difference => left,
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use image::Rgba;
+
+use crate::color::Color;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapping {
+    // Leaves radiance alone, anything above 1 is clipped
+    Clamp,
+    Reinhard,
+    // Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+impl ToneMapping {
+    fn map(self, value: f32) -> f32 {
+        match self {
+            ToneMapping::Clamp => value,
+            ToneMapping::Reinhard => value / (1.0 + value),
+            ToneMapping::Aces => {
+                (value * (2.51 * value + 0.03)) / (value * (2.43 * value + 0.59) + 0.14)
+            }
+        }
+    }
+}
+
+// Turns linear radiance into 8 bit display values, shared by the PNG writer and the window
+#[derive(Clone, Copy, Debug)]
+pub struct DisplayTransform {
+    pub tone_mapping: ToneMapping,
+    // In stops, each one doubles the brightness
+    pub exposure: f32,
+    pub srgb: bool,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> DisplayTransform {
+        DisplayTransform {
+            tone_mapping: ToneMapping::Clamp,
+            exposure: 0.0,
+            srgb: true,
+        }
+    }
+}
+
+pub fn srgb_oetf(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Inverse of `srgb_oetf`, turns sRGB encoded values back into linear ones
+pub fn srgb_eotf(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl DisplayTransform {
+    fn encode(&self, value: f32, scale: f32) -> u8 {
+        // NaN and negative radiance both end up black
+        let value = (value * scale).max(0.0);
+        let mut value = self.tone_mapping.map(value).clamp(0.0, 1.0);
+
+        if self.srgb {
+            value = srgb_oetf(value);
+        }
+
+        (value * 255.0).round() as u8
+    }
+
+    pub fn apply(&self, color: Color) -> [u8; 3] {
+        let scale = self.exposure.exp2();
+
+        [
+            self.encode(*color.r(), scale),
+            self.encode(*color.g(), scale),
+            self.encode(*color.b(), scale),
+        ]
+    }
+
+    pub fn rgba(&self, color: Color) -> Rgba<u8> {
+        let [r, g, b] = self.apply(color);
+        Rgba([r, g, b, 255])
+    }
+}
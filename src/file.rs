@@ -1,12 +1,81 @@
+use std::path::Path;
+
 use image::Rgba;
 
-use crate::ui::pixel::Pixel;
+use crate::{
+    display::{DisplayTransform, ToneMapping},
+    ui::pixel::Pixel,
+};
+
+use self::exr::{ExrImage, ExrPixelType};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    OpenExr(ExrPixelType),
+    Pfm,
+}
+
+impl ImageFormat {
+    // Picks the format from the file extension, OpenEXR defaults to full float channels
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "exr" => Some(ImageFormat::OpenExr(ExrPixelType::Float)),
+            "pfm" => Some(ImageFormat::Pfm),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OutputSettings {
+    pub path: String,
+    pub format: ImageFormat,
+    // Only applies to PNG output, the float formats are always written as linear radiance
+    pub display: DisplayTransform,
+}
+
+impl OutputSettings {
+    // Heatmaps are already display colours, so they are written without any tone mapping
+    pub fn heatmap(path: String, format: ImageFormat) -> OutputSettings {
+        OutputSettings {
+            path,
+            format,
+            display: DisplayTransform {
+                tone_mapping: ToneMapping::Clamp,
+                exposure: 0.0,
+                srgb: false,
+            },
+        }
+    }
+}
+
+fn create_parent_directory(path: &str) -> std::io::Result<()> {
+    match Path::new(path).parent() {
+        Some(prefix) => std::fs::create_dir_all(prefix),
+        None => Ok(()),
+    }
+}
+
+fn image_size(pixels: &[Vec<Pixel>]) -> (usize, usize) {
+    match pixels.first() {
+        Some(row) => (row.len(), pixels.len()),
+        None => (0, 0),
+    }
+}
 
-pub fn save_png_from_pixel_data(path: String, pixels: &[Vec<Pixel>]) {
+pub fn save_png_from_pixel_data(
+    path: String,
+    pixels: &[Vec<Pixel>],
+    display_transform: &DisplayTransform,
+) -> std::io::Result<()> {
     let height = pixels.len();
- 
+
     if height < 1 {
-        return;
+        return Ok(());
     }
 
     let width = pixels[0].len();
@@ -16,14 +85,441 @@ pub fn save_png_from_pixel_data(path: String, pixels: &[Vec<Pixel>]) {
 
     for (y, row) in pixels.iter().enumerate() {
         for (x, pixel) in row.iter().enumerate() {
-            let color = pixel.color().rgba();
+            let color = display_transform.rgba(pixel.color());
             image_buffer.put_pixel(x as u32, y as u32, color)
         }
     }
 
-    let file_path = std::path::Path::new(&path);
-    let prefix = file_path.parent().unwrap();
-    std::fs::create_dir_all(prefix).unwrap();
+    create_parent_directory(&path)?;
 
-    image_buffer.save(path).unwrap();
+    image_buffer.save(path).map_err(std::io::Error::other)
+}
+
+// Portable float map, three little endian floats per pixel stored bottom row first
+pub fn save_pfm_from_pixel_data(path: &str, pixels: &[Vec<Pixel>]) -> std::io::Result<()> {
+    let (width, height) = image_size(pixels);
+
+    let mut bytes = format!("PF\n{} {}\n-1.0\n", width, height).into_bytes();
+    bytes.reserve(width * height * 12);
+
+    for row in pixels.iter().rev() {
+        for pixel in row {
+            let color = pixel.color();
+            for component in [*color.r(), *color.g(), *color.b()] {
+                bytes.extend_from_slice(&component.to_le_bytes());
+            }
+        }
+    }
+
+    create_parent_directory(path)?;
+    std::fs::write(path, bytes)
+}
+
+pub fn save_exr_from_pixel_data(
+    path: &str,
+    pixels: &[Vec<Pixel>],
+    pixel_type: ExrPixelType,
+    sample_counts: Option<Vec<f32>>,
+) -> std::io::Result<()> {
+    let (width, height) = image_size(pixels);
+
+    let mut image = ExrImage::new(width as u32, height as u32);
+    image.add_color_channels(pixels, pixel_type);
+    // Always full floats, halfs can't hold every whole number past 2048
+    if let Some(sample_counts) = sample_counts {
+        image.add_channel("samples", ExrPixelType::Float, sample_counts);
+    }
+
+    create_parent_directory(path)?;
+    image.save(path)
+}
+
+pub fn save_pixel_data(output: &OutputSettings, pixels: &[Vec<Pixel>]) -> std::io::Result<()> {
+    match output.format {
+        ImageFormat::Png => save_png_from_pixel_data(output.path.clone(), pixels, &output.display),
+        ImageFormat::OpenExr(pixel_type) => {
+            save_exr_from_pixel_data(&output.path, pixels, pixel_type, None)
+        }
+        ImageFormat::Pfm => save_pfm_from_pixel_data(&output.path, pixels),
+    }
+}
+
+// OpenEXR renders also get a "samples" channel with the number of samples each pixel took, row
+// by row from the top left. The other formats only have room for the colour.
+pub fn save_render_data(
+    output: &OutputSettings,
+    pixels: &[Vec<Pixel>],
+    sample_counts: Vec<f32>,
+) -> std::io::Result<()> {
+    match output.format {
+        ImageFormat::OpenExr(pixel_type) => {
+            save_exr_from_pixel_data(&output.path, pixels, pixel_type, Some(sample_counts))
+        }
+        _ => save_pixel_data(output, pixels),
+    }
+}
+
+pub mod exr {
+    use crate::ui::pixel::Pixel;
+
+    const MAGIC_NUMBER: u32 = 20000630;
+    const VERSION: u32 = 2;
+    const NO_COMPRESSION: u8 = 0;
+    const INCREASING_Y: u8 = 0;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum ExrPixelType {
+        Half,
+        Float,
+    }
+
+    impl ExrPixelType {
+        fn id(self) -> i32 {
+            match self {
+                ExrPixelType::Half => 1,
+                ExrPixelType::Float => 2,
+            }
+        }
+
+        fn size(self) -> usize {
+            match self {
+                ExrPixelType::Half => 2,
+                ExrPixelType::Float => 4,
+            }
+        }
+    }
+
+    pub struct ExrChannel {
+        name: String,
+        pixel_type: ExrPixelType,
+        values: Vec<f32>,
+    }
+
+    // Uncompressed single part scanline image, channels are stored row major from the top left
+    pub struct ExrImage {
+        width: u32,
+        height: u32,
+        channels: Vec<ExrChannel>,
+    }
+
+    // Rounds to the nearest half, ties to even, overflowing to infinity
+    pub fn f32_to_half(value: f32) -> u16 {
+        let bits = value.to_bits();
+        let sign = ((bits >> 16) & 0x8000) as u16;
+        let exponent = ((bits >> 23) & 0xff) as i32;
+        let mantissa = bits & 0x7f_ffff;
+
+        if exponent == 0xff {
+            let nan = if mantissa != 0 { 0x0200 } else { 0 };
+            return sign | 0x7c00 | nan;
+        }
+
+        let half_exponent = exponent - 127 + 15;
+
+        if half_exponent >= 0x1f {
+            return sign | 0x7c00;
+        }
+
+        let (half, round_bit, mantissa) = if half_exponent <= 0 {
+            if half_exponent < -10 {
+                return sign;
+            }
+            let mantissa = mantissa | 0x80_0000;
+            let shift = (14 - half_exponent) as u32;
+            (mantissa >> shift, 1 << (shift - 1), mantissa)
+        } else {
+            (
+                ((half_exponent as u32) << 10) | (mantissa >> 13),
+                0x1000,
+                mantissa,
+            )
+        };
+
+        let round_up = mantissa & round_bit != 0 && mantissa & (3 * round_bit - 1) != 0;
+
+        sign | (half + round_up as u32) as u16
+    }
+
+    fn write_attribute(bytes: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(kind.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(value);
+    }
+
+    impl ExrImage {
+        pub fn new(width: u32, height: u32) -> ExrImage {
+            ExrImage {
+                width,
+                height,
+                channels: Vec::new(),
+            }
+        }
+
+        pub fn add_channel(&mut self, name: &str, pixel_type: ExrPixelType, values: Vec<f32>) {
+            assert!(
+                values.len() == (self.width * self.height) as usize,
+                "channel {} has {} values for a {}x{} image",
+                name,
+                values.len(),
+                self.width,
+                self.height
+            );
+            assert!(
+                self.channels.iter().all(|channel| channel.name != name),
+                "channel {} added more than once",
+                name
+            );
+
+            self.channels.push(ExrChannel {
+                name: String::from(name),
+                pixel_type,
+                values,
+            });
+        }
+
+        // Adds the R, G and B channels
+        pub fn add_color_channels(&mut self, pixels: &[Vec<Pixel>], pixel_type: ExrPixelType) {
+            for (index, name) in ["R", "G", "B"].iter().enumerate() {
+                let values = pixels
+                    .iter()
+                    .flatten()
+                    .map(|pixel| {
+                        let color = pixel.color();
+                        *[color.r(), color.g(), color.b()][index]
+                    })
+                    .collect();
+                self.add_channel(name, pixel_type, values);
+            }
+        }
+
+        pub fn encode(&self) -> Vec<u8> {
+            // Readers expect the channel list, and the data in each scanline, in name order
+            let mut channels: Vec<&ExrChannel> = self.channels.iter().collect();
+            channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+            let mut bytes = Vec::new();
+            bytes.extend_from_slice(&MAGIC_NUMBER.to_le_bytes());
+            bytes.extend_from_slice(&VERSION.to_le_bytes());
+
+            let mut channel_list = Vec::new();
+            for channel in &channels {
+                channel_list.extend_from_slice(channel.name.as_bytes());
+                channel_list.push(0);
+                channel_list.extend_from_slice(&channel.pixel_type.id().to_le_bytes());
+                // pLinear and three reserved bytes
+                channel_list.extend_from_slice(&[0, 0, 0, 0]);
+                channel_list.extend_from_slice(&1_i32.to_le_bytes());
+                channel_list.extend_from_slice(&1_i32.to_le_bytes());
+            }
+            channel_list.push(0);
+
+            let mut window = Vec::new();
+            for value in [0, 0, self.width as i32 - 1, self.height as i32 - 1] {
+                window.extend_from_slice(&value.to_le_bytes());
+            }
+
+            write_attribute(&mut bytes, "channels", "chlist", &channel_list);
+            write_attribute(&mut bytes, "compression", "compression", &[NO_COMPRESSION]);
+            write_attribute(&mut bytes, "dataWindow", "box2i", &window);
+            write_attribute(&mut bytes, "displayWindow", "box2i", &window);
+            write_attribute(&mut bytes, "lineOrder", "lineOrder", &[INCREASING_Y]);
+            write_attribute(
+                &mut bytes,
+                "pixelAspectRatio",
+                "float",
+                &1.0_f32.to_le_bytes(),
+            );
+            write_attribute(&mut bytes, "screenWindowCenter", "v2f", &[0; 8]);
+            write_attribute(
+                &mut bytes,
+                "screenWindowWidth",
+                "float",
+                &1.0_f32.to_le_bytes(),
+            );
+            bytes.push(0);
+
+            let width = self.width as usize;
+            let height = self.height as usize;
+            let scanline_size: usize = channels
+                .iter()
+                .map(|channel| channel.pixel_type.size() * width)
+                .sum();
+
+            // Without compression every block is a single scanline of fixed size
+            let first_scanline = bytes.len() + height * 8;
+            for y in 0..height {
+                let offset = first_scanline + y * (scanline_size + 8);
+                bytes.extend_from_slice(&(offset as u64).to_le_bytes());
+            }
+
+            for y in 0..height {
+                bytes.extend_from_slice(&(y as i32).to_le_bytes());
+                bytes.extend_from_slice(&(scanline_size as i32).to_le_bytes());
+
+                for channel in &channels {
+                    let row = &channel.values[y * width..(y + 1) * width];
+                    for value in row {
+                        match channel.pixel_type {
+                            ExrPixelType::Half => {
+                                bytes.extend_from_slice(&f32_to_half(*value).to_le_bytes())
+                            }
+                            ExrPixelType::Float => bytes.extend_from_slice(&value.to_le_bytes()),
+                        }
+                    }
+                }
+            }
+
+            bytes
+        }
+
+        pub fn save(&self, path: &str) -> std::io::Result<()> {
+            std::fs::write(path, self.encode())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::exr::{f32_to_half, ExrImage, ExrPixelType};
+
+    #[test]
+    fn half_zeros_and_exact_values() {
+        assert_eq!(f32_to_half(0.0), 0x0000);
+        assert_eq!(f32_to_half(-0.0), 0x8000);
+        assert_eq!(f32_to_half(1.0), 0x3c00);
+        assert_eq!(f32_to_half(-2.0), 0xc000);
+        assert_eq!(f32_to_half(0.5), 0x3800);
+        // Largest finite half
+        assert_eq!(f32_to_half(65504.0), 0x7bff);
+        // Smallest normal half
+        assert_eq!(f32_to_half(2.0_f32.powi(-14)), 0x0400);
+    }
+
+    #[test]
+    fn half_overflow_and_infinities() {
+        // Halfway between 65504 and the next step, which is past the largest half
+        assert_eq!(f32_to_half(65520.0), 0x7c00);
+        assert_eq!(f32_to_half(-1.0e6), 0xfc00);
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NEG_INFINITY), 0xfc00);
+    }
+
+    #[test]
+    fn half_subnormals() {
+        // Smallest subnormal half
+        assert_eq!(f32_to_half(2.0_f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_half(-(2.0_f32.powi(-24))), 0x8001);
+        // Largest subnormal half
+        assert_eq!(f32_to_half(1023.0 * 2.0_f32.powi(-24)), 0x03ff);
+        // Too small for even the smallest subnormal
+        assert_eq!(f32_to_half(1.0e-10), 0x0000);
+    }
+
+    #[test]
+    fn half_ties_round_to_even() {
+        // Halfway between 1 and the next half, 1 is even
+        assert_eq!(f32_to_half(1.0 + 2.0_f32.powi(-11)), 0x3c00);
+        // Halfway between 0x3c01 and 0x3c02
+        assert_eq!(f32_to_half(1.0 + 3.0 * 2.0_f32.powi(-11)), 0x3c02);
+        // Just past halfway rounds up
+        assert_eq!(
+            f32_to_half(1.0 + 2.0_f32.powi(-11) + 2.0_f32.powi(-20)),
+            0x3c01
+        );
+        // The same between subnormals
+        assert_eq!(f32_to_half(2.0_f32.powi(-25)), 0x0000);
+        assert_eq!(f32_to_half(3.0 * 2.0_f32.powi(-25)), 0x0002);
+    }
+
+    #[test]
+    fn half_nan_stays_nan() {
+        let half = f32_to_half(f32::NAN);
+        assert_eq!(half & 0x7c00, 0x7c00);
+        assert_ne!(half & 0x03ff, 0);
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_string(bytes: &[u8], offset: usize) -> &str {
+        let end = offset + bytes[offset..].iter().position(|&byte| byte == 0).unwrap();
+        std::str::from_utf8(&bytes[offset..end]).unwrap()
+    }
+
+    #[test]
+    fn encode_two_by_one() {
+        let mut image = ExrImage::new(2, 1);
+        image.add_channel("R", ExrPixelType::Float, vec![1.0, 2.0]);
+        image.add_channel("G", ExrPixelType::Half, vec![1.0, -2.0]);
+        image.add_channel("B", ExrPixelType::Float, vec![0.5, 0.25]);
+        let bytes = image.encode();
+
+        assert_eq!(read_u32(&bytes, 0), 20000630);
+        assert_eq!(read_u32(&bytes, 4), 2);
+
+        // Attributes run up to an empty name
+        let mut offset = 8;
+        let mut channel_names = Vec::new();
+        loop {
+            let name = read_string(&bytes, offset);
+            offset += name.len() + 1;
+            if name.is_empty() {
+                break;
+            }
+            let kind = read_string(&bytes, offset);
+            offset += kind.len() + 1;
+            let size = read_u32(&bytes, offset) as usize;
+            offset += 4;
+
+            if name == "channels" {
+                assert_eq!(kind, "chlist");
+                let mut channel = offset;
+                while bytes[channel] != 0 {
+                    let channel_name = read_string(&bytes, channel);
+                    channel_names.push(String::from(channel_name));
+                    channel += channel_name.len() + 1 + 16;
+                }
+                assert_eq!(channel + 1, offset + size);
+            }
+            offset += size;
+        }
+        assert_eq!(channel_names, ["B", "G", "R"]);
+
+        // One scanline, so one offset, pointing at the scanline's y and size
+        let scanline = u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize;
+        assert_eq!(scanline, offset + 8);
+        assert_eq!(read_u32(&bytes, scanline), 0);
+        let size = read_u32(&bytes, scanline + 4) as usize;
+        assert_eq!(size, 2 * 4 + 2 * 2 + 2 * 4);
+        assert_eq!(bytes.len(), scanline + 8 + size);
+
+        // Each channel's row in name order, B, then G as halves, then R
+        let data = &bytes[scanline + 8..];
+        assert_eq!(&data[0..4], &0.5_f32.to_le_bytes());
+        assert_eq!(&data[4..8], &0.25_f32.to_le_bytes());
+        assert_eq!(&data[8..10], &0x3c00_u16.to_le_bytes());
+        assert_eq!(&data[10..12], &0xc000_u16.to_le_bytes());
+        assert_eq!(&data[12..16], &1.0_f32.to_le_bytes());
+        assert_eq!(&data[16..20], &2.0_f32.to_le_bytes());
+    }
+
+    #[test]
+    fn line_offsets_point_at_each_scanline() {
+        let mut image = ExrImage::new(2, 3);
+        image.add_channel("Y", ExrPixelType::Half, vec![0.0; 6]);
+        let bytes = image.encode();
+
+        // The offset table is the last thing before the first scanline
+        let table = bytes.len() - 3 * (8 + 2 * 2) - 3 * 8;
+        for y in 0..3 {
+            let start = table + y * 8;
+            let scanline = u64::from_le_bytes(bytes[start..start + 8].try_into().unwrap()) as usize;
+            assert_eq!(read_u32(&bytes, scanline) as usize, y);
+            assert_eq!(read_u32(&bytes, scanline + 4), 4);
+        }
+    }
 }
@@ -0,0 +1,144 @@
+use std::f32::consts::PI;
+
+// Wider filters would make every chunk splat onto most of the image
+pub const MAX_FILTER_RADIUS: f32 = 16.0;
+
+pub const FILTER_NAMES: &str = "'box', 'tent', 'gaussian', 'mitchell' or 'lanczos'";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterKind {
+    // Every sample counts the same, what the renderer did before filters could be picked
+    Box,
+    Tent,
+    // Falls to zero at the radius, with a standard deviation of a third of it
+    Gaussian,
+    // Mitchell-Netravali with B = C = 1/3, slightly negative lobes keep edges sharp
+    Mitchell,
+    // Sinc windowed by a wider sinc, `radius` is the number of lobes
+    Lanczos,
+}
+
+impl FilterKind {
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        match name {
+            "box" => Some(FilterKind::Box),
+            "tent" => Some(FilterKind::Tent),
+            "gaussian" => Some(FilterKind::Gaussian),
+            "mitchell" => Some(FilterKind::Mitchell),
+            "lanczos" => Some(FilterKind::Lanczos),
+            _ => None,
+        }
+    }
+
+    pub fn default_radius(self) -> f32 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+// Reconstruction filter used to splat each sample onto the pixels around it, weighted by how far
+// the sample is from each pixel's centre. Filters are separable, the weight is the product of
+// the weights along x and y.
+#[derive(Clone, Copy, Debug)]
+pub struct Filter {
+    pub kind: FilterKind,
+    // In pixels, samples reach every pixel whose centre is within this distance along both axes
+    pub radius: f32,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::new(FilterKind::Box)
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1.0e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+fn mitchell(x: f32) -> f32 {
+    const B: f32 = 1.0 / 3.0;
+    const C: f32 = 1.0 / 3.0;
+
+    let x = x.abs();
+    if x < 1.0 {
+        ((12.0 - 9.0 * B - 6.0 * C) * x * x * x
+            + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+            + (6.0 - 2.0 * B))
+            / 6.0
+    } else if x < 2.0 {
+        ((-B - 6.0 * C) * x * x * x
+            + (6.0 * B + 30.0 * C) * x * x
+            + (-12.0 * B - 48.0 * C) * x
+            + (8.0 * B + 24.0 * C))
+            / 6.0
+    } else {
+        0.0
+    }
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Filter {
+        Filter {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    // Any smaller and a sample could miss the pixel it was taken for
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.radius.is_finite() || self.radius < 0.5 || self.radius > MAX_FILTER_RADIUS {
+            return Err(format!(
+                "filter radius must be between 0.5 and {}",
+                MAX_FILTER_RADIUS
+            ));
+        }
+        Ok(())
+    }
+
+    // Pixels are one apart however wide the filter is, so it is evaluated in pixels
+    fn evaluate_1d(&self, offset: f32) -> f32 {
+        let offset = offset.abs();
+
+        if offset > self.radius {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - offset / self.radius,
+            FilterKind::Gaussian => {
+                let sigma = self.radius / 3.0;
+                let gaussian = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(offset) - gaussian(self.radius)).max(0.0)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * offset / self.radius),
+            FilterKind::Lanczos => sinc(offset) * sinc(offset / self.radius),
+        }
+    }
+
+    pub fn evaluate(&self, x_offset: f32, y_offset: f32) -> f32 {
+        self.evaluate_1d(x_offset) * self.evaluate_1d(y_offset)
+    }
+
+    // The first and last pixel along one axis whose centre is within the radius of `position`
+    pub fn pixel_range(&self, position: f32) -> (i64, i64) {
+        (
+            (position - 0.5 - self.radius).floor() as i64 + 1,
+            (position - 0.5 + self.radius).floor() as i64,
+        )
+    }
+
+    // How many pixels past its own a sample can reach
+    pub fn margin(&self) -> u32 {
+        (self.radius - 0.5).ceil().max(0.0) as u32
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use glam::{Affine3A, Mat3A, Mat4, Vec3A};
+
+use crate::{
+    bounding_box::{bvh::BVHNode, Aabb},
+    ray::{Ray, RayCollision},
+};
+
+// Shared geometry placed in the scene through a transform, so a thousand copies of an object
+// only store it once. Rays are moved into the object's own space instead of moving the object
+// out into the world.
+pub struct Instance {
+    object: Arc<BVHNode>,
+    // From the object's space to the world
+    transform: Affine3A,
+    inverse: Affine3A,
+    normal_matrix: Mat3A,
+    aabb: Aabb,
+}
+
+impl Instance {
+    // `transform` must be affine, its bottom row is ignored
+    pub fn new(object: Arc<BVHNode>, transform: Mat4) -> Instance {
+        let transform = Affine3A::from_mat4(transform);
+        let inverse = transform.inverse();
+
+        Instance {
+            aabb: transform_bounding_box(&transform, object.bounds()),
+            normal_matrix: inverse.matrix3.transpose(),
+            object,
+            transform,
+            inverse,
+        }
+    }
+    pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
+        // The direction is left unnormalised so distances along both rays are the same
+        let object_ray = Ray::new(
+            self.inverse.transform_point3a(ray.origin),
+            self.inverse.transform_vector3a(ray.direction),
+            ray.time,
+        );
+
+        let mut collision = self.object.collide_ray(&object_ray, t_min, t_max)?;
+        collision.transform(&self.transform, &self.normal_matrix);
+        Some(collision)
+    }
+    // The object's tree already covers the whole frame
+    pub fn get_bounding_box(&self, _frame_start_time: f32, _frame_end_time: f32) -> Aabb {
+        self.aabb
+    }
+}
+
+// The box around a transformed box, worked out from its centre and half size
+fn transform_bounding_box(transform: &Affine3A, aabb: Aabb) -> Aabb {
+    if !aabb.is_finite() {
+        return Aabb::infinite();
+    }
+
+    let centre = transform.transform_point3a(aabb.centroid());
+    let half_size = (aabb.maximum - aabb.minimum) * 0.5;
+    let matrix = transform.matrix3;
+    let half_size = Vec3A::new(
+        matrix.row(0).abs().dot(half_size),
+        matrix.row(1).abs().dot(half_size),
+        matrix.row(2).abs().dot(half_size),
+    );
+
+    Aabb::new(centre - half_size, centre + half_size)
+}
@@ -0,0 +1,204 @@
+use std::{collections::HashMap, sync::Arc};
+
+use glam::{Vec2, Vec3A};
+
+use crate::{
+    bounding_box::bvh::{BVHBuildStrategy, BVHNode},
+    collider::Collider,
+    ray::Ray,
+    raytracer::DEFAULT_SEED,
+    sampler::Sampler,
+    shape::{Mesh, ShapeSample},
+};
+
+enum Light {
+    Shape(Arc<Collider>),
+    // The emissive triangles of one mesh, sampled as a whole
+    Mesh(MeshLight),
+}
+
+impl Light {
+    // `choice` picks between the parts of the light, `u` a point on the part picked
+    fn sample_towards(
+        &self,
+        origin: Vec3A,
+        time: f32,
+        choice: f32,
+        u: Vec2,
+    ) -> Option<ShapeSample> {
+        match self {
+            Light::Shape(shape) => shape.sample_towards(origin, time, u),
+            Light::Mesh(mesh) => mesh.sample_towards(origin, time, choice, u),
+        }
+    }
+    fn pdf_towards(&self, origin: Vec3A, direction: Vec3A, time: f32) -> f32 {
+        match self {
+            Light::Shape(shape) => shape.pdf_towards(origin, direction, time),
+            Light::Mesh(mesh) => mesh.pdf_towards(origin, direction, time),
+        }
+    }
+}
+
+// Triangles are picked in proportion to their area, so the mesh is sampled as one surface. Its
+// own tree finds the triangles along a direction without testing every one of them.
+struct MeshLight {
+    tree: BVHNode,
+    // Each triangle with the total area of it and those before it
+    triangles: Vec<(f32, Arc<Collider>)>,
+    area: f32,
+}
+
+impl MeshLight {
+    fn new(triangles: Vec<Arc<Collider>>) -> MeshLight {
+        let mut area = 0.0;
+        let areas = triangles
+            .iter()
+            .map(|triangle| {
+                area += triangle_area(triangle);
+                (area, triangle.clone())
+            })
+            .collect();
+
+        MeshLight {
+            tree: BVHNode::build_tree(
+                triangles,
+                0.0,
+                1.0,
+                BVHBuildStrategy::SurfaceAreaHeuristic,
+                DEFAULT_SEED,
+            ),
+            triangles: areas,
+            area,
+        }
+    }
+    fn sample_towards(
+        &self,
+        origin: Vec3A,
+        time: f32,
+        choice: f32,
+        u: Vec2,
+    ) -> Option<ShapeSample> {
+        let choice = choice * self.area;
+        let index = self
+            .triangles
+            .partition_point(|(area, _)| *area <= choice)
+            .min(self.triangles.len() - 1);
+
+        self.triangles[index].1.sample_towards(origin, time, u)
+    }
+    fn pdf_towards(&self, origin: Vec3A, direction: Vec3A, time: f32) -> f32 {
+        if self.area == 0.0 {
+            return 0.0;
+        }
+
+        let ray = Ray::new(origin, direction, time);
+        let mut pdf = 0.0;
+        self.tree
+            .for_each_along_ray(&ray, 0.0, f32::INFINITY, |triangle| {
+                pdf += triangle.pdf_towards(origin, direction, time) * triangle_area(triangle);
+            });
+
+        pdf / self.area
+    }
+}
+
+fn triangle_area(collider: &Collider) -> f32 {
+    match collider {
+        Collider::TriangleCollider(triangle) => triangle.area(),
+        _ => 0.0,
+    }
+}
+
+// Every collider with an emissive material that can be sampled, used to sample light directly at
+// each hit. Other emissive shapes only light what scatters onto them.
+pub struct Lights {
+    lights: Vec<Light>,
+}
+
+impl Lights {
+    pub fn from_colliders(colliders: &[Arc<Collider>]) -> Lights {
+        let mut lights = Vec::new();
+        // Triangles are grouped by the mesh they come from, in the order the meshes are found
+        let mut meshes: Vec<Vec<Arc<Collider>>> = Vec::new();
+        let mut mesh_indices: HashMap<*const Mesh, usize> = HashMap::new();
+
+        let emitters = colliders.iter().filter(|collider| {
+            collider.can_sample()
+                && collider
+                    .material()
+                    .is_some_and(|material| material.is_emissive())
+        });
+        for collider in emitters {
+            match collider.as_ref() {
+                Collider::TriangleCollider(triangle) => {
+                    let index = *mesh_indices
+                        .entry(Arc::as_ptr(triangle.mesh()))
+                        .or_insert_with(|| {
+                            meshes.push(Vec::new());
+                            meshes.len() - 1
+                        });
+                    meshes[index].push(collider.clone());
+                }
+                _ => lights.push(Light::Shape(collider.clone())),
+            }
+        }
+
+        lights.extend(meshes.into_iter().map(|mut triangles| {
+            if triangles.len() == 1 {
+                Light::Shape(triangles.remove(0))
+            } else {
+                Light::Mesh(MeshLight::new(triangles))
+            }
+        }));
+
+        Lights { lights }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+    // Picks a light uniformly and samples a direction towards it. The pdf is for the mix of every
+    // light, as any of them could have produced the direction, which keeps it equal to `pdf`
+    pub fn sample(&self, origin: Vec3A, time: f32, sampler: &mut Sampler) -> Option<ShapeSample> {
+        if self.lights.is_empty() {
+            return None;
+        }
+
+        // What's left of the choice once the light is picked picks a part of the light
+        let choice = sampler.get_1d() * self.lights.len() as f32;
+        let index = (choice as usize).min(self.lights.len() - 1);
+        let light = &self.lights[index];
+        let sample = light.sample_towards(origin, time, choice - index as f32, sampler.get_2d())?;
+        let pdf = self.pdf(origin, sample.direction, time);
+
+        // Grazing samples can miss the light they were taken from once the direction is rounded
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(ShapeSample { pdf, ..sample })
+    }
+    pub fn pdf(&self, origin: Vec3A, direction: Vec3A, time: f32) -> f32 {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+
+        let total: f32 = self
+            .lights
+            .iter()
+            .map(|light| light.pdf_towards(origin, direction, time))
+            .sum();
+
+        total / self.lights.len() as f32
+    }
+}
+
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let pdf_squared = pdf * pdf;
+    let other_pdf_squared = other_pdf * other_pdf;
+
+    if pdf_squared + other_pdf_squared == 0.0 {
+        return 0.0;
+    }
+
+    pdf_squared / (pdf_squared + other_pdf_squared)
+}
@@ -1,74 +1,205 @@
-use camera::Camera;
-use glam::Vec3A;
-use raytracer::render_scene_save_to_file;
-use scene::generator::make_random_balls_scene;
+use cli::{parse_arguments, CliCommand, USAGE};
+use accumulator::Accumulator;
+use file::{save_pixel_data, save_render_data};
+use raytracer::Renderer;
+use scene_file::{load_scene_file, SceneDescription};
 use stats::Stats;
-use std::{sync::Arc, thread};
+use std::{
+    sync::{atomic::AtomicBool, Arc},
+    thread,
+    time::Instant,
+};
 
 use crossbeam_channel::Sender;
 use ui::{pixel::PixelBatchUpdate, window::Window};
 
+mod accumulator;
 mod bounding_box;
 mod camera;
+mod cli;
 mod color;
+mod csg;
+mod display;
 mod file;
+mod filter;
+mod instance;
+mod light;
 mod material;
 mod maths;
+mod obj;
 mod ray;
+mod sampler;
 mod raytracer;
 mod scene;
+mod scene_file;
 mod stats;
+mod texture;
 mod shape;
 mod ui;
 mod collider;
 
-const IMAGE_WIDTH: u32 = 1080;
-const IMAGE_HEIGHT: u32 = 920;
-const WINDOW_WIDTH: u32 = 1080;
-const WINDOW_HEIGHT: u32 = 920;
-const THREAD_POOL_SIZE: u32 = 12;
-
-// const SAMPLES_PER_PIXEL_SIDE_VALUES: [u32; 2] = [1, 2];
-const SAMPLES_PER_PIXEL_SIDE_VALUES: [u32; 4] = [1, 2, 4, 8];
-
-fn ray_trace(width: u32, height: u32, pixel_batch_sender: Sender<PixelBatchUpdate>) {
-    let camera = Camera::new(
-        width,
-        height,
-        Vec3A::new(13.0, 2.0, 3.0),
-        Vec3A::new(0.0, 0.0, 0.0),
-        Vec3A::new(0.0, 1.0, 0.0),
-        20.0,
-        0.0,
-        0.0,
-    );
-    let scene = Arc::new(make_random_balls_scene());
+// Each entry in the samples ladder is the number of samples per pixel side the image should have
+// reached by the end of that pass, so every pass only takes the samples it is missing. Adaptive
+// sampling ignores the ladder and keeps adding `min_samples` to the pixels that need them until
+// none do. The image is saved after every pass and when `stop` cuts a pass short.
+fn ray_trace(
+    scene_description: SceneDescription,
+    stop: Arc<AtomicBool>,
+    pixel_batch_sender: Sender<PixelBatchUpdate>,
+) -> std::io::Result<()> {
+    let SceneDescription {
+        scene,
+        camera,
+        settings,
+    } = scene_description;
+    let scene = Arc::new(scene);
     let stats = Stats::new(pixel_batch_sender.clone(), 1);
     stats.clone().init();
 
-    for samples_per_pixel_side in SAMPLES_PER_PIXEL_SIDE_VALUES {
-        render_scene_save_to_file(
-            &scene,
-            &camera,
-            samples_per_pixel_side,
-            String::from("output/raytracer.png"),
+    let renderer = Renderer::new(&scene, &camera, &settings.render);
+    stats.clone().set_bvh_stats(renderer.bvh_stats());
+    let mut accumulator = Accumulator::new(settings.image_width, settings.image_height);
+
+    let passes: Box<dyn Iterator<Item = u32>> = match settings.render.adaptive {
+        Some(adaptive) => Box::new(std::iter::repeat(adaptive.min_samples)),
+        None => Box::new(
+            settings
+                .samples_per_pixel_side_values
+                .iter()
+                .scan(0, |samples_per_pixel, samples_per_pixel_side| {
+                    let target = samples_per_pixel_side * samples_per_pixel_side;
+                    let missing = target.saturating_sub(*samples_per_pixel);
+                    *samples_per_pixel += missing;
+                    Some(missing)
+                })
+                .filter(|missing| *missing > 0),
+        ),
+    };
+
+    for (pass, samples_per_pixel) in passes.enumerate() {
+        let start_time = Instant::now();
+
+        let summary = renderer.render_pass(
+            &mut accumulator,
+            samples_per_pixel,
+            &stop,
             pixel_batch_sender.clone(),
             stats.clone(),
         );
+
+        // A pass that was stopped still saves whatever the accumulator holds, even when no chunk
+        // of it finished
+        if summary.completed && summary.pixels_sampled == 0 {
+            println!("\nNo pixels need any more samples");
+            break;
+        }
+
+        save_render_data(
+            &settings.output,
+            &accumulator.pixel_data(),
+            accumulator.sample_counts(),
+        )?;
+
+        if !summary.completed {
+            println!(
+                "\nStopped during pass {}, saved the image so far to {}",
+                pass + 1,
+                settings.output.path
+            );
+            break;
+        }
+
+        println!(
+            "\nPass {}: {} samples over {} pixels in {:.3}s, saved to {}",
+            pass + 1,
+            summary.samples_taken,
+            summary.pixels_sampled,
+            start_time.elapsed().as_secs_f32(),
+            settings.output.path
+        );
+    }
+
+    if let Some(heatmap) = &settings.heatmap {
+        let max_samples = settings
+            .render
+            .adaptive
+            .map_or(0, |adaptive| adaptive.max_samples);
+        save_pixel_data(heatmap, &accumulator.sample_heatmap(max_samples))?;
+        println!("Saved the samples heatmap to {}", heatmap.path);
+    }
+
+    Ok(())
+}
+
+// Renders on this thread with nothing but the terminal to report to, for machines without a
+// display. Exits with a non zero status if the render could not be saved.
+fn ray_trace_headless(scene_description: SceneDescription) {
+    let output_path = scene_description.settings.output.path.clone();
+
+    // Nobody is drawing the pixel updates, they only need to be taken off the channel
+    let (pixel_batch_update_sender, pixel_batch_update_receiver) =
+        crossbeam_channel::unbounded::<PixelBatchUpdate>();
+    thread::spawn(move || pixel_batch_update_receiver.iter().for_each(drop));
+
+    let stop = Arc::new(AtomicBool::new(false));
+
+    if let Err(error) = ray_trace(scene_description, stop, pixel_batch_update_sender) {
+        eprintln!("\nError saving render to {}: {}", output_path, error);
+        std::process::exit(1);
     }
 }
 
 fn main() {
+    let options = match parse_arguments(std::env::args().skip(1)) {
+        Ok(CliCommand::Render(options)) => options,
+        Ok(CliCommand::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(error) => {
+            eprintln!("Error: {}\n\n{}", error, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let mut scene_description = match load_scene_file(&options.scene_path) {
+        Ok(scene_description) => scene_description,
+        Err(error) => {
+            eprintln!("Error loading scene {}: {}", options.scene_path, error);
+            std::process::exit(1);
+        }
+    };
+    if let Err(error) = options.apply(&mut scene_description) {
+        eprintln!("Error: {}", error);
+        std::process::exit(2);
+    }
+
+    let image_width = scene_description.settings.image_width;
+    let image_height = scene_description.settings.image_height;
+    let display_transform = scene_description.settings.output.display;
+
     rayon::ThreadPoolBuilder::new()
-        .num_threads(THREAD_POOL_SIZE as usize)
+        .num_threads(options.threads as usize)
         .build_global()
         .unwrap();
 
+    if options.headless {
+        ray_trace_headless(scene_description);
+        return;
+    }
+
+    let output_path = scene_description.settings.output.path.clone();
+
     let (pixel_batch_update_sender, pixel_batch_update_receiver) =
         crossbeam_channel::unbounded::<PixelBatchUpdate>();
 
-    thread::spawn(|| {
-        ray_trace(IMAGE_WIDTH, IMAGE_HEIGHT, pixel_batch_update_sender);
+    let stop = Arc::new(AtomicBool::new(false));
+    let render_stop = stop.clone();
+
+    let render_thread = thread::spawn(move || {
+        if let Err(error) = ray_trace(scene_description, render_stop, pixel_batch_update_sender) {
+            eprintln!("\nError saving render to {}: {}", output_path, error);
+        }
     });
 
     // thread::spawn(|| {
@@ -85,11 +216,14 @@ fn main() {
     // });
 
     let window = Window::new(
-        WINDOW_WIDTH as f64,
-        WINDOW_HEIGHT as f64,
-        IMAGE_WIDTH,
-        IMAGE_HEIGHT,
+        image_width as f64,
+        image_height as f64,
+        image_width,
+        image_height,
+        display_transform,
         pixel_batch_update_receiver,
+        stop,
+        render_thread,
     );
 
     let ui_result = window.init();
@@ -6,21 +6,40 @@ use crate::{
 pub struct MaterialCollisionResult {
     pub color: Color,
     pub ray: Ray,
+    // Probability density of the scattered direction, `None` for specular reflection and refraction
+    pub pdf: Option<f32>,
+}
+
+// Scattering towards a given direction, `color` includes the cosine term
+pub struct MaterialEvaluation {
+    pub color: Color,
+    pub pdf: f32,
 }
 
 mod utils {
     use glam::Vec3A;
-    use rand::Rng;
 
-    use crate::{maths::random_point_in_unit_sphere, ray::Ray};
+    use crate::{
+        maths::sample_unit_sphere,
+        ray::Ray,
+        sampler::Sampler,
+    };
 
     pub(crate) fn reflect_vec3_af32(v1: Vec3A, v2: Vec3A) -> Vec3A {
         v1 - 2.0 * v1.dot(v2) * v2
     }
 
-    pub(crate) fn reflect_ray(ray: &Ray, point: Vec3A, normal: Vec3A, smoothness: f32) -> Ray {
+    pub(crate) fn reflect_ray(
+        ray: &Ray,
+        point: Vec3A,
+        normal: Vec3A,
+        smoothness: f32,
+        sampler: &mut Sampler,
+    ) -> Ray {
         let direction = reflect_vec3_af32(ray.direction, normal); //ray.direction - 2.0 * ray.direction.dot(&normal) * normal;
-        let roughness_offset = (1.0 - smoothness) * random_point_in_unit_sphere();
+        let offset_direction = sampler.get_2d();
+        let roughness_offset =
+            (1.0 - smoothness) * sample_unit_sphere(offset_direction, sampler.get_1d());
         Ray::new(point + roughness_offset, direction, ray.time)
     }
 
@@ -36,6 +55,7 @@ mod utils {
         normal: Vec3A,
         on_front_face: bool,
         refraction_index: f32,
+        sampler: &mut Sampler,
     ) -> Ray {
         let refraction_ratio: f32 = if on_front_face {
             1.0 / refraction_index
@@ -48,9 +68,8 @@ mod utils {
         let cos_theta = ((-1.0) * unit_direction).dot(normal).min(1.0);
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
 
-        let mut rng = rand::thread_rng();
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let will_reflect = rng.gen::<f32>() < reflectance(cos_theta, refraction_ratio);
+        let will_reflect = sampler.get_1d() < reflectance(cos_theta, refraction_ratio);
 
         if cannot_refract || will_reflect {
             let direction = reflect_vec3_af32(unit_direction, normal);
@@ -67,17 +86,257 @@ mod utils {
     }
 }
 
+// GGX (Trowbridge-Reitz) microfacet distribution with separate roughness along the tangent and
+// bitangent. Directions are in the local shading frame, where the normal is +z.
+pub(crate) mod microfacet {
+    use std::f32::consts::PI;
+
+    use glam::{Vec2, Vec3A};
+
+    use crate::{color::Color, maths::orthonormal_basis, ray::RayCollision};
+
+    // Below this the surface is treated as perfectly smooth
+    const SMOOTH_ALPHA: f32 = 1.0e-3;
+
+    // Tangent, bitangent and normal at a hit, the tangent following the shape's when it has one
+    pub(crate) struct ShadingFrame {
+        tangent: Vec3A,
+        bitangent: Vec3A,
+        normal: Vec3A,
+    }
+
+    impl ShadingFrame {
+        pub(crate) fn new(collision: &RayCollision) -> ShadingFrame {
+            let normal = collision.normal();
+            let tangent = collision.tangent() - normal * normal.dot(collision.tangent());
+
+            let (tangent, bitangent) = if tangent.length_squared() > 1.0e-12 {
+                let tangent = tangent.normalize();
+                (tangent, normal.cross(tangent))
+            } else {
+                orthonormal_basis(normal)
+            };
+
+            ShadingFrame {
+                tangent,
+                bitangent,
+                normal,
+            }
+        }
+
+        pub(crate) fn to_local(&self, direction: Vec3A) -> Vec3A {
+            Vec3A::new(
+                direction.dot(self.tangent),
+                direction.dot(self.bitangent),
+                direction.dot(self.normal),
+            )
+        }
+
+        pub(crate) fn to_world(&self, direction: Vec3A) -> Vec3A {
+            self.tangent * direction.x + self.bitangent * direction.y + self.normal * direction.z
+        }
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    pub(crate) struct Ggx {
+        alpha_x: f32,
+        alpha_y: f32,
+    }
+
+    impl Ggx {
+        // Roughness is squared into alpha, which makes it change more evenly to the eye
+        pub(crate) fn new(roughness_u: f32, roughness_v: f32) -> Ggx {
+            Ggx {
+                alpha_x: (roughness_u * roughness_u).max(1.0e-4),
+                alpha_y: (roughness_v * roughness_v).max(1.0e-4),
+            }
+        }
+
+        pub(crate) fn is_smooth(&self) -> bool {
+            self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA
+        }
+
+        pub(crate) fn distribution(&self, half: Vec3A) -> f32 {
+            if half.z <= 0.0 {
+                return 0.0;
+            }
+
+            let cos2_theta = half.z * half.z;
+            let slope = (half.x * half.x / (self.alpha_x * self.alpha_x)
+                + half.y * half.y / (self.alpha_y * self.alpha_y))
+                / cos2_theta;
+
+            1.0 / (PI
+                * self.alpha_x
+                * self.alpha_y
+                * cos2_theta
+                * cos2_theta
+                * (1.0 + slope).powi(2))
+        }
+
+        // Smith's Lambda, the hidden microfacet area per visible area seen from `direction`
+        fn lambda(&self, direction: Vec3A) -> f32 {
+            let z2 = direction.z * direction.z;
+            if z2 == 0.0 {
+                return f32::INFINITY;
+            }
+
+            let alpha2_tan2_theta = (self.alpha_x * self.alpha_x * direction.x * direction.x
+                + self.alpha_y * self.alpha_y * direction.y * direction.y)
+                / z2;
+
+            ((1.0 + alpha2_tan2_theta).sqrt() - 1.0) / 2.0
+        }
+
+        pub(crate) fn masking(&self, direction: Vec3A) -> f32 {
+            1.0 / (1.0 + self.lambda(direction))
+        }
+
+        // Height correlated masking and shadowing
+        pub(crate) fn masking_shadowing(&self, outgoing: Vec3A, incoming: Vec3A) -> f32 {
+            1.0 / (1.0 + self.lambda(outgoing) + self.lambda(incoming))
+        }
+
+        // Density of the normals visible from `outgoing`, which `sample_visible_normal` draws from
+        pub(crate) fn visible_normal_pdf(&self, outgoing: Vec3A, half: Vec3A) -> f32 {
+            let cos_outgoing = outgoing.z.abs();
+            if cos_outgoing == 0.0 {
+                return 0.0;
+            }
+
+            self.masking(outgoing) * self.distribution(half) * outgoing.dot(half).max(0.0)
+                / cos_outgoing
+        }
+
+        // Heitz's "Sampling the GGX Distribution of Visible Normals", so no samples are wasted on
+        // microfacets facing away from the viewer
+        pub(crate) fn sample_visible_normal(&self, outgoing: Vec3A, u: Vec2) -> Vec3A {
+            let stretched = Vec3A::new(
+                self.alpha_x * outgoing.x,
+                self.alpha_y * outgoing.y,
+                outgoing.z,
+            )
+            .normalize();
+
+            let length2 = stretched.x * stretched.x + stretched.y * stretched.y;
+            let tangent1 = if length2 > 0.0 {
+                Vec3A::new(-stretched.y, stretched.x, 0.0) / length2.sqrt()
+            } else {
+                Vec3A::new(1.0, 0.0, 0.0)
+            };
+            let tangent2 = stretched.cross(tangent1);
+
+            let radius = u.x.sqrt();
+            let phi = 2.0 * PI * u.y;
+            let t1 = radius * phi.cos();
+            let blend = 0.5 * (1.0 + stretched.z);
+            let t2 = (1.0 - blend) * (1.0 - t1 * t1).max(0.0).sqrt() + blend * radius * phi.sin();
+
+            let normal = tangent1 * t1
+                + tangent2 * t2
+                + stretched * (1.0 - t1 * t1 - t2 * t2).max(0.0).sqrt();
+
+            Vec3A::new(
+                self.alpha_x * normal.x,
+                self.alpha_y * normal.y,
+                normal.z.max(1.0e-6),
+            )
+            .normalize()
+        }
+    }
+
+    pub(crate) fn reflect(outgoing: Vec3A, normal: Vec3A) -> Vec3A {
+        2.0 * outgoing.dot(normal) * normal - outgoing
+    }
+
+    // Bends `outgoing` through a surface with normal `normal` on its side, `eta` is the index of
+    // refraction on the far side over the near side. `None` for total internal reflection.
+    pub(crate) fn refract(outgoing: Vec3A, normal: Vec3A, eta: f32) -> Option<Vec3A> {
+        let cos_outgoing = outgoing.dot(normal);
+        let sin2_transmitted = (1.0 - cos_outgoing * cos_outgoing).max(0.0) / (eta * eta);
+
+        if sin2_transmitted >= 1.0 {
+            return None;
+        }
+
+        let cos_transmitted = (1.0 - sin2_transmitted).sqrt();
+        Some(-outgoing / eta + (cos_outgoing / eta - cos_transmitted) * normal)
+    }
+
+    // Exact Fresnel reflectance of a dielectric for unpolarised light
+    pub(crate) fn fresnel_dielectric(cos_outgoing: f32, eta: f32) -> f32 {
+        let cos_outgoing = cos_outgoing.clamp(0.0, 1.0);
+        let sin2_transmitted = (1.0 - cos_outgoing * cos_outgoing) / (eta * eta);
+
+        if sin2_transmitted >= 1.0 {
+            return 1.0;
+        }
+
+        let cos_transmitted = (1.0 - sin2_transmitted).sqrt();
+        let parallel =
+            (eta * cos_outgoing - cos_transmitted) / (eta * cos_outgoing + cos_transmitted);
+        let perpendicular =
+            (cos_outgoing - eta * cos_transmitted) / (cos_outgoing + eta * cos_transmitted);
+
+        (parallel * parallel + perpendicular * perpendicular) / 2.0
+    }
+
+    // Fresnel reflectance of a conductor with complex index of refraction `eta` + i`k`
+    fn fresnel_conductor_channel(cos_theta: f32, eta: f32, k: f32) -> f32 {
+        let cos2 = cos_theta * cos_theta;
+        let sin2 = 1.0 - cos2;
+
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2.0 * a * cos_theta;
+        let perpendicular = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+
+        (perpendicular + parallel) / 2.0
+    }
+
+    // Schlick's approximation, blending from the reflectance `f0` head on to white at grazing
+    pub(crate) fn fresnel_schlick(cos_theta: f32, f0: Color) -> Color {
+        let weight = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+        f0 + (Color::one() - f0) * weight
+    }
+
+    pub(crate) fn fresnel_conductor(cos_theta: f32, eta: Color, k: Color) -> Color {
+        let cos_theta = cos_theta.clamp(0.0, 1.0);
+
+        Color::new(
+            fresnel_conductor_channel(cos_theta, *eta.r(), *k.r()),
+            fresnel_conductor_channel(cos_theta, *eta.g(), *k.g()),
+            fresnel_conductor_channel(cos_theta, *eta.b(), *k.b()),
+        )
+    }
+}
+
 pub mod materials {
-    use glam::Vec3A;
+    use std::{f32::consts::PI, sync::Arc};
+
+    use glam::{Vec2, Vec3A};
 
     use crate::{
-        maths::{is_vec3_af32_near_zero, random_point_in_unit_sphere},
+        maths::{is_vec3_af32_near_zero, sample_unit_sphere_surface},
         ray::{Ray, RayCollision},
         color::Color,
+        sampler::Sampler,
+        texture::{constant_texture, Texture},
     };
 
     use super::{
-        utils::{reflect_ray, refract_ray}, MaterialCollisionResult,
+        microfacet::{
+            fresnel_conductor, fresnel_dielectric, fresnel_schlick, reflect, refract, Ggx,
+            ShadingFrame,
+        },
+        utils::{reflect_ray, refract_ray}, MaterialCollisionResult, MaterialEvaluation,
     };
 
     pub struct Dielectric {
@@ -92,7 +351,12 @@ pub mod materials {
                 transparency,
             }
         }
-        pub fn scatter(&self, ray: &Ray, hit_record: &RayCollision) -> Option<MaterialCollisionResult> {
+        pub fn scatter(
+            &self,
+            ray: &Ray,
+            hit_record: &RayCollision,
+            sampler: &mut Sampler,
+        ) -> Option<MaterialCollisionResult> {
             Some(MaterialCollisionResult {
                 color: Color::new(self.transparency, self.transparency, self.transparency),
                 ray: refract_ray(
@@ -101,78 +365,757 @@ pub mod materials {
                     hit_record.normal(),
                     hit_record.on_front_face(),
                     self.refraction_index,
+                    sampler,
                 ),
+                pdf: None,
             })
         }
     }
 
+    pub struct Emissive {
+        color: Color,
+        strength: f32,
+    }
+
+    impl Emissive {
+        pub fn new(color: Color, strength: f32) -> Emissive {
+            Emissive { color, strength }
+        }
+        pub fn emitted(&self, _ray: &Ray, _ray_collision: &RayCollision) -> Color {
+            self.color * self.strength
+        }
+    }
+
     pub struct Lambertian {
-        albedo: Color,
+        albedo: Arc<Texture>,
     }
 
     impl Lambertian {
         pub fn new(albedo: Color) -> Lambertian {
+            Lambertian::with_texture(Arc::new(Texture::Constant(albedo)))
+        }
+        pub fn with_texture(albedo: Arc<Texture>) -> Lambertian {
             Lambertian { albedo }
         }
         pub fn scatter(
             &self,
             _ray: &Ray,
             ray_collision: &RayCollision,
+            sampler: &mut Sampler,
         ) -> Option<MaterialCollisionResult> {
             let mut scatter_direction: Vec3A =
-                ray_collision.normal() + random_point_in_unit_sphere().normalize();
+                ray_collision.normal() + sample_unit_sphere_surface(sampler.get_2d());
 
             if is_vec3_af32_near_zero(&scatter_direction) {
                 scatter_direction = ray_collision.normal();
             }
 
             let scattered = Ray::new(ray_collision.point(), scatter_direction, 0.0);
+            let cos_theta = scatter_direction.normalize().dot(ray_collision.normal());
 
             Some(MaterialCollisionResult {
-                color: self.albedo,
+                color: self.albedo.value(ray_collision.uv(), ray_collision.point()),
                 ray: scattered,
+                pdf: Some(cos_theta.max(0.0) / PI),
+            })
+        }
+        pub fn evaluate(
+            &self,
+            ray_collision: &RayCollision,
+            direction: Vec3A,
+        ) -> Option<MaterialEvaluation> {
+            let cos_theta = direction.normalize().dot(ray_collision.normal());
+
+            if cos_theta <= 0.0 {
+                return None;
+            }
+
+            Some(MaterialEvaluation {
+                color: self.albedo.value(ray_collision.uv(), ray_collision.point())
+                    * (cos_theta / PI),
+                pdf: cos_theta / PI,
             })
         }
     }
 
     pub struct Metal {
-        albedo: Color,
+        albedo: Arc<Texture>,
         smoothness: f32,
     }
 
     impl Metal {
         pub fn new(albedo: Color, smoothness: f32) -> Metal {
+            Metal::with_texture(Arc::new(Texture::Constant(albedo)), smoothness)
+        }
+        pub fn with_texture(albedo: Arc<Texture>, smoothness: f32) -> Metal {
             Metal { albedo, smoothness }
         }
-        pub fn scatter(&self, ray: &Ray, hit_record: &RayCollision) -> Option<MaterialCollisionResult> {
+        pub fn scatter(
+            &self,
+            ray: &Ray,
+            hit_record: &RayCollision,
+            sampler: &mut Sampler,
+        ) -> Option<MaterialCollisionResult> {
             let scattered = reflect_ray(
                 ray,
                 hit_record.point(),
                 hit_record.normal(),
                 self.smoothness,
+                sampler,
             );
             if scattered.direction.dot(hit_record.normal()) > 0.0 {
                 return Some(MaterialCollisionResult {
-                    color: self.albedo,
+                    color: self.albedo.value(hit_record.uv(), hit_record.point()),
                     ray: scattered,
+                    pdf: None,
                 });
             }
             None
         }
     }
 
+    pub const METAL_NAMES: &str = "'gold', 'silver', 'copper' or 'aluminium'";
+
+    // Complex index of refraction of a metal, `eta` + i`k` for red, green and blue
+    #[derive(Clone, Copy, Debug)]
+    pub struct ConductorIor {
+        pub eta: Color,
+        pub k: Color,
+    }
+
+    impl ConductorIor {
+        pub fn from_name(name: &str) -> Option<ConductorIor> {
+            let (eta, k) = match name {
+                "gold" => ((0.143, 0.374, 1.442), (3.983, 2.386, 1.603)),
+                "silver" => ((0.155, 0.117, 0.138), (4.828, 3.122, 2.147)),
+                "copper" => ((0.200, 0.924, 1.102), (3.912, 2.452, 2.142)),
+                "aluminium" => ((1.657, 0.880, 0.521), (9.224, 6.270, 4.837)),
+                _ => return None,
+            };
+
+            Some(ConductorIor {
+                eta: Color::new(eta.0, eta.1, eta.2),
+                k: Color::new(k.0, k.1, k.2),
+            })
+        }
+    }
+
+    // Microfacet metal, only the Fresnel term colours it so it darkens and tints towards grazing
+    // angles the way real metals do
+    pub struct Conductor {
+        ior: ConductorIor,
+        distribution: Ggx,
+    }
+
+    impl Conductor {
+        // Roughness runs from 0 (a mirror) to 1, `roughness_u` along the surface's tangent and
+        // `roughness_v` across it
+        pub fn new(ior: ConductorIor, roughness_u: f32, roughness_v: f32) -> Conductor {
+            Conductor {
+                ior,
+                distribution: Ggx::new(roughness_u, roughness_v),
+            }
+        }
+        pub fn scatter(
+            &self,
+            ray: &Ray,
+            collision: &RayCollision,
+            sampler: &mut Sampler,
+        ) -> Option<MaterialCollisionResult> {
+            let frame = ShadingFrame::new(collision);
+            let outgoing = frame.to_local(-ray.direction.normalize());
+            if outgoing.z <= 0.0 {
+                return None;
+            }
+
+            if self.distribution.is_smooth() {
+                let incoming = Vec3A::new(-outgoing.x, -outgoing.y, outgoing.z);
+                return Some(MaterialCollisionResult {
+                    color: fresnel_conductor(outgoing.z, self.ior.eta, self.ior.k),
+                    ray: Ray::new(collision.point(), frame.to_world(incoming), ray.time),
+                    pdf: None,
+                });
+            }
+
+            let half = self
+                .distribution
+                .sample_visible_normal(outgoing, sampler.get_2d());
+            let incoming = reflect(outgoing, half);
+            if incoming.z <= 0.0 {
+                return None;
+            }
+
+            let cos_half = outgoing.dot(half);
+            let fresnel = fresnel_conductor(cos_half, self.ior.eta, self.ior.k);
+            // D and the visible normal density cancel, leaving the shadowing not already
+            // accounted for by sampling visible normals
+            let weight = self.distribution.masking_shadowing(outgoing, incoming)
+                / self.distribution.masking(outgoing);
+
+            Some(MaterialCollisionResult {
+                color: fresnel * weight,
+                ray: Ray::new(collision.point(), frame.to_world(incoming), ray.time),
+                pdf: Some(self.distribution.visible_normal_pdf(outgoing, half) / (4.0 * cos_half)),
+            })
+        }
+        pub fn evaluate(
+            &self,
+            ray: &Ray,
+            collision: &RayCollision,
+            direction: Vec3A,
+        ) -> Option<MaterialEvaluation> {
+            if self.distribution.is_smooth() {
+                return None;
+            }
+
+            let frame = ShadingFrame::new(collision);
+            let outgoing = frame.to_local(-ray.direction.normalize());
+            let incoming = frame.to_local(direction.normalize());
+            if outgoing.z <= 0.0 || incoming.z <= 0.0 {
+                return None;
+            }
+
+            let half = (outgoing + incoming).normalize();
+            let cos_half = outgoing.dot(half);
+            let fresnel = fresnel_conductor(cos_half, self.ior.eta, self.ior.k);
+            let specular = self.distribution.distribution(half)
+                * self.distribution.masking_shadowing(outgoing, incoming)
+                / (4.0 * outgoing.z * incoming.z);
+
+            Some(MaterialEvaluation {
+                color: fresnel * (specular * incoming.z),
+                pdf: self.distribution.visible_normal_pdf(outgoing, half) / (4.0 * cos_half),
+            })
+        }
+    }
+
+    // Microfacet glass that both reflects and refracts, frosted when rough
+    pub struct RoughDielectric {
+        refraction_index: f32,
+        // Colour picked up by light passing through the surface
+        tint: Color,
+        distribution: Ggx,
+    }
+
+    // A direction picked by a material in its shading frame, `pdf` is `None` for smooth surfaces
+    // that only scatter one way
+    struct LocalSample {
+        incoming: Vec3A,
+        color: Color,
+        pdf: Option<f32>,
+    }
+
+    impl RoughDielectric {
+        pub fn new(
+            refraction_index: f32,
+            tint: Color,
+            roughness_u: f32,
+            roughness_v: f32,
+        ) -> RoughDielectric {
+            RoughDielectric {
+                refraction_index,
+                tint,
+                distribution: Ggx::new(roughness_u, roughness_v),
+            }
+        }
+        // Index of refraction on the far side of the surface over the side the ray came from
+        fn eta(&self, collision: &RayCollision) -> f32 {
+            if collision.on_front_face() {
+                self.refraction_index
+            } else {
+                1.0 / self.refraction_index
+            }
+        }
+        pub fn scatter(
+            &self,
+            ray: &Ray,
+            collision: &RayCollision,
+            sampler: &mut Sampler,
+        ) -> Option<MaterialCollisionResult> {
+            let frame = ShadingFrame::new(collision);
+            let outgoing = frame.to_local(-ray.direction.normalize());
+            let sample = self.sample_local(
+                outgoing,
+                self.eta(collision),
+                sampler.get_2d(),
+                sampler.get_1d(),
+            )?;
+
+            Some(MaterialCollisionResult {
+                color: sample.color,
+                ray: Ray::new(collision.point(), frame.to_world(sample.incoming), ray.time),
+                pdf: sample.pdf,
+            })
+        }
+        // `u` picks the microfacet and `choice` between reflecting and refracting
+        fn sample_local(
+            &self,
+            outgoing: Vec3A,
+            eta: f32,
+            u: Vec2,
+            choice: f32,
+        ) -> Option<LocalSample> {
+            if outgoing.z <= 0.0 {
+                return None;
+            }
+
+            let smooth = self.distribution.is_smooth();
+            let half = if smooth {
+                Vec3A::new(0.0, 0.0, 1.0)
+            } else {
+                self.distribution.sample_visible_normal(outgoing, u)
+            };
+
+            let cos_half = outgoing.dot(half);
+            let fresnel = fresnel_dielectric(cos_half, eta);
+            let refracted = refract(outgoing, half, eta);
+
+            // Reflection and refraction are picked in proportion to the Fresnel reflectance,
+            // which cancels it out of the weights
+            let (incoming, color, choice_pdf) = match refracted {
+                Some(refracted) if choice >= fresnel => {
+                    if refracted.z >= 0.0 {
+                        return None;
+                    }
+                    // Radiance is squeezed into a smaller solid angle on the denser side
+                    (refracted, self.tint / (eta * eta), 1.0 - fresnel)
+                }
+                _ => {
+                    let reflected = reflect(outgoing, half);
+                    if reflected.z <= 0.0 {
+                        return None;
+                    }
+                    (reflected, Color::one(), fresnel)
+                }
+            };
+
+            if smooth {
+                return Some(LocalSample {
+                    incoming,
+                    color,
+                    pdf: None,
+                });
+            }
+
+            let weight = self.distribution.masking_shadowing(outgoing, incoming)
+                / self.distribution.masking(outgoing);
+            let visible_normal_pdf = self.distribution.visible_normal_pdf(outgoing, half);
+            let jacobian = if incoming.z > 0.0 {
+                1.0 / (4.0 * cos_half)
+            } else {
+                let denominator = incoming.dot(half) + cos_half / eta;
+                incoming.dot(half).abs() / (denominator * denominator)
+            };
+
+            Some(LocalSample {
+                incoming,
+                color: color * weight,
+                pdf: Some(choice_pdf * visible_normal_pdf * jacobian),
+            })
+        }
+        pub fn evaluate(
+            &self,
+            ray: &Ray,
+            collision: &RayCollision,
+            direction: Vec3A,
+        ) -> Option<MaterialEvaluation> {
+            let frame = ShadingFrame::new(collision);
+            self.evaluate_local(
+                frame.to_local(-ray.direction.normalize()),
+                frame.to_local(direction.normalize()),
+                self.eta(collision),
+            )
+        }
+        fn evaluate_local(
+            &self,
+            outgoing: Vec3A,
+            incoming: Vec3A,
+            eta: f32,
+        ) -> Option<MaterialEvaluation> {
+            if self.distribution.is_smooth() || outgoing.z <= 0.0 || incoming.z == 0.0 {
+                return None;
+            }
+
+            let reflecting = incoming.z > 0.0;
+
+            let half = if reflecting {
+                outgoing + incoming
+            } else {
+                outgoing + incoming * eta
+            };
+            if half.length_squared() == 0.0 {
+                return None;
+            }
+            let half = half.normalize() * half.z.signum();
+
+            let cos_half = outgoing.dot(half);
+            let incoming_cos_half = incoming.dot(half);
+            // Microfacets facing away from either direction can't connect them
+            if cos_half <= 0.0 || (incoming_cos_half > 0.0) != reflecting {
+                return None;
+            }
+
+            let fresnel = fresnel_dielectric(cos_half, eta);
+            let distribution = self.distribution.distribution(half);
+            let shadowing = self.distribution.masking_shadowing(outgoing, incoming);
+            let visible_normal_pdf = self.distribution.visible_normal_pdf(outgoing, half);
+            let cos_incoming = incoming.z.abs();
+
+            if reflecting {
+                let specular = fresnel * distribution * shadowing / (4.0 * outgoing.z * incoming.z);
+                return Some(MaterialEvaluation {
+                    color: Color::one() * (specular * cos_incoming),
+                    pdf: fresnel * visible_normal_pdf / (4.0 * cos_half),
+                });
+            }
+
+            let denominator = incoming_cos_half + cos_half / eta;
+            let denominator = denominator * denominator;
+            let transmission =
+                (1.0 - fresnel) * distribution * shadowing * (incoming_cos_half * cos_half).abs()
+                    / (cos_incoming * outgoing.z * denominator)
+                    / (eta * eta);
+
+            Some(MaterialEvaluation {
+                color: self.tint * (transmission * cos_incoming),
+                pdf: (1.0 - fresnel) * visible_normal_pdf * incoming_cos_half.abs() / denominator,
+            })
+        }
+    }
+
+    // Below this the specular lobes would turn into mirrors, which can't be mixed with the others
+    const PRINCIPLED_MIN_ROUGHNESS: f32 = 0.05;
+
+    // One material covering most surfaces, loosely following Disney's principled BSDF. Every
+    // parameter is a texture so it can vary over the surface, the scalar ones use the average of
+    // the texture's channels.
+    pub struct Principled {
+        pub base_color: Arc<Texture>,
+        // 0 for dielectrics, 1 for metals tinted by the base colour
+        pub metallic: Arc<Texture>,
+        pub roughness: Arc<Texture>,
+        // Strength of the dielectric highlight, 0.5 matches the index of refraction exactly
+        pub specular: Arc<Texture>,
+        // Soft white rim at grazing angles, for cloth
+        pub sheen: Arc<Texture>,
+        // Strength of a clear varnish layer over everything else
+        pub clearcoat: Arc<Texture>,
+        pub clearcoat_roughness: Arc<Texture>,
+        // How much of the dielectric base is glass rather than diffuse
+        pub transmission: Arc<Texture>,
+        pub ior: Arc<Texture>,
+    }
+
+    // The parameters looked up at one hit, with the lobes they make
+    struct PrincipledLobes {
+        base_color: Color,
+        roughness: f32,
+        diffuse_weight: f32,
+        sheen: f32,
+        // Reflectance head on of the dielectric and metallic highlights, already weighted
+        dielectric_f0: f32,
+        dielectric_weight: f32,
+        metallic_weight: f32,
+        specular: Ggx,
+        clearcoat: f32,
+        clearcoat_distribution: Ggx,
+        glass_weight: f32,
+        glass: RoughDielectric,
+        eta: f32,
+        // Chance of sampling the diffuse, specular, glass and clearcoat lobes
+        probabilities: [f32; 4],
+    }
+
+    // A GGX reflection lobe's value times the cosine, leaving out Fresnel, and its pdf
+    fn evaluate_specular(
+        distribution: &Ggx,
+        outgoing: Vec3A,
+        incoming: Vec3A,
+        half: Vec3A,
+    ) -> (f32, f32) {
+        let cos_half = outgoing.dot(half);
+        if cos_half <= 0.0 {
+            return (0.0, 0.0);
+        }
+
+        let specular = distribution.distribution(half)
+            * distribution.masking_shadowing(outgoing, incoming)
+            / (4.0 * outgoing.z);
+        let pdf = distribution.visible_normal_pdf(outgoing, half) / (4.0 * cos_half);
+        (specular, pdf)
+    }
+
+    impl Principled {
+        pub fn new(base_color: Arc<Texture>) -> Principled {
+            Principled {
+                base_color,
+                metallic: constant_texture(0.0),
+                roughness: constant_texture(0.5),
+                specular: constant_texture(0.5),
+                sheen: constant_texture(0.0),
+                clearcoat: constant_texture(0.0),
+                clearcoat_roughness: constant_texture(0.1),
+                transmission: constant_texture(0.0),
+                ior: constant_texture(1.5),
+            }
+        }
+        fn lobes(&self, collision: &RayCollision) -> PrincipledLobes {
+            let uv = collision.uv();
+            let point = collision.point();
+            let scalar = |texture: &Arc<Texture>| texture.scalar(uv, point).clamp(0.0, 1.0);
+
+            let base_color = self.base_color.value(uv, point);
+            let metallic = scalar(&self.metallic);
+            let roughness = scalar(&self.roughness).max(PRINCIPLED_MIN_ROUGHNESS);
+            let clearcoat_roughness =
+                scalar(&self.clearcoat_roughness).max(PRINCIPLED_MIN_ROUGHNESS);
+            let transmission = scalar(&self.transmission);
+            let ior = self.ior.scalar(uv, point).max(1.0 + 1.0e-3);
+
+            let diffuse_weight = (1.0 - metallic) * (1.0 - transmission);
+            let glass_weight = (1.0 - metallic) * transmission;
+            let clearcoat = 0.25 * scalar(&self.clearcoat);
+
+            // Inside something glassy only the glass lobe can have been what let the ray in
+            let inside = !collision.on_front_face() && glass_weight > 0.0;
+            let probabilities = if inside {
+                [0.0, 0.0, 1.0, 0.0]
+            } else {
+                let weights = [
+                    diffuse_weight,
+                    metallic + 0.25 * diffuse_weight,
+                    glass_weight,
+                    clearcoat,
+                ];
+                let total: f32 = weights.iter().sum();
+                weights.map(|weight| weight / total)
+            };
+
+            let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+            PrincipledLobes {
+                base_color,
+                roughness,
+                diffuse_weight,
+                sheen: scalar(&self.sheen),
+                dielectric_f0: (f0 * 2.0 * scalar(&self.specular)).min(1.0),
+                dielectric_weight: diffuse_weight,
+                metallic_weight: metallic,
+                specular: Ggx::new(roughness, roughness),
+                clearcoat,
+                clearcoat_distribution: Ggx::new(clearcoat_roughness, clearcoat_roughness),
+                glass_weight,
+                glass: RoughDielectric::new(ior, base_color, roughness, roughness),
+                eta: if collision.on_front_face() {
+                    ior
+                } else {
+                    1.0 / ior
+                },
+                probabilities,
+            }
+        }
+        pub fn scatter(
+            &self,
+            ray: &Ray,
+            collision: &RayCollision,
+            sampler: &mut Sampler,
+        ) -> Option<MaterialCollisionResult> {
+            let lobes = self.lobes(collision);
+            let frame = ShadingFrame::new(collision);
+            let outgoing = frame.to_local(-ray.direction.normalize());
+            if outgoing.z <= 0.0 {
+                return None;
+            }
+
+            // The number picking the lobe is stretched back over 0 to 1 so the glass lobe can
+            // reuse it to choose between reflecting and refracting
+            let mut choice = sampler.get_1d();
+            let mut lobe = 0;
+            while lobe < 3 && choice >= lobes.probabilities[lobe] {
+                choice -= lobes.probabilities[lobe];
+                lobe += 1;
+            }
+            let choice = (choice / lobes.probabilities[lobe]).min(1.0 - f32::EPSILON);
+            let u = sampler.get_2d();
+
+            let incoming = match lobe {
+                0 => {
+                    let direction = Vec3A::new(0.0, 0.0, 1.0) + sample_unit_sphere_surface(u);
+                    if is_vec3_af32_near_zero(&direction) {
+                        return None;
+                    }
+                    direction.normalize()
+                }
+                1 => reflect(outgoing, lobes.specular.sample_visible_normal(outgoing, u)),
+                2 => {
+                    lobes
+                        .glass
+                        .sample_local(outgoing, lobes.eta, u, choice)?
+                        .incoming
+                }
+                _ => reflect(
+                    outgoing,
+                    lobes
+                        .clearcoat_distribution
+                        .sample_visible_normal(outgoing, u),
+                ),
+            };
+
+            // Every lobe's chance of picking the same direction is counted, so lobes with
+            // overlapping directions don't add noise
+            let evaluation = self.evaluate_local(&lobes, outgoing, incoming)?;
+            if evaluation.pdf <= 0.0 {
+                return None;
+            }
+
+            Some(MaterialCollisionResult {
+                color: evaluation.color / evaluation.pdf,
+                ray: Ray::new(collision.point(), frame.to_world(incoming), ray.time),
+                pdf: Some(evaluation.pdf),
+            })
+        }
+        pub fn evaluate(
+            &self,
+            ray: &Ray,
+            collision: &RayCollision,
+            direction: Vec3A,
+        ) -> Option<MaterialEvaluation> {
+            let lobes = self.lobes(collision);
+            let frame = ShadingFrame::new(collision);
+            self.evaluate_local(
+                &lobes,
+                frame.to_local(-ray.direction.normalize()),
+                frame.to_local(direction.normalize()),
+            )
+        }
+        fn evaluate_local(
+            &self,
+            lobes: &PrincipledLobes,
+            outgoing: Vec3A,
+            incoming: Vec3A,
+        ) -> Option<MaterialEvaluation> {
+            if outgoing.z <= 0.0 || incoming.z == 0.0 {
+                return None;
+            }
+
+            let [diffuse_chance, specular_chance, glass_chance, clearcoat_chance] =
+                lobes.probabilities;
+            let mut color = Color::zero();
+            let mut pdf = 0.0;
+
+            if lobes.glass_weight > 0.0 && glass_chance > 0.0 {
+                if let Some(glass) = lobes.glass.evaluate_local(outgoing, incoming, lobes.eta) {
+                    color += glass.color * lobes.glass_weight;
+                    pdf += glass_chance * glass.pdf;
+                }
+            }
+
+            // Everything else only reflects, and only from outside
+            if incoming.z > 0.0 && glass_chance < 1.0 {
+                let half = (outgoing + incoming).normalize();
+                let cos_half = outgoing.dot(half);
+
+                // Burley's diffuse, darker at grazing angles on smooth surfaces and brighter on
+                // rough ones, with sheen rising towards the rims
+                let schlick_weight = |cos: f32| (1.0 - cos.clamp(0.0, 1.0)).powi(5);
+                let retro_reflection = 0.5 + 2.0 * lobes.roughness * cos_half * cos_half;
+                let diffuse = (1.0 + (retro_reflection - 1.0) * schlick_weight(outgoing.z))
+                    * (1.0 + (retro_reflection - 1.0) * schlick_weight(incoming.z))
+                    / PI;
+                let sheen = lobes.sheen * schlick_weight(cos_half);
+                color += (lobes.base_color * diffuse + Color::one() * sheen)
+                    * (lobes.diffuse_weight * incoming.z);
+                pdf += diffuse_chance * incoming.z / PI;
+
+                let (specular, specular_pdf) =
+                    evaluate_specular(&lobes.specular, outgoing, incoming, half);
+                let fresnel = fresnel_schlick(cos_half, Color::one() * lobes.dielectric_f0)
+                    * lobes.dielectric_weight
+                    + fresnel_schlick(cos_half, lobes.base_color) * lobes.metallic_weight;
+                color += fresnel * specular;
+                pdf += specular_chance * specular_pdf;
+
+                if lobes.clearcoat > 0.0 {
+                    let (clearcoat, clearcoat_pdf) =
+                        evaluate_specular(&lobes.clearcoat_distribution, outgoing, incoming, half);
+                    color += fresnel_schlick(cos_half, Color::new(0.04, 0.04, 0.04))
+                        * (lobes.clearcoat * clearcoat);
+                    pdf += clearcoat_chance * clearcoat_pdf;
+                }
+            }
+
+            if pdf <= 0.0 {
+                return None;
+            }
+
+            Some(MaterialEvaluation { color, pdf })
+        }
+    }
+
+    #[allow(clippy::enum_variant_names)]
     pub enum Material {
         MaterialDielectric(Dielectric),
         MaterialLambertian(Lambertian),
         MaterialMetal(Metal),
+        MaterialEmissive(Emissive),
+        MaterialConductor(Conductor),
+        MaterialRoughDielectric(RoughDielectric),
+        MaterialPrincipled(Principled),
     }
 
     impl Material {
-        pub fn scatter(&self, ray: &Ray, collision: &RayCollision) -> Option<MaterialCollisionResult> {
+        pub fn scatter(
+            &self,
+            ray: &Ray,
+            collision: &RayCollision,
+            sampler: &mut Sampler,
+        ) -> Option<MaterialCollisionResult> {
+            match self {
+                Material::MaterialDielectric(dielectric) => {
+                    dielectric.scatter(ray, collision, sampler)
+                }
+                Material::MaterialLambertian(lambertian) => {
+                    lambertian.scatter(ray, collision, sampler)
+                }
+                Material::MaterialMetal(metal) => metal.scatter(ray, collision, sampler),
+                Material::MaterialConductor(conductor) => {
+                    conductor.scatter(ray, collision, sampler)
+                }
+                Material::MaterialRoughDielectric(dielectric) => {
+                    dielectric.scatter(ray, collision, sampler)
+                }
+                Material::MaterialPrincipled(principled) => {
+                    principled.scatter(ray, collision, sampler)
+                }
+                Material::MaterialEmissive(_) => None,
+            }
+        }
+        pub fn evaluate(
+            &self,
+            ray: &Ray,
+            collision: &RayCollision,
+            direction: Vec3A,
+        ) -> Option<MaterialEvaluation> {
+            match self {
+                Material::MaterialLambertian(lambertian) => lambertian.evaluate(collision, direction),
+                Material::MaterialConductor(conductor) => {
+                    conductor.evaluate(ray, collision, direction)
+                }
+                Material::MaterialRoughDielectric(dielectric) => {
+                    dielectric.evaluate(ray, collision, direction)
+                }
+                Material::MaterialPrincipled(principled) => {
+                    principled.evaluate(ray, collision, direction)
+                }
+                _ => None,
+            }
+        }
+        pub fn is_emissive(&self) -> bool {
+            matches!(self, Material::MaterialEmissive(_))
+        }
+        pub fn emitted(&self, ray: &Ray, collision: &RayCollision) -> Color {
             match self {
-                Material::MaterialDielectric(dielectric) => dielectric.scatter(ray, collision),
-                Material::MaterialLambertian(lambertian) => lambertian.scatter(ray, collision),
-                Material::MaterialMetal(metal) => metal.scatter(ray, collision),
+                Material::MaterialEmissive(emissive) => emissive.emitted(ray, collision),
+                _ => Color::zero(),
             }
         }
     }
@@ -1,14 +1,71 @@
-use glam::Vec3A;
-use rand::Rng;
+use glam::{Vec2, Vec3A};
 
-pub fn random_f32_between(a: f32, b: f32) -> f32 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(a..=b)
+// SplitMix64 finaliser, turns a seed and a stream index into an unrelated seed
+pub fn mix_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
 }
 
-pub fn random_usize_between(a: usize, b: usize) -> usize {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(a..=b)
+// Two unit vectors perpendicular to `normal` and each other
+pub fn orthonormal_basis(normal: Vec3A) -> (Vec3A, Vec3A) {
+    let helper = if normal.x.abs() > 0.9 {
+        Vec3A::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3A::new(1.0, 0.0, 0.0)
+    };
+    let tangent = normal.cross(helper).normalize();
+    let bitangent = normal.cross(tangent);
+    (tangent, bitangent)
+}
+
+// The functions taking `u` map uniform samples from a `Sampler` onto a shape, so well spread out
+// samples stay well spread out
+
+pub fn sample_direction_in_cone(axis: Vec3A, cos_theta_max: f32, u: Vec2) -> Vec3A {
+    let cos_theta = 1.0 - u.x * (1.0 - cos_theta_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u.y;
+    let (tangent, bitangent) = orthonormal_basis(axis);
+
+    (tangent * phi.cos() * sin_theta + bitangent * phi.sin() * sin_theta + axis * cos_theta)
+        .normalize()
+}
+
+pub fn sample_barycentric(u: Vec2) -> Vec3A {
+    let sqrt_u = u.x.sqrt();
+    Vec3A::new(1.0 - sqrt_u, sqrt_u * (1.0 - u.y), sqrt_u * u.y)
+}
+
+pub fn sample_unit_sphere_surface(u: Vec2) -> Vec3A {
+    let z = 1.0 - 2.0 * u.x;
+    let radius = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u.y;
+    Vec3A::new(radius * phi.cos(), radius * phi.sin(), z)
+}
+
+// Uniform inside the unit sphere, `radius` picks how far out
+pub fn sample_unit_sphere(u: Vec2, radius: f32) -> Vec3A {
+    sample_unit_sphere_surface(u) * radius.cbrt()
+}
+
+// Shirley and Chiu's concentric mapping, which keeps the square's strata intact on the disk
+pub fn sample_unit_disk(u: Vec2) -> Vec2 {
+    let offset = 2.0 * u - Vec2::ONE;
+
+    if offset.x == 0.0 && offset.y == 0.0 {
+        return Vec2::ZERO;
+    }
+
+    let quarter_pi = std::f32::consts::FRAC_PI_4;
+    let (radius, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, quarter_pi * (offset.y / offset.x))
+    } else {
+        (offset.y, 2.0 * quarter_pi - quarter_pi * (offset.x / offset.y))
+    };
+
+    radius * Vec2::new(theta.cos(), theta.sin())
 }
 
 pub fn is_vec3_af32_near_zero(vec: &Vec3A) -> bool {
@@ -16,56 +73,222 @@ pub fn is_vec3_af32_near_zero(vec: &Vec3A) -> bool {
     vec.x.abs() < EPS && vec.y.abs() < EPS && vec.z.abs() < EPS
 }
 
-pub fn random_point_in_unit_sphere() -> Vec3A {
-    let mut rng = rand::thread_rng();
+// Real roots of a x^2 + b x + c in ascending order, in double precision since curved shapes lose
+// a lot of it squaring distances
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return Vec::new();
+        }
+        return vec![-c / b];
+    }
 
-    loop {
-        let point = Vec3A::new(
-            rng.gen_range(-1.0..1.0),
-            rng.gen_range(-1.0..1.0),
-            rng.gen_range(-1.0..1.0),
-        );
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return Vec::new();
+    }
 
-        if point.length_squared() < 1.0 {
-            return point;
-        }
+    // Avoids subtracting two nearly equal numbers for the smaller root
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let mut roots = if q == 0.0 {
+        vec![0.0, 0.0]
+    } else {
+        vec![q / a, c / q]
+    };
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+// The largest real root of x^3 + a x^2 + b x + c, which always exists
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    let q = (a * a - 3.0 * b) / 9.0;
+    let r = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+
+    if r * r < q * q * q {
+        // Three real roots, found with Viete's trigonometric method. With theta in [0, pi] the
+        // cosine of (theta + 2 pi) / 3 is the most negative of the three, giving the largest root.
+        let theta = (r / (q * q * q).sqrt()).clamp(-1.0, 1.0).acos();
+        return -2.0 * q.sqrt() * ((theta + 2.0 * std::f64::consts::PI) / 3.0).cos() - a / 3.0;
     }
+
+    let s = -r.signum() * (r.abs() + (r * r - q * q * q).sqrt()).cbrt();
+    let t = if s == 0.0 { 0.0 } else { q / s };
+    s + t - a / 3.0
 }
 
-#[allow(dead_code)]
-pub fn random_point_on_unit_sphere() -> Vec3A {
-    let mut rng = rand::thread_rng();
+// Real roots of c[4] x^4 + c[3] x^3 + c[2] x^2 + c[1] x + c[0] in ascending order, by Ferrari's
+// method with each root polished by Newton's method
+pub fn solve_quartic(coefficients: [f64; 5]) -> Vec<f64> {
+    let [c0, c1, c2, c3, c4] = coefficients;
+    if c4 == 0.0 {
+        return Vec::new();
+    }
 
-    loop {
-        let point = Vec3A::new(
-            rng.gen_range(-1.0..1.0),
-            rng.gen_range(-1.0..1.0),
-            rng.gen_range(-1.0..1.0),
-        );
+    let (a, b, c, d) = (c3 / c4, c2 / c4, c1 / c4, c0 / c4);
+
+    // Substituting x = y - a / 4 leaves y^4 + p y^2 + q y + r
+    let a2 = a * a;
+    let p = b - 3.0 * a2 / 8.0;
+    let q = c - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+
+    let mut roots = Vec::new();
+
+    if q.abs() < 1.0e-12 {
+        // Quadratic in y^2
+        for z in solve_quadratic(1.0, p, r) {
+            if z >= 0.0 {
+                roots.push(z.sqrt());
+                roots.push(-z.sqrt());
+            }
+        }
+    } else {
+        // Completing the square needs a positive root m of the resolvent cubic, then the quartic
+        // splits into y^2 -+ sqrt(2m) y + p / 2 + m +- q / (2 sqrt(2m))
+        let m = largest_cubic_root(p, p * p / 4.0 - r, -q * q / 8.0);
+        if m <= 0.0 {
+            return Vec::new();
+        }
 
-        if point.length_squared() < 1.0 {
-            return point.normalize();
+        let root_2m = (2.0 * m).sqrt();
+        for sign in [1.0, -1.0] {
+            roots.extend(solve_quadratic(
+                1.0,
+                -sign * root_2m,
+                p / 2.0 + m + sign * q / (2.0 * root_2m),
+            ));
         }
     }
+
+    let value = |x: f64| (((c4 * x + c3) * x + c2) * x + c1) * x + c0;
+    let slope = |x: f64| ((4.0 * c4 * x + 3.0 * c3) * x + 2.0 * c2) * x + c1;
+
+    let mut roots: Vec<f64> = roots
+        .into_iter()
+        .map(|y| {
+            let mut x = y - a / 4.0;
+            for _ in 0..2 {
+                let derivative = slope(x);
+                if derivative == 0.0 {
+                    break;
+                }
+                x -= value(x) / derivative;
+            }
+            x
+        })
+        .collect();
+    roots.sort_by(f64::total_cmp);
+    roots
 }
 
-#[allow(dead_code)]
-pub fn random_point_in_unit_hemisphere(normal: Vec3A) -> Vec3A {
-    let mut in_unit_sphere = random_point_in_unit_sphere();
-    if in_unit_sphere.dot(normal) <= 0.0 {
-        in_unit_sphere *= -1.0;
+#[cfg(test)]
+mod tests {
+    use super::{largest_cubic_root, solve_quadratic, solve_quartic};
+
+    fn assert_roots(roots: Vec<f64>, expected: &[f64]) {
+        assert_eq!(
+            roots.len(),
+            expected.len(),
+            "found roots {:?}, expected {:?}",
+            roots,
+            expected
+        );
+        for (root, expected_root) in roots.iter().zip(expected) {
+            assert!(
+                (root - expected_root).abs() < 1.0e-9,
+                "found roots {:?}, expected {:?}",
+                roots,
+                expected
+            );
+        }
     }
-    in_unit_sphere
-}
 
-pub fn random_point_in_unit_disk() -> Vec3A {
-    let mut rng = rand::thread_rng();
+    #[test]
+    fn quadratic_with_two_roots() {
+        // (x + 2)(x - 3)
+        assert_roots(solve_quadratic(1.0, -1.0, -6.0), &[-2.0, 3.0]);
+        // 2 (x - 0.5)(x - 4)
+        assert_roots(solve_quadratic(2.0, -9.0, 4.0), &[0.5, 4.0]);
+    }
 
-    loop {
-        let point = Vec3A::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
+    #[test]
+    fn quadratic_with_a_double_root() {
+        assert_roots(solve_quadratic(1.0, -2.0, 1.0), &[1.0, 1.0]);
+    }
 
-        if point.length_squared() < 1.0 {
-            return point;
-        }
+    #[test]
+    fn quadratic_without_real_roots() {
+        assert_roots(solve_quadratic(1.0, 0.0, 1.0), &[]);
+    }
+
+    #[test]
+    fn quadratic_falls_back_to_linear() {
+        assert_roots(solve_quadratic(0.0, 2.0, -4.0), &[2.0]);
+        assert_roots(solve_quadratic(0.0, 0.0, 1.0), &[]);
+    }
+
+    #[test]
+    fn largest_cubic_root_of_three() {
+        // (x - 1)(x - 2)(x - 3)
+        assert_roots(vec![largest_cubic_root(-6.0, 11.0, -6.0)], &[3.0]);
+        // (x + 4)(x + 1)(x - 0.5)
+        assert_roots(vec![largest_cubic_root(4.5, 1.5, -2.0)], &[0.5]);
+    }
+
+    #[test]
+    fn largest_cubic_root_of_one() {
+        // (x - 2)(x^2 + 1)
+        assert_roots(vec![largest_cubic_root(-2.0, 1.0, -2.0)], &[2.0]);
+    }
+
+    #[test]
+    fn quartic_with_four_roots() {
+        // (x + 2)(x + 1)(x - 0.5)(x - 3)
+        assert_roots(
+            solve_quartic([3.0, -2.5, -7.0, -0.5, 1.0]),
+            &[-2.0, -1.0, 0.5, 3.0],
+        );
+    }
+
+    #[test]
+    fn quartic_with_two_roots() {
+        // (x - 1)(x - 2)(x^2 + 1)
+        assert_roots(solve_quartic([2.0, -3.0, 3.0, -3.0, 1.0]), &[1.0, 2.0]);
+    }
+
+    #[test]
+    fn quartic_without_real_roots() {
+        // (x^2 + 1)(x^2 + 2)
+        assert_roots(solve_quartic([2.0, 0.0, 3.0, 0.0, 1.0]), &[]);
+    }
+
+    #[test]
+    fn quartic_without_odd_terms() {
+        // 2 (x^2 - 1)(x^2 - 4)
+        assert_roots(
+            solve_quartic([8.0, 0.0, -10.0, 0.0, 2.0]),
+            &[-2.0, -1.0, 1.0, 2.0],
+        );
+    }
+
+    #[test]
+    fn quartic_through_a_torus() {
+        // A ray along x at height y through a torus around z with major radius 1 and minor
+        // radius 0.25, written the way the torus collider sets it up
+        let (y, z) = (0.24_f64, 1.0e-4_f64);
+        let (major2, minor2) = (1.0_f64, 0.0625_f64);
+        let offset = y * y + z * z - major2 - minor2;
+        let roots = solve_quartic([
+            offset * offset + 4.0 * major2 * (z * z - minor2),
+            0.0,
+            2.0 * offset,
+            0.0,
+            1.0,
+        ]);
+
+        let tube = (minor2 - z * z).sqrt();
+        let outer = ((1.0 + tube).powi(2) - y * y).sqrt();
+        let inner = ((1.0 - tube).powi(2) - y * y).sqrt();
+        assert_roots(roots, &[-outer, -inner, inner, outer]);
     }
 }
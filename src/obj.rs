@@ -0,0 +1,657 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use glam::{Vec2, Vec3A};
+
+use crate::{
+    color::Color,
+    material::materials::{
+        Dielectric, Emissive, Lambertian,
+        Material::{self, MaterialDielectric, MaterialEmissive, MaterialLambertian, MaterialMetal},
+        Metal,
+    },
+    shape::{Mesh, MeshFace, MeshVertex},
+};
+
+const DEFAULT_REFRACTION_INDEX: f32 = 1.5;
+const MAX_SPECULAR_EXPONENT: f32 = 1000.0;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    IndexOutOfRange {
+        path: PathBuf,
+        line: usize,
+        kind: &'static str,
+        index: i64,
+        count: usize,
+    },
+    UnsupportedDirective {
+        path: PathBuf,
+        line: usize,
+        directive: String,
+    },
+    UnknownMaterial {
+        path: PathBuf,
+        line: usize,
+        name: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, error } => {
+                write!(f, "could not read {}: {}", path.display(), error)
+            }
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::IndexOutOfRange {
+                path,
+                line,
+                kind,
+                index,
+                count,
+            } => write!(
+                f,
+                "{}:{}: {} index {} is out of range, there are {} {}s defined",
+                path.display(),
+                line,
+                kind,
+                index,
+                count,
+                kind
+            ),
+            ObjError::UnsupportedDirective {
+                path,
+                line,
+                directive,
+            } => write!(
+                f,
+                "{}:{}: unsupported directive '{}'",
+                path.display(),
+                line,
+                directive
+            ),
+            ObjError::UnknownMaterial { path, line, name } => write!(
+                f,
+                "{}:{}: unknown material '{}'",
+                path.display(),
+                line,
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+pub struct ObjGroup {
+    pub name: String,
+    pub faces: Range<usize>,
+}
+
+pub struct ObjModel {
+    pub mesh: Arc<Mesh>,
+    pub groups: Vec<ObjGroup>,
+}
+
+struct LineReader<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl<'a> LineReader<'a> {
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message,
+        }
+    }
+    fn unsupported(&self, directive: &str) -> ObjError {
+        ObjError::UnsupportedDirective {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            directive: String::from(directive),
+        }
+    }
+    fn f32(&self, value: Option<&str>) -> Result<f32, ObjError> {
+        let value = value.ok_or_else(|| self.error(String::from("expected a number")))?;
+        value
+            .parse::<f32>()
+            .map_err(|_| self.error(format!("expected a number, found '{}'", value)))
+    }
+    fn optional_f32(&self, value: Option<&str>, default: f32) -> Result<f32, ObjError> {
+        match value {
+            Some(_) => self.f32(value),
+            None => Ok(default),
+        }
+    }
+    fn name(&self, value: Option<&str>, directive: &str) -> Result<String, ObjError> {
+        value
+            .map(String::from)
+            .ok_or_else(|| self.error(format!("'{}' is missing a name", directive)))
+    }
+    fn color<'b>(&self, mut values: impl Iterator<Item = &'b str>) -> Result<Color, ObjError> {
+        let r = self.f32(values.next())?;
+        let g = self.optional_f32(values.next(), r)?;
+        let b = self.optional_f32(values.next(), r)?;
+        Ok(Color::new(r, g, b))
+    }
+    // OBJ indices start at 1, negative indices count back from the most recently defined element
+    fn index(&self, value: &str, kind: &'static str, count: usize) -> Result<u32, ObjError> {
+        let index = value
+            .parse::<i64>()
+            .map_err(|_| self.error(format!("expected a {} index, found '{}'", kind, value)))?;
+
+        let resolved = match index.cmp(&0) {
+            std::cmp::Ordering::Greater => index - 1,
+            std::cmp::Ordering::Less => count as i64 + index,
+            std::cmp::Ordering::Equal => -1,
+        };
+
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(ObjError::IndexOutOfRange {
+                path: self.path.to_path_buf(),
+                line: self.line,
+                kind,
+                index,
+                count,
+            });
+        }
+
+        Ok(resolved as u32)
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    std::fs::read_to_string(path).map_err(|error| ObjError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(comment_start) => &line[..comment_start],
+        None => line,
+    }
+}
+
+#[derive(Default)]
+struct MtlMaterial {
+    diffuse: Option<Color>,
+    specular: Option<Color>,
+    emission: Option<Color>,
+    specular_exponent: Option<f32>,
+    refraction_index: Option<f32>,
+    transmission_filter: Option<Color>,
+    dissolve: Option<f32>,
+    illumination_model: Option<u32>,
+}
+
+impl MtlMaterial {
+    // MTL describes Phong style materials, these are mapped onto the closest material we support
+    fn to_material(&self) -> Material {
+        let diffuse = self.diffuse.unwrap_or_else(|| Color::new(0.8, 0.8, 0.8));
+        let specular = self.specular.unwrap_or_else(Color::zero);
+        let is_transparent = self.dissolve.is_some_and(|dissolve| dissolve < 1.0);
+        let is_glass = matches!(self.illumination_model, Some(4 | 6 | 7 | 9)) || is_transparent;
+        let is_mirror = matches!(self.illumination_model, Some(3 | 5 | 8))
+            || (specular.max_component() > 0.0 && diffuse.max_component() == 0.0);
+
+        if let Some(emission) = self
+            .emission
+            .filter(|emission| emission.max_component() > 0.0)
+        {
+            MaterialEmissive(Emissive::new(emission, 1.0))
+        } else if is_glass {
+            let transparency = self
+                .transmission_filter
+                .map_or(1.0, |filter| (filter.r() + filter.g() + filter.b()) / 3.0);
+            MaterialDielectric(Dielectric::new(
+                self.refraction_index.unwrap_or(DEFAULT_REFRACTION_INDEX),
+                transparency,
+            ))
+        } else if is_mirror {
+            let smoothness = self.specular_exponent.map_or(1.0, |exponent| {
+                (exponent / MAX_SPECULAR_EXPONENT).clamp(0.0, 1.0)
+            });
+            let albedo = if specular.max_component() > 0.0 {
+                specular
+            } else {
+                diffuse
+            };
+            MaterialMetal(Metal::new(albedo, smoothness))
+        } else {
+            MaterialLambertian(Lambertian::new(diffuse))
+        }
+    }
+}
+
+pub fn load_mtl_file(path: &Path) -> Result<HashMap<String, Arc<Material>>, ObjError> {
+    parse_mtl(&read_file(path)?, path)
+}
+
+// `path` is only used in errors
+fn parse_mtl(source: &str, path: &Path) -> Result<HashMap<String, Arc<Material>>, ObjError> {
+    let mut reader = LineReader { path, line: 0 };
+    let mut parsed: Vec<(String, MtlMaterial)> = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        reader.line = index + 1;
+        let mut parts = strip_comment(line).split_whitespace();
+
+        let directive = match parts.next() {
+            Some(directive) => directive,
+            None => continue,
+        };
+
+        if directive == "newmtl" {
+            parsed.push((
+                reader.name(parts.next(), directive)?,
+                MtlMaterial::default(),
+            ));
+            continue;
+        }
+
+        let material = match parsed.last_mut() {
+            Some((_, material)) => material,
+            None => return Err(reader.error(format!("'{}' found before 'newmtl'", directive))),
+        };
+
+        match directive {
+            "Kd" => material.diffuse = Some(reader.color(parts)?),
+            "Ks" => material.specular = Some(reader.color(parts)?),
+            "Ke" => material.emission = Some(reader.color(parts)?),
+            "Tf" => material.transmission_filter = Some(reader.color(parts)?),
+            "Ns" => material.specular_exponent = Some(reader.f32(parts.next())?),
+            "Ni" => material.refraction_index = Some(reader.f32(parts.next())?),
+            "d" => material.dissolve = Some(reader.f32(parts.next())?),
+            "Tr" => material.dissolve = Some(1.0 - reader.f32(parts.next())?),
+            "illum" => {
+                let value = parts.next().unwrap_or_default();
+                material.illumination_model = Some(value.parse::<u32>().map_err(|_| {
+                    reader.error(format!("expected an illumination model, found '{}'", value))
+                })?);
+            }
+            // Ambient colour has no meaning in a path tracer
+            "Ka" => {}
+            _ => return Err(reader.unsupported(directive)),
+        }
+    }
+
+    Ok(parsed
+        .into_iter()
+        .map(|(name, material)| (name, Arc::new(material.to_material())))
+        .collect())
+}
+
+pub fn load_obj_file(path: &Path, default_material: Arc<Material>) -> Result<ObjModel, ObjError> {
+    parse_obj(&read_file(path)?, path, default_material)
+}
+
+// Material libraries are loaded relative to `path`
+fn parse_obj(
+    source: &str,
+    path: &Path,
+    default_material: Arc<Material>,
+) -> Result<ObjModel, ObjError> {
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut reader = LineReader { path, line: 0 };
+
+    let mut positions: Vec<Vec3A> = Vec::new();
+    let mut normals: Vec<Vec3A> = Vec::new();
+    let mut uvs: Vec<Vec2> = Vec::new();
+    let mut faces: Vec<MeshFace> = Vec::new();
+    let mut groups: Vec<ObjGroup> = Vec::new();
+
+    let mut materials: Vec<Arc<Material>> = vec![default_material];
+    let mut material_indices: HashMap<String, u32> = HashMap::new();
+    let mut library: HashMap<String, Arc<Material>> = HashMap::new();
+    let mut current_material: u32 = 0;
+    // A face can belong to several groups at once, `g a b` puts the faces after it in both
+    let mut current_groups: Vec<String> = vec![String::from("default")];
+    let mut group_start = 0;
+
+    for (index, line) in source.lines().enumerate() {
+        reader.line = index + 1;
+        let mut parts = strip_comment(line).split_whitespace();
+
+        let directive = match parts.next() {
+            Some(directive) => directive,
+            None => continue,
+        };
+
+        match directive {
+            "v" => positions.push(Vec3A::new(
+                reader.f32(parts.next())?,
+                reader.f32(parts.next())?,
+                reader.f32(parts.next())?,
+            )),
+            "vn" => normals.push(
+                Vec3A::new(
+                    reader.f32(parts.next())?,
+                    reader.f32(parts.next())?,
+                    reader.f32(parts.next())?,
+                )
+                .normalize_or_zero(),
+            ),
+            "vt" => uvs.push(Vec2::new(
+                reader.f32(parts.next())?,
+                reader.optional_f32(parts.next(), 0.0)?,
+            )),
+            "f" => {
+                let mut vertices: Vec<MeshVertex> = Vec::new();
+
+                for vertex in parts {
+                    let mut indices = vertex.split('/');
+                    let position = reader.index(
+                        indices.next().unwrap_or_default(),
+                        "position",
+                        positions.len(),
+                    )?;
+                    let uv = match indices.next() {
+                        None | Some("") => None,
+                        Some(uv) => Some(reader.index(uv, "texture coordinate", uvs.len())?),
+                    };
+                    let normal = match indices.next() {
+                        None | Some("") => None,
+                        Some(normal) => Some(reader.index(normal, "normal", normals.len())?),
+                    };
+                    vertices.push(MeshVertex::new(position, normal, uv));
+                }
+
+                if vertices.len() < 3 {
+                    return Err(reader.error(String::from("faces need at least three vertices")));
+                }
+
+                // Polygons are triangulated as a fan around their first vertex
+                for fan_index in 1..(vertices.len() - 1) {
+                    faces.push(MeshFace {
+                        vertices: [vertices[0], vertices[fan_index], vertices[fan_index + 1]],
+                        material: current_material,
+                    });
+                }
+            }
+            "g" | "o" => {
+                if faces.len() > group_start {
+                    for name in current_groups {
+                        groups.push(ObjGroup {
+                            name,
+                            faces: group_start..faces.len(),
+                        });
+                    }
+                }
+                current_groups = if directive == "g" {
+                    parts.map(String::from).collect()
+                } else {
+                    vec![parts.collect::<Vec<&str>>().join(" ")]
+                };
+                if current_groups.is_empty() {
+                    current_groups.push(String::from("default"));
+                }
+                group_start = faces.len();
+            }
+            "mtllib" => {
+                for file_name in parts {
+                    library.extend(load_mtl_file(&directory.join(file_name))?);
+                }
+            }
+            "usemtl" => {
+                let name = reader.name(parts.next(), directive)?;
+                current_material = match material_indices.get(&name) {
+                    Some(material_index) => *material_index,
+                    None => {
+                        let material = library.get(&name).cloned().ok_or_else(|| {
+                            ObjError::UnknownMaterial {
+                                path: path.to_path_buf(),
+                                line: reader.line,
+                                name: name.clone(),
+                            }
+                        })?;
+                        materials.push(material);
+                        let material_index = (materials.len() - 1) as u32;
+                        material_indices.insert(name, material_index);
+                        material_index
+                    }
+                };
+            }
+            // Smoothing groups only matter when generating normals, which we don't do
+            "s" => {}
+            _ => return Err(reader.unsupported(directive)),
+        }
+    }
+
+    if faces.len() > group_start {
+        for name in current_groups {
+            groups.push(ObjGroup {
+                name,
+                faces: group_start..faces.len(),
+            });
+        }
+    }
+
+    if faces.is_empty() {
+        return Err(ObjError::Parse {
+            path: path.to_path_buf(),
+            line: reader.line,
+            message: String::from("file contains no faces"),
+        });
+    }
+
+    let mut mesh = Mesh::new(positions, normals, uvs, materials);
+    for face in faces {
+        mesh.add_face(face);
+    }
+
+    Ok(ObjModel {
+        mesh: Arc::new(mesh),
+        groups,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use super::{parse_mtl, parse_obj, ObjError, ObjModel};
+    use crate::{
+        color::Color,
+        material::materials::{Lambertian, Material::MaterialLambertian},
+    };
+
+    const TRIANGLE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\n";
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    fn parse(source: &str) -> Result<ObjModel, ObjError> {
+        let material = Arc::new(MaterialLambertian(Lambertian::new(Color::new(
+            0.5, 0.5, 0.5,
+        ))));
+        parse_obj(source, Path::new("test.obj"), material)
+    }
+
+    // A vertex's position, uv and normal indices
+    type Vertex = (u32, Option<u32>, Option<u32>);
+
+    fn faces(source: &str) -> Vec<[Vertex; 3]> {
+        let model = parse(source).unwrap_or_else(|error| panic!("{}", error));
+        model
+            .mesh
+            .faces()
+            .iter()
+            .map(|face| {
+                face.vertices
+                    .map(|vertex| (vertex.position, vertex.uv, vertex.normal))
+            })
+            .collect()
+    }
+
+    fn groups(source: &str) -> Vec<(String, usize, usize)> {
+        let model = parse(source).unwrap_or_else(|error| panic!("{}", error));
+        model
+            .groups
+            .into_iter()
+            .map(|group| (group.name, group.faces.start, group.faces.end))
+            .collect()
+    }
+
+    fn assert_out_of_range(source: &str, expected_kind: &str, expected_index: i64) {
+        match parse(source) {
+            Err(ObjError::IndexOutOfRange {
+                kind, index, line, ..
+            }) => {
+                assert_eq!((kind, index), (expected_kind, expected_index));
+                assert_eq!(line, source.lines().count());
+            }
+            Err(error) => panic!("unexpected error: {}", error),
+            Ok(_) => panic!("parsed:\n{}", source),
+        }
+    }
+
+    #[test]
+    fn positive_and_negative_indices() {
+        let source = format!("{}f 1 2 3\nf -3 -2 -1\n", TRIANGLE);
+        let vertex = |position| (position, None, None);
+        let triangle = [vertex(0), vertex(1), vertex(2)];
+
+        assert_eq!(faces(&source), [triangle, triangle]);
+    }
+
+    #[test]
+    fn indices_out_of_range() {
+        assert_out_of_range(&format!("{}f 0 1 2", TRIANGLE), "position", 0);
+        assert_out_of_range(&format!("{}f 1 2 4", TRIANGLE), "position", 4);
+        assert_out_of_range(&format!("{}f -4 1 2", TRIANGLE), "position", -4);
+        assert_out_of_range(
+            &format!("{}vt 0 0\nf 1/1 2/2 3/1", TRIANGLE),
+            "texture coordinate",
+            2,
+        );
+        assert_out_of_range(&format!("{}f 1//1 2//1 3//1", TRIANGLE), "normal", 1);
+    }
+
+    #[test]
+    fn vertex_forms() {
+        let source = format!(
+            "{}vt 0 0\nvt 1 0\nvn 0 0 1\nf 1/1/1 2/2/1 3/1/1\nf 1//1 2//1 3//1\nf 1/2 2/1 3/2\n",
+            TRIANGLE
+        );
+
+        assert_eq!(
+            faces(&source),
+            [
+                [
+                    (0, Some(0), Some(0)),
+                    (1, Some(1), Some(0)),
+                    (2, Some(0), Some(0))
+                ],
+                [(0, None, Some(0)), (1, None, Some(0)), (2, None, Some(0))],
+                [(0, Some(1), None), (1, Some(0), None), (2, Some(1), None)],
+            ]
+        );
+    }
+
+    #[test]
+    fn polygons_are_fanned() {
+        let vertex = |position| (position, None, None);
+
+        assert_eq!(
+            faces(&format!("{}f 1 2 3 4\n", SQUARE)),
+            [
+                [vertex(0), vertex(1), vertex(2)],
+                [vertex(0), vertex(2), vertex(3)],
+            ]
+        );
+        assert_eq!(
+            faces(&format!("{}v 0.5 2 0\nf 1 2 3 5 4\n", SQUARE)).len(),
+            3
+        );
+    }
+
+    #[test]
+    fn faces_need_three_vertices() {
+        assert!(matches!(
+            parse(&format!("{}f 1 2", TRIANGLE)),
+            Err(ObjError::Parse { line: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn group_ranges() {
+        let source = format!(
+            "{}f 1 2 3\ng a b\nf 1 2 3\nf 1 2 3\no lid top\nf 1 2 3\ng empty\ng c\nf 1 2 3\n",
+            TRIANGLE
+        );
+        let group = |name: &str, start, end| (String::from(name), start, end);
+
+        assert_eq!(
+            groups(&source),
+            [
+                group("default", 0, 1),
+                group("a", 1, 3),
+                group("b", 1, 3),
+                group("lid top", 3, 4),
+                group("c", 4, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_material() {
+        match parse(&format!("{}usemtl brass\nf 1 2 3", TRIANGLE)) {
+            Err(ObjError::UnknownMaterial { name, line, .. }) => {
+                assert_eq!((name.as_str(), line), ("brass", 4));
+            }
+            Err(error) => panic!("unexpected error: {}", error),
+            Ok(_) => panic!("unknown material was accepted"),
+        }
+    }
+
+    #[test]
+    fn unsupported_directives() {
+        match parse(&format!("{}curv 0 1 1 2\n", TRIANGLE)) {
+            Err(ObjError::UnsupportedDirective {
+                directive, line, ..
+            }) => assert_eq!((directive.as_str(), line), ("curv", 4)),
+            Err(error) => panic!("unexpected error: {}", error),
+            Ok(_) => panic!("unsupported directive was accepted"),
+        }
+
+        let source = "newmtl brick\nKd 0.8 0.3 0.2\nmap_Kd brick.png\n";
+        match parse_mtl(source, Path::new("test.mtl")) {
+            Err(ObjError::UnsupportedDirective {
+                directive, line, ..
+            }) => assert_eq!((directive.as_str(), line), ("map_Kd", 3)),
+            Err(error) => panic!("unexpected error: {}", error),
+            Ok(_) => panic!("map_Kd was accepted"),
+        }
+    }
+
+    #[test]
+    fn mtl_materials() {
+        let source = "# Two materials\nnewmtl red\nKd 0.8 0.1 0.1\nKa 1 1 1\n\nnewmtl lamp\nKe 4\n";
+        let library = parse_mtl(source, Path::new("test.mtl")).unwrap();
+
+        assert_eq!(library.len(), 2);
+        assert!(!library["red"].is_emissive());
+        assert!(library["lamp"].is_emissive());
+    }
+}
@@ -1,7 +1,6 @@
 use std::sync::Arc;
 
-use glam::Vec3A;
-use uuid::Uuid;
+use glam::{Affine3A, Mat3A, Vec2, Vec3A};
 
 use crate::material::materials::Material;
 
@@ -9,6 +8,8 @@ pub struct Ray {
     pub origin: Vec3A,
     pub direction: Vec3A,
     pub time: f32,
+    // Computed once here rather than for every bounding box the ray is tested against
+    pub inverse_direction: Vec3A,
 }
 
 impl Ray {
@@ -17,6 +18,7 @@ impl Ray {
             origin,
             direction,
             time,
+            inverse_direction: direction.recip(),
         }
     }
 
@@ -31,7 +33,10 @@ pub struct RayCollision {
     time: f32,
     on_front_face: bool,
     material: Arc<Material>,
-    bvh_node_uuids: Vec<Uuid>,
+    uv: Vec2,
+    // Direction along the surface that anisotropic materials line up with, zero when the shape
+    // doesn't have one
+    tangent: Vec3A,
 }
 
 impl RayCollision {
@@ -53,11 +58,35 @@ impl RayCollision {
             time,
             on_front_face: front_face,
             material: material.clone(),
-            bvh_node_uuids: Vec::new(),
+            uv: Vec2::ZERO,
+            tangent: Vec3A::ZERO,
         }
     }
-    pub fn add_bvh_node_uuid(&mut self, node_uuid: Uuid) {
-        self.bvh_node_uuids.push(node_uuid);
+    pub fn set_uv(&mut self, uv: Vec2) {
+        self.uv = uv;
+    }
+    pub fn set_tangent(&mut self, tangent: Vec3A) {
+        self.tangent = tangent;
+    }
+    // Whether the ray is entering the solid, which for combined shapes can differ from the side of
+    // the surface it hit
+    pub fn set_on_front_face(&mut self, on_front_face: bool) {
+        self.on_front_face = on_front_face;
+    }
+    pub fn set_shading_normal(&mut self, shading_normal: Vec3A) {
+        // Keep the shading normal on the same side as the face-forward geometric normal
+        if shading_normal.dot(self.normal) < 0.0 {
+            self.normal = -shading_normal;
+        } else {
+            self.normal = shading_normal;
+        }
+    }
+    // Moves a collision found in an instance's own space out into the world, `normal_matrix` being
+    // the inverse transpose of the transform so normals stay perpendicular to the surface
+    pub fn transform(&mut self, transform: &Affine3A, normal_matrix: &Mat3A) {
+        self.point = transform.transform_point3a(self.point);
+        self.normal = (*normal_matrix * self.normal).normalize();
+        self.tangent = transform.transform_vector3a(self.tangent);
     }
     pub fn point(&self) -> Vec3A {
         self.point
@@ -74,11 +103,15 @@ impl RayCollision {
     pub fn material(&self) -> &Material {
         self.material.as_ref()
     }
-    pub fn bvh_node_uuids(&self) -> &Vec<Uuid> {
-        &self.bvh_node_uuids
+    pub fn uv(&self) -> Vec2 {
+        self.uv
+    }
+    pub fn tangent(&self) -> Vec3A {
+        self.tangent
     }
 }
 
+#[allow(dead_code)]
 pub trait RayCollider: Send + Sync {
     fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision>;
 }
@@ -90,12 +123,13 @@ pub fn collide_ray_with_sphere(
     t_min: f32,
     t_max: f32,
 ) -> Option<f32> {
-    // If pointing away from sphere ignore
-    if (centre - ray.origin).dot(ray.direction) < 0.0 {
+    let oc = ray.origin - centre;
+
+    // If outside the sphere and pointing away from it ignore
+    if oc.dot(ray.direction) > 0.0 && oc.length_squared() > radius * radius {
         return None;
     }
 
-    let oc = ray.origin - centre;
     let a = ray.direction.length_squared();
     let half_b = oc.dot(ray.direction);
     let c = ray.origin.distance_squared(centre) - radius * radius;
@@ -117,3 +151,136 @@ pub fn collide_ray_with_sphere(
 
     Some(root)
 }
+
+pub struct TriangleHit {
+    pub time: f32,
+    pub barycentric: Vec3A,
+}
+
+fn triangle_edge_functions_f64(
+    a: (f64, f64),
+    b: (f64, f64),
+    c: (f64, f64),
+) -> (f64, f64, f64) {
+    (
+        c.0 * b.1 - c.1 * b.0,
+        a.0 * c.1 - a.1 * c.0,
+        b.0 * a.1 - b.1 * a.0,
+    )
+}
+
+// Watertight ray/triangle intersection (Woop, Benthin and Wald 2013), rays hitting a shared edge
+// or vertex always hit at least one of the triangles so meshes don't leak light through cracks.
+pub fn collide_ray_with_triangle(
+    ray: &Ray,
+    vertices: [Vec3A; 3],
+    t_min: f32,
+    t_max: f32,
+) -> Option<TriangleHit> {
+    let direction = ray.direction;
+    let abs_direction = direction.abs();
+
+    let kz = if abs_direction.x > abs_direction.y {
+        if abs_direction.x > abs_direction.z {
+            0
+        } else {
+            2
+        }
+    } else if abs_direction.y > abs_direction.z {
+        1
+    } else {
+        2
+    };
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if direction[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    if direction[kz] == 0.0 {
+        return None;
+    }
+
+    let shear_x = direction[kx] / direction[kz];
+    let shear_y = direction[ky] / direction[kz];
+    let shear_z = 1.0 / direction[kz];
+
+    let a = vertices[0] - ray.origin;
+    let b = vertices[1] - ray.origin;
+    let c = vertices[2] - ray.origin;
+
+    let ax = a[kx] - shear_x * a[kz];
+    let ay = a[ky] - shear_y * a[kz];
+    let bx = b[kx] - shear_x * b[kz];
+    let by = b[ky] - shear_y * b[kz];
+    let cx = c[kx] - shear_x * c[kz];
+    let cy = c[ky] - shear_y * c[kz];
+
+    let mut u = cx * by - cy * bx;
+    let mut v = ax * cy - ay * cx;
+    let mut w = bx * ay - by * ax;
+
+    // Fall back to double precision when the ray passes exactly through an edge
+    if u == 0.0 || v == 0.0 || w == 0.0 {
+        let (u64, v64, w64) = triangle_edge_functions_f64(
+            (ax as f64, ay as f64),
+            (bx as f64, by as f64),
+            (cx as f64, cy as f64),
+        );
+        u = u64 as f32;
+        v = v64 as f32;
+        w = w64 as f32;
+    }
+
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+
+    let determinant = u + v + w;
+
+    if determinant == 0.0 {
+        return None;
+    }
+
+    let az = shear_z * a[kz];
+    let bz = shear_z * b[kz];
+    let cz = shear_z * c[kz];
+    let scaled_time = u * az + v * bz + w * cz;
+
+    let time = scaled_time / determinant;
+
+    if time < t_min || time >= t_max {
+        return None;
+    }
+
+    let inverse_determinant = 1.0 / determinant;
+
+    Some(TriangleHit {
+        time,
+        barycentric: Vec3A::new(u, v, w) * inverse_determinant,
+    })
+}
+
+// Distance along the ray to the plane through `point` facing along `normal`, from either side
+pub fn collide_ray_with_plane(
+    ray: &Ray,
+    point: Vec3A,
+    normal: Vec3A,
+    t_min: f32,
+    t_max: f32,
+) -> Option<f32> {
+    let denominator = normal.dot(ray.direction);
+
+    // Parallel rays never reach the plane
+    if denominator.abs() < 1.0e-8 {
+        return None;
+    }
+
+    let time = normal.dot(point - ray.origin) / denominator;
+
+    if time < t_min || time >= t_max || !time.is_finite() {
+        return None;
+    }
+
+    Some(time)
+}
@@ -1,191 +1,419 @@
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
 use crossbeam_channel::Sender;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 use crate::{
-    file::save_png_from_pixel_data,
-    bounding_box::bvh::BVHNode,
-    ray::{Ray, RayCollider},
+    accumulator::{Accumulator, FilmTile, SampleSums},
+    bounding_box::bvh::{BVHBuildStrategy, BVHNode, BVHStats},
+    light::{power_heuristic, Lights},
+    ray::{Ray, RayCollision},
     color::Color,
+    filter::Filter,
+    sampler::{Sampler, SamplerKind},
     scene::Scene,
     stats::Stats,
-    ui::pixel::{Pixel, PixelBatchUpdate, PixelsData}, shape::Sphere,
+    ui::pixel::{Pixel, PixelBatchUpdate},
 };
 
 use super::camera::Camera;
 
-const MAX_RAY_DEPTH: u32 = 64;
-const CHUNK_SIZE: u32 = 5;
+pub const DEFAULT_CHUNK_SIZE: u32 = 5;
+pub const DEFAULT_SEED: u64 = 0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub max_ray_depth: u32,
+    pub bvh_build_strategy: BVHBuildStrategy,
+    // Width and height of the square blocks of pixels handed to each thread
+    pub chunk_size: u32,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub adaptive: Option<AdaptiveSettings>,
+}
+
+// Pixels stop being sampled once the relative error of their mean luminance drops below
+// `threshold`, but never before `min_samples` or after `max_samples`
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveSettings {
+    pub threshold: f32,
+    pub min_samples: u32,
+    pub max_samples: u32,
+}
+
+impl Default for AdaptiveSettings {
+    fn default() -> AdaptiveSettings {
+        AdaptiveSettings {
+            threshold: 0.02,
+            min_samples: 16,
+            max_samples: 1024,
+        }
+    }
+}
+
+impl AdaptiveSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.threshold.is_nan() || self.threshold <= 0.0 {
+            return Err(String::from("adaptive threshold must be above zero"));
+        }
+        // The error estimate needs at least two samples
+        if self.min_samples < 2 || self.max_samples < self.min_samples {
+            return Err(String::from(
+                "adaptive sampling needs 2 <= min_samples <= max_samples",
+            ));
+        }
+        Ok(())
+    }
+
+    fn samples_to_take(&self, accumulator: &Accumulator, x: u32, y: u32, samples: u32) -> u32 {
+        let sample_count = accumulator.sample_count(x, y);
+
+        if sample_count >= self.max_samples {
+            return 0;
+        }
+        if sample_count >= self.min_samples
+            && accumulator.relative_error(x, y) <= self.threshold
+        {
+            return 0;
+        }
+
+        samples
+            .max(self.min_samples.saturating_sub(sample_count))
+            .min(self.max_samples - sample_count)
+    }
+}
+
+pub struct PassSummary {
+    // False when the pass was stopped before every chunk was rendered
+    pub completed: bool,
+    pub pixels_sampled: u32,
+    pub samples_taken: u64,
+}
 
 struct PixelChunk {
     y: u32,
     x: u32,
-    chunk_size: u32,
+    width: u32,
+    height: u32,
 }
 
-fn ray_color(bvh_tree: &BVHNode, scene: &Arc<Scene>, ray: &Ray, depth: u32) -> Color {
-    if depth == 0 {
-        return Color::zero();
-    }
+const RAY_COLLISION_T_MIN: f32 = 0.001;
 
-    if let Some(ray_collision) = bvh_tree.collide_ray(ray, 0.001, f32::INFINITY) {
-        if let Some(material_scatter) = ray_collision.material().scatter(ray, &ray_collision) {
-            material_scatter.color
-                * ray_color(bvh_tree, scene, &material_scatter.ray, depth - 1)
-        } else {
-            Color::zero()
+// Light arriving at `ray_collision` directly from a randomly chosen light, weighted against the
+// chance of the material scattering towards the same light
+fn sample_direct_light(
+    bvh_tree: &BVHNode,
+    lights: &Lights,
+    ray: &Ray,
+    ray_collision: &RayCollision,
+    sampler: &mut Sampler,
+) -> Color {
+    let light_sample = match lights.sample(ray_collision.point(), ray.time, sampler) {
+        Some(light_sample) => light_sample,
+        None => return Color::zero(),
+    };
+
+    let evaluation = match ray_collision
+        .material()
+        .evaluate(ray, ray_collision, light_sample.direction)
+    {
+        Some(evaluation) => evaluation,
+        None => return Color::zero(),
+    };
+
+    let shadow_ray = Ray::new(ray_collision.point(), light_sample.direction, ray.time);
+
+    // Whatever emitter is hit first counts, not only the sampled one, as the pdf covers every
+    // light that could have produced the direction. Anything else hit first blocks the light.
+    match bvh_tree.collide_ray(&shadow_ray, RAY_COLLISION_T_MIN, f32::INFINITY) {
+        Some(shadow_collision) if shadow_collision.material().is_emissive() => {
+            let emitted = shadow_collision
+                .material()
+                .emitted(&shadow_ray, &shadow_collision);
+            let weight = power_heuristic(light_sample.pdf, evaluation.pdf);
+
+            emitted * evaluation.color * (weight / light_sample.pdf)
         }
-    } else {
-        let unit_direction = ray.direction.normalize();
-        let t = 0.5 * (unit_direction.y + 1.0);
-        Color::new(1.0, 1.0, 1.0) * (1.0 - t) + Color::new(0.5, 0.7, 1.0) * t
+        _ => Color::zero(),
     }
 }
 
-fn sample_pixel(
+fn ray_color(
     bvh_tree: &BVHNode,
     scene: &Arc<Scene>,
-    camera: &Camera,
-    x: u32,
-    y: u32,
-    samples_per_pixel_side: u32,
+    lights: &Lights,
+    ray: &Ray,
     max_ray_depth: u32,
+    sampler: &mut Sampler,
 ) -> Color {
-    let mut pixel_color = Color::zero();
-
-    for u_offset in 0..samples_per_pixel_side {
-        for v_offset in 0..samples_per_pixel_side {
-            let u_delta = u_offset as f32 / samples_per_pixel_side as f32;
-            let v_delta = v_offset as f32 / samples_per_pixel_side as f32;
-
-            // println!("{} {}", u_delta, v_delta);
+    let mut color = Color::zero();
+    let mut throughput = Color::one();
+    let mut ray = Ray::new(ray.origin, ray.direction, ray.time);
+    // Density of the material sample that produced `ray`, `None` for camera rays and specular
+    // bounces where light sampling isn't possible
+    let mut scatter_pdf: Option<f32> = None;
+
+    for bounce in 0..max_ray_depth {
+        let ray_collision = match bvh_tree.collide_ray(&ray, RAY_COLLISION_T_MIN, f32::INFINITY) {
+            Some(ray_collision) => ray_collision,
+            None => {
+                color += throughput * scene.background.color(&ray);
+                break;
+            }
+        };
 
-            let u = (x as f32 + u_delta) / ((camera.screen_width() - 1) as f32);
-            let v = (((camera.screen_height() - 1) as f32) - (y as f32) + v_delta)
-                / ((camera.screen_height() - 1) as f32);
+        let material = ray_collision.material();
+        let emitted = material.emitted(&ray, &ray_collision);
 
-            let ray = camera.make_ray(u, v);
+        if material.is_emissive() {
+            let weight = match scatter_pdf {
+                Some(pdf) => {
+                    power_heuristic(pdf, lights.pdf(ray.origin, ray.direction, ray.time))
+                }
+                None => 1.0,
+            };
+            color += throughput * emitted * weight;
+        }
 
-            let sample_color = ray_color(bvh_tree, scene, &ray, max_ray_depth);
+        sampler.start_material_sample(bounce);
+        let material_scatter = match material.scatter(&ray, &ray_collision, sampler) {
+            Some(material_scatter) => material_scatter,
+            None => break,
+        };
 
-            pixel_color += sample_color;
+        if material_scatter.pdf.is_some() && !lights.is_empty() {
+            sampler.start_light_sample(bounce);
+            color += throughput
+                * sample_direct_light(bvh_tree, lights, &ray, &ray_collision, sampler);
         }
+
+        throughput = throughput * material_scatter.color;
+        scatter_pdf = material_scatter.pdf;
+        ray = Ray::new(
+            material_scatter.ray.origin,
+            material_scatter.ray.direction,
+            ray.time,
+        );
     }
 
-    pixel_color / (samples_per_pixel_side * samples_per_pixel_side) as f32
+    color
 }
 
-pub fn render_scene(
-    scene: &Arc<Scene>,
-    camera: &Camera,
-    samples_per_pixel_side: u32,
-    pixel_batch_sender: Sender<PixelBatchUpdate>,
-    stats: Stats,
-) -> PixelsData {
-    let height = camera.screen_height();
-    let width = camera.screen_width();
-    let mut pixels: PixelsData = Vec::new();
-
-    for y in 0..height {
-        let mut row: Vec<Pixel> = Vec::new();
-        for x in 0..width {
-            row.push(Pixel::new(x, y, Color::zero()));
-        }
-        pixels.push(row);
-    }
-
+// Chunks along the right and bottom edges are cut short when the image size isn't a multiple of
+// the chunk size
+fn make_pixel_chunks(width: u32, height: u32, chunk_size: u32) -> Vec<PixelChunk> {
     let mut pixel_chunks: Vec<PixelChunk> = Vec::new();
 
-    for x in 0..(width / CHUNK_SIZE) {
-        for y in 0..(height / CHUNK_SIZE) {
+    for x in (0..width).step_by(chunk_size as usize) {
+        for y in (0..height).step_by(chunk_size as usize) {
             pixel_chunks.push(PixelChunk {
-                y: y * CHUNK_SIZE,
-                x: x * CHUNK_SIZE,
-                chunk_size: CHUNK_SIZE,
+                y,
+                x,
+                width: chunk_size.min(width - x),
+                height: chunk_size.min(height - y),
             })
         }
     }
 
-    let samples_per_pixel = samples_per_pixel_side * samples_per_pixel_side;
-
-    let bvh_tree = BVHNode::build_tree(scene.colliders.clone(), 0.0, 1.0);
-
-    stats
-        .clone()
-        .start_current_frame(pixel_chunks.len() as u32, samples_per_pixel);
-
-    let pixel_updates: Vec<Pixel> = pixel_chunks
-        .into_par_iter()
-        .map(|chunk| {
-            let mut pixel_updates: Vec<Pixel> = Vec::new();
-            for y_offset in 0..chunk.chunk_size {
-                for x_offset in 0..chunk.chunk_size {
-                    let x = x_offset + chunk.x;
-                    let y = y_offset + chunk.y;
-                    let pixel_color = sample_pixel(
-                        &bvh_tree,
-                        scene,
-                        camera,
-                        x,
-                        y,
-                        samples_per_pixel_side,
-                        MAX_RAY_DEPTH,
-                    );
-
-                    let pixel = Pixel::new(x as u32, y as u32, pixel_color);
-
-                    pixel_updates.push(pixel);
-                }
-            }
+    pixel_chunks
+}
 
-            pixel_batch_sender
-                .send(PixelBatchUpdate {
-                    pixels: pixel_updates.clone(),
-                })
-                .unwrap();
+pub struct Renderer<'a> {
+    scene: &'a Arc<Scene>,
+    camera: &'a Camera,
+    settings: &'a RenderSettings,
+    bvh_tree: BVHNode,
+    lights: Lights,
+}
 
-            stats.clone().complete_chunk();
+impl<'a> Renderer<'a> {
+    pub fn new(scene: &'a Arc<Scene>, camera: &'a Camera, settings: &'a RenderSettings) -> Renderer<'a> {
+        // Only the top level tree is built here, meshes and objects built their own trees when
+        // they were added to the scene so moving them around doesn't mean rebuilding those
+        let colliders = scene.top_level_colliders();
+        let bvh_tree = BVHNode::build_tree(
+            colliders.clone(),
+            0.0,
+            1.0,
+            settings.bvh_build_strategy,
+            settings.seed,
+        );
+        Renderer {
+            scene,
+            camera,
+            settings,
+            bvh_tree,
+            lights: Lights::from_colliders(&colliders),
+        }
+    }
 
-            pixel_updates
-        })
-        .reduce(
-            Vec::new,
-            |acc: Vec<Pixel>, arr: Vec<Pixel>| {
-                let mut out = acc;
+    pub fn bvh_stats(&self) -> BVHStats {
+        self.bvh_tree.stats()
+    }
 
-                for el in arr {
-                    out.push(el);
-                }
+    // `samples` jittered camera rays through pixel `x`, `y`, continuing the pixel's sample
+    // sequence from `first_sample`. Each sample is splatted onto `tile` through the filter, the
+    // returned sums only cover this pixel's own samples.
+    fn sample_pixel(
+        &self,
+        sampler: &mut Sampler,
+        tile: &mut FilmTile,
+        x: u32,
+        y: u32,
+        first_sample: u32,
+        samples: u32,
+    ) -> SampleSums {
+        let camera = self.camera;
+        let mut sums = SampleSums::zero();
+
+        for sample in 0..samples {
+            sampler.start_pixel_sample(x, y, first_sample + sample, sample, samples);
+
+            // Position on the image in pixels, from the top left corner
+            let delta = sampler.pixel();
+            let image_x = x as f32 + delta.x;
+            let image_y = y as f32 + delta.y;
+
+            let u = image_x / ((camera.screen_width() - 1) as f32);
+            let v = (camera.screen_height() as f32 - image_y)
+                / ((camera.screen_height() - 1) as f32);
 
-                out
-            },
-        );
+            let lens = sampler.lens();
+            let ray = camera.make_ray(u, v, lens, sampler.time());
 
-    stats.complete_frame();
+            let color = ray_color(
+                &self.bvh_tree,
+                self.scene,
+                &self.lights,
+                &ray,
+                self.settings.max_ray_depth,
+                sampler,
+            );
 
-    for pixel in pixel_updates {
-        pixels[pixel.position().y as usize][pixel.position().x as usize] = pixel;
+            tile.splat(&self.settings.filter, image_x, image_y, color);
+            sums.add(color);
+        }
+
+        sums
     }
 
-    pixels
-}
+    // Adds `samples_per_pixel` more samples to every pixel in `accumulator`, or with adaptive
+    // sampling to every pixel that still needs them, sending the refined pixels to the window as
+    // each chunk finishes. Once `stop` is set no more chunks are started and the samples from
+    // finished chunks are kept.
+    pub fn render_pass(
+        &self,
+        accumulator: &mut Accumulator,
+        samples_per_pixel: u32,
+        stop: &AtomicBool,
+        pixel_batch_sender: Sender<PixelBatchUpdate>,
+        stats: Stats,
+    ) -> PassSummary {
+        let pixel_chunks = make_pixel_chunks(
+            accumulator.width(),
+            accumulator.height(),
+            self.settings.chunk_size,
+        );
+        let chunk_count = pixel_chunks.len();
+
+        stats
+            .clone()
+            .start_current_frame(chunk_count as u32, samples_per_pixel);
+
+        let accumulated: &Accumulator = accumulator;
+        let chunk_samples: Vec<_> = pixel_chunks
+            .into_par_iter()
+            .filter_map(|chunk| {
+                if stop.load(Ordering::Relaxed) {
+                    return None;
+                }
 
-pub fn render_scene_save_to_file(
-    scene: &Arc<Scene>,
-    camera: &Camera,
-    samples_per_pixel_side: u32,
-    file_path: String,
-    pixel_batch_sender: Sender<PixelBatchUpdate>,
-    stats: Stats,
-) -> PixelsData {
-    let pixel_data = render_scene(
-        scene,
-        camera,
-        samples_per_pixel_side,
-        pixel_batch_sender,
-        stats,
-    );
-    save_png_from_pixel_data(file_path, &pixel_data);
-    pixel_data
+                // Every sample comes from the sampler, keyed by pixel and sample index, so the
+                // image doesn't depend on which thread renders which chunk
+                let mut sampler = Sampler::new(self.settings.sampler, self.settings.seed);
+
+                let mut tile = FilmTile::new(
+                    chunk.x,
+                    chunk.y,
+                    chunk.width,
+                    chunk.height,
+                    self.settings.filter.margin(),
+                    accumulated.width(),
+                    accumulated.height(),
+                );
+                let mut pixel_samples: Vec<(u32, u32, SampleSums)> = Vec::new();
+
+                for y_offset in 0..chunk.height {
+                    for x_offset in 0..chunk.width {
+                        let x = x_offset + chunk.x;
+                        let y = y_offset + chunk.y;
+
+                        let samples = match &self.settings.adaptive {
+                            Some(adaptive) => {
+                                adaptive.samples_to_take(accumulated, x, y, samples_per_pixel)
+                            }
+                            None => samples_per_pixel,
+                        };
+                        if samples == 0 {
+                            continue;
+                        }
+
+                        let sums = self.sample_pixel(
+                            &mut sampler,
+                            &mut tile,
+                            x,
+                            y,
+                            accumulated.sample_count(x, y),
+                            samples,
+                        );
+                        pixel_samples.push((x, y, sums));
+                    }
+                }
+
+                // Neighbouring chunks' samples for the edge pixels are left out of the preview
+                // until the pass finishes
+                let pixel_updates: Vec<Pixel> = pixel_samples
+                    .iter()
+                    .map(|(x, y, _)| {
+                        Pixel::new(*x, *y, accumulated.color_with(*x, *y, tile.get(*x, *y)))
+                    })
+                    .collect();
+
+                if !pixel_updates.is_empty() {
+                    pixel_batch_sender
+                        .send(PixelBatchUpdate {
+                            pixels: pixel_updates,
+                        })
+                        .unwrap();
+                }
+
+                stats.clone().complete_chunk();
+
+                Some((pixel_samples, tile))
+            })
+            .collect();
+
+        let mut summary = PassSummary {
+            completed: chunk_samples.len() == chunk_count,
+            pixels_sampled: 0,
+            samples_taken: 0,
+        };
+
+        for (pixel_samples, tile) in &chunk_samples {
+            for (x, y, sums) in pixel_samples {
+                accumulator.add_samples(*x, *y, sums);
+                summary.pixels_sampled += 1;
+                summary.samples_taken += sums.count as u64;
+            }
+            accumulator.add_tile(tile);
+        }
+
+        stats.complete_frame();
+
+        summary
+    }
 }
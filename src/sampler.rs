@@ -0,0 +1,297 @@
+use glam::Vec2;
+
+use crate::maths::mix_seed;
+
+// Dimensions are handed out in a fixed layout so the same dimension of every sample of a pixel
+// is used for the same decision, which is what lets the low discrepancy samplers spread them out
+const PIXEL_DIMENSION: u32 = 0;
+const LENS_DIMENSION: u32 = 2;
+const TIME_DIMENSION: u32 = 4;
+const FIRST_BOUNCE_DIMENSION: u32 = 5;
+// Light choice (1) and position (2), then three for the material, a bounce that asks for more
+// overlaps the next bounce's dimensions
+const DIMENSIONS_PER_BOUNCE: u32 = 6;
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+// The Halton sampler uses one prime base per dimension, later dimensions are independent random
+const HALTON_PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerKind {
+    // Every dimension is an unrelated random number
+    Independent,
+    // Each pass's samples are spread over a jittered grid, shuffled separately for each dimension
+    Stratified,
+    // Halton sequence with its digits Owen scrambled separately for each pixel
+    Halton,
+    // Sobol sequence with hash based Owen scrambling, padded by shuffling for each dimension pair
+    Sobol,
+}
+
+pub const SAMPLER_NAMES: &str = "'independent', 'stratified', 'halton' or 'sobol'";
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match name {
+            "independent" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            _ => None,
+        }
+    }
+}
+
+fn to_unit_f32(bits: u32) -> f32 {
+    (bits as f32 * (1.0 / 4_294_967_296.0)).min(ONE_MINUS_EPSILON)
+}
+
+fn hash_to_f32(hash: u64) -> f32 {
+    ((hash >> 40) as f32 * (1.0 / 16_777_216.0)).min(ONE_MINUS_EPSILON)
+}
+
+// Kensler's hashed permutation, a different shuffle of 0..length for every `seed`
+fn permute(mut index: u32, length: u32, seed: u32) -> u32 {
+    let mut mask = length - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dcb303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e501cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860a3df);
+        index &= mask;
+        index ^= index >> 5;
+
+        if index < length {
+            return index.wrapping_add(seed) % length;
+        }
+    }
+}
+
+// Mirrors the digits of `index` in `base` around the decimal point, shuffling each digit by a
+// permutation that depends on the digits before it. Leading zeros are shuffled too, until the
+// digits are too small to change an f32, otherwise large bases would leave most of the unit
+// interval empty at low sample counts.
+fn scrambled_radical_inverse(mut index: u32, base: u32, seed: u64) -> f32 {
+    let inverse_base = 1.0 / base as f64;
+    let mut inverse_base_power = 1.0;
+    let mut reversed_digits: u64 = 0;
+
+    while 1.0 - (inverse_base_power as f32) < 1.0 {
+        let digit = permute(index % base, base, mix_seed(seed, reversed_digits) as u32);
+        reversed_digits = reversed_digits * base as u64 + digit as u64;
+        inverse_base_power *= inverse_base;
+        index /= base;
+    }
+
+    ((reversed_digits as f64 * inverse_base_power) as f32).min(ONE_MINUS_EPSILON)
+}
+
+// First two dimensions of the Sobol sequence, the second comes from the polynomial x + 1
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut y = 0;
+    let mut direction: u32 = 1 << 31;
+
+    for bit in 0..32 {
+        if index & (1 << bit) != 0 {
+            y ^= direction;
+        }
+        direction ^= direction >> 1;
+    }
+
+    (index.reverse_bits(), y)
+}
+
+// Burley's "Practical Hash-based Owen Scrambling"
+fn laine_karras_permutation(mut value: u32, seed: u32) -> u32 {
+    value = value.wrapping_add(seed);
+    value ^= value.wrapping_mul(0x6c50b47c);
+    value ^= value.wrapping_mul(0xb82f1e52);
+    value ^= value.wrapping_mul(0xc7afe638);
+    value ^= value.wrapping_mul(0x8d22f6e6);
+    value
+}
+
+fn nested_uniform_scramble(value: u32, seed: u32) -> u32 {
+    laine_karras_permutation(value.reverse_bits(), seed).reverse_bits()
+}
+
+// Hands out the numbers for one camera sample at a time. `start_pixel_sample` picks the sample,
+// the `start_` functions move to where each part of the path begins and each call to `get_1d` or
+// `get_2d` takes the next dimension from there.
+pub struct Sampler {
+    kind: SamplerKind,
+    seed: u64,
+    pixel_seed: u64,
+    // Index of the sample among every sample the pixel has taken, across passes
+    sample_index: u32,
+    // Index of the sample within the current pass and how many the pass takes, for stratifying
+    pass_sample_index: u32,
+    pass_sample_count: u32,
+    pass_seed: u64,
+    dimension: u32,
+}
+
+impl Sampler {
+    pub fn new(kind: SamplerKind, seed: u64) -> Sampler {
+        Sampler {
+            kind,
+            seed,
+            pixel_seed: seed,
+            sample_index: 0,
+            pass_sample_index: 0,
+            pass_sample_count: 1,
+            pass_seed: seed,
+            dimension: 0,
+        }
+    }
+
+    pub fn start_pixel_sample(
+        &mut self,
+        x: u32,
+        y: u32,
+        sample_index: u32,
+        pass_sample_index: u32,
+        pass_sample_count: u32,
+    ) {
+        self.pixel_seed = mix_seed(self.seed, ((y as u64) << 32) | x as u64);
+        self.sample_index = sample_index;
+        self.pass_sample_index = pass_sample_index;
+        self.pass_sample_count = pass_sample_count.max(1);
+        self.pass_seed = mix_seed(self.pixel_seed, (sample_index - pass_sample_index) as u64);
+        self.dimension = PIXEL_DIMENSION;
+    }
+
+    pub fn pixel(&mut self) -> Vec2 {
+        self.dimension = PIXEL_DIMENSION;
+        self.get_2d()
+    }
+
+    pub fn lens(&mut self) -> Vec2 {
+        self.dimension = LENS_DIMENSION;
+        self.get_2d()
+    }
+
+    pub fn time(&mut self) -> f32 {
+        self.dimension = TIME_DIMENSION;
+        self.get_1d()
+    }
+
+    // Light sampling at a bounce takes up to three dimensions
+    pub fn start_light_sample(&mut self, bounce: u32) {
+        self.dimension = FIRST_BOUNCE_DIMENSION + bounce * DIMENSIONS_PER_BOUNCE;
+    }
+
+    // Followed by up to three for the material to scatter the ray
+    pub fn start_material_sample(&mut self, bounce: u32) {
+        self.dimension = FIRST_BOUNCE_DIMENSION + bounce * DIMENSIONS_PER_BOUNCE + 3;
+    }
+
+    fn dimension_hash(&self, dimension: u32) -> u64 {
+        mix_seed(self.pixel_seed, dimension as u64)
+    }
+
+    fn independent(&self, dimension: u32) -> f32 {
+        hash_to_f32(mix_seed(
+            self.dimension_hash(dimension),
+            self.sample_index as u64,
+        ))
+    }
+
+    pub fn get_1d(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+
+        match self.kind {
+            SamplerKind::Independent => self.independent(dimension),
+            SamplerKind::Stratified => {
+                let stratum = permute(
+                    self.pass_sample_index,
+                    self.pass_sample_count,
+                    mix_seed(self.pass_seed, dimension as u64) as u32,
+                );
+                (stratum as f32 + self.independent(dimension)) / self.pass_sample_count as f32
+            }
+            SamplerKind::Halton => match HALTON_PRIMES.get(dimension as usize) {
+                Some(base) => scrambled_radical_inverse(
+                    self.sample_index,
+                    *base,
+                    self.dimension_hash(dimension),
+                ),
+                None => self.independent(dimension),
+            },
+            SamplerKind::Sobol => self.sobol(dimension).0,
+        }
+    }
+
+    pub fn get_2d(&mut self) -> Vec2 {
+        let dimension = self.dimension;
+
+        match self.kind {
+            SamplerKind::Stratified => {
+                self.dimension += 2;
+
+                // When the samples don't fill the grid a different random set of cells is left
+                // out every pass, so no part of the pixel is favoured
+                let count = self.pass_sample_count;
+                let columns = (count as f32).sqrt() as u32;
+                let rows = count.div_ceil(columns);
+                let stratum = permute(
+                    self.pass_sample_index,
+                    columns * rows,
+                    mix_seed(self.pass_seed, dimension as u64) as u32,
+                );
+
+                Vec2::new(
+                    ((stratum % columns) as f32 + self.independent(dimension)) / columns as f32,
+                    ((stratum / columns) as f32 + self.independent(dimension + 1)) / rows as f32,
+                )
+            }
+            SamplerKind::Sobol => {
+                self.dimension += 2;
+                let (x, y) = self.sobol(dimension);
+                Vec2::new(x, y)
+            }
+            SamplerKind::Independent | SamplerKind::Halton => {
+                let x = self.get_1d();
+                Vec2::new(x, self.get_1d())
+            }
+        }
+    }
+
+    // Each dimension (pair) shuffles the sample order with its own seed, so the first two Sobol
+    // dimensions can be reused everywhere without the pairs lining up with each other
+    fn sobol(&self, dimension: u32) -> (f32, f32) {
+        let seed = self.dimension_hash(dimension);
+        let index = nested_uniform_scramble(self.sample_index, seed as u32);
+        let (x, y) = sobol_2d(index);
+
+        (
+            to_unit_f32(nested_uniform_scramble(x, (seed >> 32) as u32)),
+            to_unit_f32(nested_uniform_scramble(y, mix_seed(seed, 1) as u32)),
+        )
+    }
+}
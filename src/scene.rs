@@ -1,56 +1,163 @@
-use std::sync::Arc;
+use std::{ops::Range, sync::Arc};
 
-use crate::collider::Collider;
+use glam::Mat4;
+
+use crate::{
+    bounding_box::bvh::{BVHBuildStrategy, BVHNode},
+    collider::Collider,
+    color::Color,
+    instance::Instance,
+    ray::Ray,
+    raytracer::DEFAULT_SEED,
+    shape::Mesh,
+};
 
 // type SyncedShaped = Shape;
 
 type ArcCollider = Arc<Collider>;
 
+#[derive(Clone, Copy, Debug)]
+pub enum Background {
+    Sky,
+    Solid(Color),
+}
+
+impl Background {
+    pub fn color(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Sky => {
+                let unit_direction = ray.direction.normalize();
+                let t = 0.5 * (unit_direction.y + 1.0);
+                Color::new(1.0, 1.0, 1.0) * (1.0 - t) + Color::new(0.5, 0.7, 1.0) * t
+            }
+            Background::Solid(color) => *color,
+        }
+    }
+}
+
+// A mesh's bottom level tree and where it is placed. The tree is only built once, changing
+// `transform` moves the mesh the next time a renderer builds its top level tree.
+#[derive(Clone)]
+pub struct MeshInstance {
+    object: Option<Arc<BVHNode>>,
+    mesh: Arc<Mesh>,
+    // Emissive faces, left out of `object` so they can be sampled as lights. They're moved into
+    // place whenever the transform changes, as lights can't be sampled through an instance.
+    light_faces: Vec<usize>,
+    lights: Vec<ArcCollider>,
+    transform: Mat4,
+}
+
+impl MeshInstance {
+    fn add_to(&self, colliders: &mut Vec<ArcCollider>) {
+        if let Some(object) = &self.object {
+            let instance = Instance::new(object.clone(), self.transform);
+            colliders.push(Arc::new(Collider::InstanceCollider(instance)));
+        }
+        colliders.extend(self.lights.iter().cloned());
+    }
+}
+
 #[derive(Clone)]
 pub struct Scene {
     pub colliders: Vec<ArcCollider>,
+    pub meshes: Vec<MeshInstance>,
+    pub background: Background,
 }
 
 impl Scene {
     pub fn new() -> Scene {
         Scene {
             colliders: Vec::new(),
+            meshes: Vec::new(),
+            background: Background::Sky,
         }
     }
     pub fn add_shape(&mut self, shape: ArcCollider) {
         self.colliders.push(shape)
     }
+    // Each mesh gets a bottom level tree of its own, built once here, so the top level tree the
+    // renderer builds only has to place the mesh as a whole. Emissive faces stay in the top level
+    // so they can still be sampled as lights. Returns the mesh's index in `meshes`.
+    pub fn add_mesh(&mut self, mesh: &Arc<Mesh>, faces: Range<usize>) -> usize {
+        let (lights, faces): (Vec<usize>, Vec<usize>) =
+            faces.partition(|&face| mesh.is_face_emissive(face));
+
+        let object = if faces.is_empty() {
+            None
+        } else {
+            Some(Arc::new(BVHNode::build_tree(
+                Mesh::triangle_colliders(mesh, faces),
+                0.0,
+                1.0,
+                BVHBuildStrategy::SurfaceAreaHeuristic,
+                DEFAULT_SEED,
+            )))
+        };
+
+        self.meshes.push(MeshInstance {
+            object,
+            mesh: mesh.clone(),
+            lights: Mesh::triangle_colliders(mesh, lights.iter().copied()),
+            light_faces: lights,
+            transform: Mat4::IDENTITY,
+        });
+        self.meshes.len() - 1
+    }
+    // Moves a mesh added by `add_mesh` without rebuilding its tree, `transform` must be affine
+    pub fn set_mesh_transform(&mut self, mesh: usize, transform: Mat4) {
+        let mesh = &mut self.meshes[mesh];
+        mesh.transform = transform;
+
+        if !mesh.light_faces.is_empty() {
+            let lights = Arc::new(mesh.mesh.transformed(&mesh.light_faces, transform));
+            mesh.lights = Mesh::triangle_colliders(&lights, 0..lights.face_count());
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.colliders.is_empty() && self.meshes.is_empty()
+    }
+    // What the top level tree is built over, the loose colliders and every mesh where it is now
+    pub fn top_level_colliders(&self) -> Vec<ArcCollider> {
+        let mut colliders = self.colliders.clone();
+        for mesh in &self.meshes {
+            mesh.add_to(&mut colliders);
+        }
+        colliders
+    }
 }
 
 pub mod generator {
     use std::sync::Arc;
 
     use glam::Vec3A;
-    use rand::Rng;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
 
     use crate::{
-        collider::Collider::SphereCollider,
+        collider::Collider::{PlaneCollider, SphereCollider},
         color::Color,
         material::materials::{
             Dielectric, Lambertian,
             Material::{MaterialDielectric, MaterialLambertian, MaterialMetal},
             Metal,
         },
-        shape::Sphere,
+        shape::{Plane, Sphere},
+        texture::{CheckerTexture, NoisePattern, NoiseTexture, Texture},
     };
 
     use super::Scene;
 
-    pub fn make_random_balls_scene() -> Scene {
+    // The same seed always places the same balls
+    pub fn make_random_balls_scene(seed: u64) -> Scene {
         let mut scene = Scene::new();
-        let mut rng = rand::thread_rng();
+        let mut rng = StdRng::seed_from_u64(seed);
 
         let ground_mat = Arc::new(MaterialLambertian(Lambertian::new(Color::new(
             0.5, 0.5, 0.5,
         ))));
-        let ground_sphere = Sphere::new(0.0, -1000.0, 0.0, 1000.0, ground_mat, Vec3A::ZERO);
+        let ground = Plane::new(Vec3A::ZERO, Vec3A::new(0.0, 1.0, 0.0), ground_mat);
 
-        scene.add_shape(Arc::new(SphereCollider(ground_sphere)));
+        scene.add_shape(Arc::new(PlaneCollider(ground)));
 
         let x = 11;
 
@@ -66,7 +173,7 @@ pub mod generator {
 
                 if mat < 0.7 {
                     // Diffuse
-                    let albedo = Color::random(0.0..1.0) * Color::random(0.0..1.0);
+                    let albedo = Color::random(0.0..1.0, &mut rng) * Color::random(0.0..1.0, &mut rng);
                     let sphere_mat = Arc::new(MaterialLambertian(Lambertian::new(albedo)));
                     let sphere = Sphere::new(
                         center.x,
@@ -80,7 +187,7 @@ pub mod generator {
                     scene.add_shape(Arc::new(SphereCollider(sphere)));
                 } else if mat < 0.9 {
                     // Metal
-                    let albedo = Color::random(0.4..1.0);
+                    let albedo = Color::random(0.4..1.0, &mut rng);
                     let fuzz = rng.gen_range(0.0..0.5);
                     let sphere_mat = Arc::new(MaterialMetal(Metal::new(albedo, fuzz)));
                     let sphere =
@@ -117,4 +224,108 @@ pub mod generator {
 
         scene
     }
+
+    fn noise_texture(
+        pattern: NoisePattern,
+        seed: u64,
+        scale: f32,
+        low: Color,
+        high: Color,
+    ) -> Arc<Texture> {
+        let mut noise = NoiseTexture::new(pattern, seed);
+        noise.scale = scale;
+        noise.low = low;
+        noise.high = high;
+        Arc::new(Texture::Noise(noise))
+    }
+
+    // A row of spheres showing each procedural texture on a checkered floor, `seed` picks the noise
+    pub fn make_procedural_textures_scene(seed: u64) -> Scene {
+        let mut scene = Scene::new();
+
+        let checker = Texture::Checker(CheckerTexture {
+            even: Arc::new(Texture::Constant(Color::new(0.9, 0.9, 0.9))),
+            odd: Arc::new(Texture::Constant(Color::new(0.2, 0.3, 0.1))),
+            size: 1.0,
+        });
+        let ground_mat = Arc::new(MaterialLambertian(Lambertian::with_texture(Arc::new(
+            checker,
+        ))));
+        scene.add_shape(Arc::new(PlaneCollider(Plane::new(
+            Vec3A::ZERO,
+            Vec3A::new(0.0, 1.0, 0.0),
+            ground_mat,
+        ))));
+
+        let textures = [
+            noise_texture(
+                NoisePattern::Noise,
+                seed,
+                4.0,
+                Color::new(0.1, 0.2, 0.5),
+                Color::new(0.9, 0.9, 1.0),
+            ),
+            noise_texture(
+                NoisePattern::Turbulence,
+                seed,
+                2.0,
+                Color::new(0.05, 0.05, 0.05),
+                Color::new(1.0, 0.6, 0.2),
+            ),
+            noise_texture(
+                NoisePattern::Marble,
+                seed,
+                3.0,
+                Color::new(0.2, 0.2, 0.25),
+                Color::new(0.95, 0.95, 0.9),
+            ),
+            noise_texture(
+                NoisePattern::Wood,
+                seed,
+                6.0,
+                Color::new(0.35, 0.18, 0.07),
+                Color::new(0.75, 0.5, 0.25),
+            ),
+        ];
+
+        for (index, texture) in textures.into_iter().enumerate() {
+            let material = Arc::new(MaterialLambertian(Lambertian::with_texture(texture)));
+            let sphere = Sphere::new(
+                index as f32 * 2.2 - 3.3,
+                1.0,
+                0.0,
+                1.0,
+                material,
+                Vec3A::ZERO,
+            );
+            scene.add_shape(Arc::new(SphereCollider(sphere)));
+        }
+
+        // Checkers can be made of other textures too
+        let marble_checker = Texture::Checker(CheckerTexture {
+            even: noise_texture(
+                NoisePattern::Marble,
+                seed,
+                8.0,
+                Color::new(0.1, 0.1, 0.1),
+                Color::new(0.9, 0.9, 0.9),
+            ),
+            odd: Arc::new(Texture::Constant(Color::new(0.7, 0.1, 0.1))),
+            size: 0.25,
+        });
+        let metal = Arc::new(MaterialMetal(Metal::with_texture(
+            Arc::new(marble_checker),
+            0.9,
+        )));
+        scene.add_shape(Arc::new(SphereCollider(Sphere::new(
+            0.0,
+            0.5,
+            2.0,
+            0.5,
+            metal,
+            Vec3A::ZERO,
+        ))));
+
+        scene
+    }
 }
@@ -0,0 +1,1612 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use glam::{Mat4, Vec2, Vec3A, Vec4};
+
+use crate::{
+    bounding_box::{
+        bvh::{BVHBuildStrategy, BVHNode},
+        Aabb,
+    },
+    camera::Camera,
+    collider::{
+        Collider,
+        Collider::{
+            ConeCollider, CsgCollider, CuboidCollider, CylinderCollider, DiskCollider,
+            InstanceCollider, PlaneCollider, QuadCollider, QuadricCollider, SphereCollider,
+            TorusCollider, TriangleCollider,
+        },
+    },
+    color::Color,
+    csg::{Csg, CsgOperation, CSG_OPERATION_NAMES},
+    display::{DisplayTransform, ToneMapping},
+    file::{exr::ExrPixelType, ImageFormat, OutputSettings},
+    filter::{Filter, FilterKind, FILTER_NAMES},
+    instance::Instance,
+    material::materials::{
+        Conductor, ConductorIor, Dielectric, Emissive, Lambertian,
+        Material::{
+            self, MaterialConductor, MaterialDielectric, MaterialEmissive, MaterialLambertian,
+            MaterialMetal, MaterialPrincipled, MaterialRoughDielectric,
+        },
+        Metal, Principled, RoughDielectric, METAL_NAMES,
+    },
+    obj::load_obj_file,
+    raytracer::{AdaptiveSettings, RenderSettings, DEFAULT_CHUNK_SIZE, DEFAULT_SEED},
+    sampler::{SamplerKind, SAMPLER_NAMES},
+    scene::{
+        generator::{make_procedural_textures_scene, make_random_balls_scene},
+        Background, Scene,
+    },
+    shape::{
+        Cone, Cuboid, Cylinder, Disk, Mesh, MeshFace, MeshVertex, Plane, Quad, Quadric, Sphere,
+        Torus, Triangle,
+    },
+    texture::{
+        constant_texture, CheckerTexture, ImageTexture, NoisePattern, NoiseTexture, Texture,
+        WrapMode,
+    },
+};
+
+const DEFAULT_IMAGE_WIDTH: u32 = 1080;
+const DEFAULT_IMAGE_HEIGHT: u32 = 920;
+const DEFAULT_SAMPLES_PER_PIXEL_SIDE_VALUES: [u32; 4] = [1, 2, 4, 8];
+const DEFAULT_MAX_RAY_DEPTH: u32 = 64;
+const DEFAULT_OUTPUT_PATH: &str = "output/raytracer.png";
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io {
+        path: String,
+        error: std::io::Error,
+    },
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io { path, error } => write!(f, "could not read {}: {}", path, error),
+            SceneFileError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+#[derive(Clone, Debug)]
+pub struct SceneSettings {
+    pub image_width: u32,
+    pub image_height: u32,
+    pub samples_per_pixel_side_values: Vec<u32>,
+    pub render: RenderSettings,
+    pub output: OutputSettings,
+    // Where to save a picture of how many samples each pixel took
+    pub heatmap: Option<OutputSettings>,
+}
+
+pub struct SceneDescription {
+    pub scene: Scene,
+    pub camera: Camera,
+    pub settings: SceneSettings,
+}
+
+struct CameraDescription {
+    position: Vec3A,
+    look_at: Vec3A,
+    up: Vec3A,
+    field_of_view: f32,
+    aperture: f32,
+    shutter: f32,
+}
+
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Token<'a> {
+    fn error(&self, message: String) -> SceneFileError {
+        SceneFileError::Parse {
+            line: self.line,
+            column: self.column,
+            message,
+        }
+    }
+}
+
+struct Property<'a> {
+    key: Token<'a>,
+    value: Token<'a>,
+}
+
+struct Directive<'a> {
+    name: Token<'a>,
+    arguments: Vec<Token<'a>>,
+    properties: Vec<Property<'a>>,
+    line: usize,
+    end_column: usize,
+}
+
+fn parse_f32(token: &Token) -> Result<f32, SceneFileError> {
+    token
+        .text
+        .parse::<f32>()
+        .map_err(|_| token.error(format!("expected a number, found '{}'", token.text)))
+}
+
+fn parse_bool(token: &Token) -> Result<bool, SceneFileError> {
+    match token.text {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(token.error(format!("expected true or false, found '{}'", token.text))),
+    }
+}
+
+fn parse_image_format(token: &Token) -> Result<ImageFormat, SceneFileError> {
+    ImageFormat::from_path(token.text).ok_or_else(|| {
+        token.error(format!(
+            "unknown image format for '{}', expected a .png, .exr or .pfm file",
+            token.text
+        ))
+    })
+}
+
+fn parse_u32(token: &Token) -> Result<u32, SceneFileError> {
+    token
+        .text
+        .parse::<u32>()
+        .map_err(|_| token.error(format!("expected a whole number, found '{}'", token.text)))
+}
+
+fn split_list<'a>(token: &Token<'a>) -> Vec<Token<'a>> {
+    let mut offset = 0;
+    token
+        .text
+        .split(',')
+        .map(|text| {
+            let part = Token {
+                text,
+                line: token.line,
+                column: token.column + offset,
+            };
+            offset += text.chars().count() + 1;
+            part
+        })
+        .collect()
+}
+
+fn parse_vec3(token: &Token) -> Result<Vec3A, SceneFileError> {
+    let parts = split_list(token);
+
+    if parts.len() != 3 {
+        return Err(token.error(format!(
+            "expected three comma separated numbers, found '{}'",
+            token.text
+        )));
+    }
+
+    Ok(Vec3A::new(
+        parse_f32(&parts[0])?,
+        parse_f32(&parts[1])?,
+        parse_f32(&parts[2])?,
+    ))
+}
+
+fn parse_vec2(token: &Token) -> Result<Vec2, SceneFileError> {
+    let parts = split_list(token);
+
+    if parts.len() != 2 {
+        return Err(token.error(format!(
+            "expected two comma separated numbers, found '{}'",
+            token.text
+        )));
+    }
+
+    Ok(Vec2::new(parse_f32(&parts[0])?, parse_f32(&parts[1])?))
+}
+
+fn parse_color(token: &Token) -> Result<Color, SceneFileError> {
+    let rgb = parse_vec3(token)?;
+    Ok(Color::new(rgb.x, rgb.y, rgb.z))
+}
+
+impl<'a> Directive<'a> {
+    fn missing(&self, message: String) -> SceneFileError {
+        SceneFileError::Parse {
+            line: self.line,
+            column: self.end_column,
+            message,
+        }
+    }
+    fn argument(&self, index: usize, description: &str) -> Result<Token<'a>, SceneFileError> {
+        self.arguments.get(index).copied().ok_or_else(|| {
+            self.missing(format!(
+                "'{}' is missing its {}",
+                self.name.text, description
+            ))
+        })
+    }
+    fn take(&mut self, key: &str) -> Option<Token<'a>> {
+        let index = self
+            .properties
+            .iter()
+            .position(|property| property.key.text == key)?;
+        Some(self.properties.remove(index).value)
+    }
+    fn take_required(&mut self, key: &str) -> Result<Token<'a>, SceneFileError> {
+        self.take(key).ok_or_else(|| {
+            self.missing(format!(
+                "'{}' is missing required property '{}'",
+                self.name.text, key
+            ))
+        })
+    }
+    fn required_f32(&mut self, key: &str) -> Result<f32, SceneFileError> {
+        parse_f32(&self.take_required(key)?)
+    }
+    fn optional_f32(&mut self, key: &str, default: f32) -> Result<f32, SceneFileError> {
+        match self.take(key) {
+            Some(token) => parse_f32(&token),
+            None => Ok(default),
+        }
+    }
+    fn optional_u32(&mut self, key: &str, default: u32) -> Result<u32, SceneFileError> {
+        match self.take(key) {
+            Some(token) => parse_u32(&token),
+            None => Ok(default),
+        }
+    }
+    fn required_vec3(&mut self, key: &str) -> Result<Vec3A, SceneFileError> {
+        parse_vec3(&self.take_required(key)?)
+    }
+    fn optional_vec3(&mut self, key: &str, default: Vec3A) -> Result<Vec3A, SceneFileError> {
+        match self.take(key) {
+            Some(token) => parse_vec3(&token),
+            None => Ok(default),
+        }
+    }
+    fn optional_vec3_list(
+        &mut self,
+        keys: [&str; 3],
+    ) -> Result<Option<[Vec3A; 3]>, SceneFileError> {
+        let tokens = keys.map(|key| self.take(key));
+        match tokens {
+            [Some(a), Some(b), Some(c)] => {
+                Ok(Some([parse_vec3(&a)?, parse_vec3(&b)?, parse_vec3(&c)?]))
+            }
+            [None, None, None] => Ok(None),
+            _ => Err(self.missing(format!(
+                "'{}' needs all of '{}', '{}' and '{}' or none of them",
+                self.name.text, keys[0], keys[1], keys[2]
+            ))),
+        }
+    }
+    fn optional_vec2_list(&mut self, keys: [&str; 3]) -> Result<Option<[Vec2; 3]>, SceneFileError> {
+        let tokens = keys.map(|key| self.take(key));
+        match tokens {
+            [Some(a), Some(b), Some(c)] => {
+                Ok(Some([parse_vec2(&a)?, parse_vec2(&b)?, parse_vec2(&c)?]))
+            }
+            [None, None, None] => Ok(None),
+            _ => Err(self.missing(format!(
+                "'{}' needs all of '{}', '{}' and '{}' or none of them",
+                self.name.text, keys[0], keys[1], keys[2]
+            ))),
+        }
+    }
+    fn required_color(&mut self, key: &str) -> Result<Color, SceneFileError> {
+        parse_color(&self.take_required(key)?)
+    }
+    fn optional_color(&mut self, key: &str, default: Color) -> Result<Color, SceneFileError> {
+        match self.take(key) {
+            Some(token) => parse_color(&token),
+            None => Ok(default),
+        }
+    }
+    // `roughness` sets both directions, `roughness_u` and `roughness_v` override it along the
+    // surface's tangent and across it
+    fn roughness(&mut self) -> Result<(f32, f32), SceneFileError> {
+        let roughness = self.optional_f32("roughness", 0.0)?;
+        let roughness_u = self.optional_f32("roughness_u", roughness)?;
+        let roughness_v = self.optional_f32("roughness_v", roughness)?;
+
+        for (key, value) in [("roughness_u", roughness_u), ("roughness_v", roughness_v)] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(self.missing(format!(
+                    "'{}' needs {} between 0 and 1, found {}",
+                    self.name.text, key, value
+                )));
+            }
+        }
+        Ok((roughness_u, roughness_v))
+    }
+    fn finish(self, argument_count: usize) -> Result<(), SceneFileError> {
+        if let Some(argument) = self.arguments.get(argument_count) {
+            return Err(argument.error(format!(
+                "unexpected argument '{}' for '{}'",
+                argument.text, self.name.text
+            )));
+        }
+        if let Some(property) = self.properties.first() {
+            return Err(property.key.error(format!(
+                "unknown property '{}' for '{}'",
+                property.key.text, self.name.text
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn tokenize_line(line_number: usize, line: &str) -> Vec<Token<'_>> {
+    let content = match line.find('#') {
+        Some(comment_start) => &line[..comment_start],
+        None => line,
+    };
+
+    let mut tokens = Vec::new();
+    let mut start: Option<(usize, usize)> = None;
+    let mut column = 0;
+
+    for (byte_index, char) in content.char_indices() {
+        column += 1;
+        if char.is_whitespace() {
+            if let Some((token_start, token_column)) = start.take() {
+                tokens.push(Token {
+                    text: &content[token_start..byte_index],
+                    line: line_number,
+                    column: token_column,
+                });
+            }
+        } else if start.is_none() {
+            start = Some((byte_index, column));
+        }
+    }
+
+    if let Some((token_start, token_column)) = start {
+        tokens.push(Token {
+            text: &content[token_start..],
+            line: line_number,
+            column: token_column,
+        });
+    }
+
+    tokens
+}
+
+fn parse_directive<'a>(
+    line_number: usize,
+    line: &'a str,
+) -> Result<Option<Directive<'a>>, SceneFileError> {
+    let mut tokens = tokenize_line(line_number, line).into_iter();
+
+    let name = match tokens.next() {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+
+    let mut directive = Directive {
+        name,
+        arguments: Vec::new(),
+        properties: Vec::new(),
+        line: line_number,
+        end_column: line.trim_end().chars().count() + 1,
+    };
+
+    for token in tokens {
+        match token.text.split_once('=') {
+            Some((key, value)) => {
+                if key.is_empty() || value.is_empty() {
+                    return Err(
+                        token.error(format!("expected 'key=value', found '{}'", token.text))
+                    );
+                }
+                if directive
+                    .properties
+                    .iter()
+                    .any(|property| property.key.text == key)
+                {
+                    return Err(token.error(format!("property '{}' given more than once", key)));
+                }
+                directive.properties.push(Property {
+                    key: Token { text: key, ..token },
+                    value: Token {
+                        text: value,
+                        line: token.line,
+                        column: token.column + key.chars().count() + 1,
+                    },
+                });
+            }
+            None => {
+                if !directive.properties.is_empty() {
+                    return Err(token.error(format!(
+                        "unexpected argument '{}' after properties",
+                        token.text
+                    )));
+                }
+                directive.arguments.push(token);
+            }
+        }
+    }
+
+    Ok(Some(directive))
+}
+
+fn parse_number_groups<T>(
+    token: &Token,
+    stride: usize,
+    parse: fn(&Token) -> Result<T, SceneFileError>,
+) -> Result<Vec<Vec<T>>, SceneFileError> {
+    let parts = split_list(token);
+
+    if !parts.len().is_multiple_of(stride) {
+        return Err(token.error(format!(
+            "expected a multiple of {} comma separated numbers, found {}",
+            stride,
+            parts.len()
+        )));
+    }
+
+    parts
+        .chunks(stride)
+        .map(|group| group.iter().map(parse).collect())
+        .collect()
+}
+
+// Exactly `N` comma separated numbers
+fn parse_f32_array<const N: usize>(token: &Token) -> Result<[f32; N], SceneFileError> {
+    split_list(token)
+        .iter()
+        .map(parse_f32)
+        .collect::<Result<Vec<f32>, SceneFileError>>()?
+        .try_into()
+        .map_err(|values: Vec<f32>| {
+            token.error(format!(
+                "expected {} comma separated numbers, found {}",
+                N,
+                values.len()
+            ))
+        })
+}
+
+// `matrix` lists all 16 numbers row by row, otherwise the object is scaled by `scale`, rotated
+// by `rotate` degrees around x, then y and then z, and moved by `translate`
+fn parse_transform(directive: &mut Directive) -> Result<Mat4, SceneFileError> {
+    let translate = directive.take("translate");
+    let rotate = directive.take("rotate");
+    let scale = directive.take("scale");
+
+    let transform = match directive.take("matrix") {
+        Some(token) => {
+            if translate.is_some() || rotate.is_some() || scale.is_some() {
+                return Err(token.error(String::from(
+                    "'matrix' can't be combined with 'translate', 'rotate' or 'scale'",
+                )));
+            }
+
+            let matrix = Mat4::from_cols_array(&parse_f32_array::<16>(&token)?).transpose();
+            if matrix.row(3) != Vec4::W {
+                return Err(token.error(String::from("the last row of 'matrix' must be 0,0,0,1")));
+            }
+            matrix
+        }
+        None => {
+            let translate = match translate {
+                Some(token) => parse_vec3(&token)?,
+                None => Vec3A::ZERO,
+            };
+            let rotate = match rotate {
+                Some(token) => parse_vec3(&token)?,
+                None => Vec3A::ZERO,
+            };
+            // One number scales evenly
+            let scale = match scale {
+                Some(token) if token.text.contains(',') => parse_vec3(&token)?,
+                Some(token) => Vec3A::splat(parse_f32(&token)?),
+                None => Vec3A::ONE,
+            };
+
+            Mat4::from_translation(translate.into())
+                * Mat4::from_rotation_z(rotate.z.to_radians())
+                * Mat4::from_rotation_y(rotate.y.to_radians())
+                * Mat4::from_rotation_x(rotate.x.to_radians())
+                * Mat4::from_scale(scale.into())
+        }
+    };
+
+    if transform.determinant() == 0.0 {
+        return Err(directive.missing(String::from(
+            "transform flattens the shape and can't be undone",
+        )));
+    }
+
+    Ok(transform)
+}
+
+fn parse_mesh(directive: &mut Directive, material: Arc<Material>) -> Result<Mesh, SceneFileError> {
+    let positions = parse_number_groups(&directive.take_required("positions")?, 3, parse_f32)?;
+    let indices = directive.take_required("indices")?;
+    let normals = match directive.take("normals") {
+        Some(token) => Some(parse_number_groups(&token, 3, parse_f32)?),
+        None => None,
+    };
+    let uvs = match directive.take("uvs") {
+        Some(token) => Some(parse_number_groups(&token, 2, parse_f32)?),
+        None => None,
+    };
+
+    let vertex_count = positions.len();
+
+    for (name, count) in [
+        ("normals", normals.as_ref().map(Vec::len)),
+        ("uvs", uvs.as_ref().map(Vec::len)),
+    ] {
+        if let Some(count) = count.filter(|count| *count != vertex_count) {
+            return Err(directive.missing(format!(
+                "'mesh' has {} positions but {} {}",
+                vertex_count, count, name
+            )));
+        }
+    }
+
+    let has_normals = normals.is_some();
+    let has_uvs = uvs.is_some();
+
+    let mut mesh = Mesh::new(
+        positions
+            .iter()
+            .map(|p| Vec3A::new(p[0], p[1], p[2]))
+            .collect(),
+        normals
+            .unwrap_or_default()
+            .iter()
+            .map(|n| Vec3A::new(n[0], n[1], n[2]))
+            .collect(),
+        uvs.unwrap_or_default()
+            .iter()
+            .map(|uv| Vec2::new(uv[0], uv[1]))
+            .collect(),
+        vec![material],
+    );
+
+    let index_tokens = split_list(&indices);
+
+    if !index_tokens.len().is_multiple_of(3) {
+        return Err(indices.error(format!(
+            "expected a multiple of 3 comma separated indices, found {}",
+            index_tokens.len()
+        )));
+    }
+
+    for face in index_tokens.chunks(3) {
+        let mut vertices = [MeshVertex::new(0, None, None); 3];
+
+        for (vertex, index_token) in vertices.iter_mut().zip(face) {
+            let index = parse_u32(index_token)?;
+            if index as usize >= vertex_count {
+                return Err(index_token.error(format!(
+                    "index {} is out of range for a mesh with {} positions",
+                    index, vertex_count
+                )));
+            }
+            *vertex = MeshVertex::new(
+                index,
+                has_normals.then_some(index),
+                has_uvs.then_some(index),
+            );
+        }
+
+        mesh.add_face(MeshFace {
+            vertices,
+            material: 0,
+        });
+    }
+
+    Ok(mesh)
+}
+
+struct SceneBuilder {
+    scene: Scene,
+    camera: Option<CameraDescription>,
+    settings: SceneSettings,
+    materials: HashMap<String, Arc<Material>>,
+    textures: HashMap<String, Arc<Texture>>,
+    objects: HashMap<String, Arc<BVHNode>>,
+    // Shapes go into this object instead of the scene between `object` and `end`
+    object: Option<ObjectDefinition>,
+    directory: PathBuf,
+}
+
+struct ObjectDefinition {
+    name: String,
+    line: usize,
+    column: usize,
+    colliders: Vec<Arc<Collider>>,
+}
+
+impl SceneBuilder {
+    fn new(directory: &Path) -> SceneBuilder {
+        SceneBuilder {
+            scene: Scene::new(),
+            camera: None,
+            settings: SceneSettings {
+                image_width: DEFAULT_IMAGE_WIDTH,
+                image_height: DEFAULT_IMAGE_HEIGHT,
+                samples_per_pixel_side_values: DEFAULT_SAMPLES_PER_PIXEL_SIDE_VALUES.to_vec(),
+                render: RenderSettings {
+                    max_ray_depth: DEFAULT_MAX_RAY_DEPTH,
+                    bvh_build_strategy: BVHBuildStrategy::SurfaceAreaHeuristic,
+                    chunk_size: DEFAULT_CHUNK_SIZE,
+                    seed: DEFAULT_SEED,
+                    sampler: SamplerKind::Sobol,
+                    filter: Filter::default(),
+                    adaptive: None,
+                },
+                output: OutputSettings {
+                    path: String::from(DEFAULT_OUTPUT_PATH),
+                    format: ImageFormat::Png,
+                    display: DisplayTransform::default(),
+                },
+                heatmap: None,
+            },
+            materials: HashMap::new(),
+            textures: HashMap::new(),
+            objects: HashMap::new(),
+            object: None,
+            directory: directory.to_path_buf(),
+        }
+    }
+
+    fn add_shape(&mut self, shape: Arc<Collider>) {
+        match &mut self.object {
+            Some(object) => object.colliders.push(shape),
+            None => self.scene.add_shape(shape),
+        }
+    }
+
+    fn add_mesh(&mut self, mesh: &Arc<Mesh>, faces: Range<usize>, transform: Mat4) {
+        match &mut self.object {
+            Some(object) if transform == Mat4::IDENTITY => object
+                .colliders
+                .extend(Mesh::triangle_colliders(mesh, faces)),
+            Some(object) => {
+                let tree = BVHNode::build_tree(
+                    Mesh::triangle_colliders(mesh, faces),
+                    0.0,
+                    1.0,
+                    BVHBuildStrategy::SurfaceAreaHeuristic,
+                    DEFAULT_SEED,
+                );
+                let instance = Instance::new(Arc::new(tree), transform);
+                object.colliders.push(Arc::new(InstanceCollider(instance)));
+            }
+            None => {
+                let index = self.scene.add_mesh(mesh, faces);
+                self.scene.set_mesh_transform(index, transform);
+            }
+        }
+    }
+
+    fn material(&self, token: &Token) -> Result<Arc<Material>, SceneFileError> {
+        self.materials
+            .get(token.text)
+            .cloned()
+            .ok_or_else(|| token.error(format!("unknown material '{}'", token.text)))
+    }
+
+    fn object(&self, token: &Token) -> Result<Arc<BVHNode>, SceneFileError> {
+        self.objects
+            .get(token.text)
+            .cloned()
+            .ok_or_else(|| token.error(format!("unknown object '{}'", token.text)))
+    }
+
+    // Either a colour like `0.5,0.5,0.5` or the name of a texture
+    fn texture(&self, token: &Token) -> Result<Arc<Texture>, SceneFileError> {
+        if !token.text.starts_with(|c: char| c.is_alphabetic()) {
+            return Ok(Arc::new(Texture::Constant(parse_color(token)?)));
+        }
+
+        self.textures
+            .get(token.text)
+            .cloned()
+            .ok_or_else(|| token.error(format!("unknown texture '{}'", token.text)))
+    }
+
+    // A single number, or a texture by name whose channels are averaged
+    fn scalar_texture(&self, token: &Token) -> Result<Arc<Texture>, SceneFileError> {
+        if !token.text.starts_with(|c: char| c.is_alphabetic()) {
+            return Ok(constant_texture(parse_f32(token)?));
+        }
+
+        self.texture(token)
+    }
+
+    fn apply(&mut self, mut directive: Directive) -> Result<(), SceneFileError> {
+        match directive.name.text {
+            "image" => {
+                let width = directive.take_required("width")?;
+                let height = directive.take_required("height")?;
+                self.settings.image_width = parse_u32(&width)?;
+                self.settings.image_height = parse_u32(&height)?;
+                if self.settings.image_width == 0 {
+                    return Err(width.error(String::from("image width must be above zero")));
+                }
+                if self.settings.image_height == 0 {
+                    return Err(height.error(String::from("image height must be above zero")));
+                }
+                directive.finish(0)
+            }
+            "render" => {
+                if let Some(samples) = directive.take("samples") {
+                    let values = split_list(&samples)
+                        .iter()
+                        .map(|value| match parse_u32(value)? {
+                            0 => Err(value.error(String::from("samples must be above zero"))),
+                            value => Ok(value),
+                        })
+                        .collect::<Result<Vec<u32>, SceneFileError>>()?;
+                    self.settings.samples_per_pixel_side_values = values;
+                }
+                if let Some(max_depth) = directive.take("max_depth") {
+                    self.settings.render.max_ray_depth = match parse_u32(&max_depth)? {
+                        0 => {
+                            return Err(
+                                max_depth.error(String::from("max_depth must be above zero"))
+                            )
+                        }
+                        value => value,
+                    };
+                }
+                if let Some(chunk_size) = directive.take("chunk_size") {
+                    self.settings.render.chunk_size = match parse_u32(&chunk_size)? {
+                        0 => {
+                            return Err(chunk_size.error(String::from(
+                                "chunk size must be above zero",
+                            )))
+                        }
+                        value => value,
+                    };
+                }
+                if let Some(seed) = directive.take("seed") {
+                    self.settings.render.seed = seed.text.parse::<u64>().map_err(|_| {
+                        seed.error(format!("expected a whole number, found '{}'", seed.text))
+                    })?;
+                }
+                if let Some(sampler) = directive.take("sampler") {
+                    self.settings.render.sampler =
+                        SamplerKind::from_name(sampler.text).ok_or_else(|| {
+                            sampler.error(format!(
+                                "unknown sampler '{}', expected {}",
+                                sampler.text, SAMPLER_NAMES
+                            ))
+                        })?;
+                }
+                if let Some(bvh) = directive.take("bvh") {
+                    self.settings.render.bvh_build_strategy = match bvh.text {
+                        "sah" => BVHBuildStrategy::SurfaceAreaHeuristic,
+                        "median" => BVHBuildStrategy::RandomMedian,
+                        _ => {
+                            return Err(bvh.error(format!(
+                                "unknown BVH builder '{}', expected 'sah' or 'median'",
+                                bvh.text
+                            )))
+                        }
+                    };
+                }
+                directive.finish(0)
+            }
+            "output" => {
+                if let Some(path) = directive.take("path") {
+                    self.settings.output.path = String::from(path.text);
+                    self.settings.output.format = parse_image_format(&path)?;
+                }
+                if let Some(precision) = directive.take("precision") {
+                    let pixel_type = match precision.text {
+                        "half" => ExrPixelType::Half,
+                        "float" => ExrPixelType::Float,
+                        _ => {
+                            return Err(precision.error(format!(
+                                "unknown precision '{}', expected 'half' or 'float'",
+                                precision.text
+                            )))
+                        }
+                    };
+                    if !matches!(self.settings.output.format, ImageFormat::OpenExr(_)) {
+                        return Err(precision.error(String::from(
+                            "precision can only be set for .exr output",
+                        )));
+                    }
+                    self.settings.output.format = ImageFormat::OpenExr(pixel_type);
+                }
+                if let Some(heatmap) = directive.take("heatmap") {
+                    self.settings.heatmap = Some(OutputSettings::heatmap(
+                        String::from(heatmap.text),
+                        parse_image_format(&heatmap)?,
+                    ));
+                }
+                directive.finish(0)
+            }
+            "adaptive" => {
+                let defaults = AdaptiveSettings::default();
+                let adaptive = AdaptiveSettings {
+                    threshold: directive.optional_f32("threshold", defaults.threshold)?,
+                    min_samples: directive.optional_u32("min_samples", defaults.min_samples)?,
+                    max_samples: directive.optional_u32("max_samples", defaults.max_samples)?,
+                };
+                if let Err(message) = adaptive.validate() {
+                    return Err(directive.name.error(message));
+                }
+                self.settings.render.adaptive = Some(adaptive);
+                directive.finish(0)
+            }
+            "filter" => {
+                let name = directive.argument(0, "filter type")?;
+                let kind = FilterKind::from_name(name.text).ok_or_else(|| {
+                    name.error(format!(
+                        "unknown filter '{}', expected {}",
+                        name.text, FILTER_NAMES
+                    ))
+                })?;
+                let filter = Filter {
+                    kind,
+                    radius: directive.optional_f32("radius", kind.default_radius())?,
+                };
+                if let Err(message) = filter.validate() {
+                    return Err(directive.name.error(message));
+                }
+                self.settings.render.filter = filter;
+                directive.finish(1)
+            }
+            "display" => {
+                let display = &mut self.settings.output.display;
+                if let Some(tone_map) = directive.take("tone_map") {
+                    display.tone_mapping = match tone_map.text {
+                        "clamp" => ToneMapping::Clamp,
+                        "reinhard" => ToneMapping::Reinhard,
+                        "aces" => ToneMapping::Aces,
+                        _ => {
+                            return Err(tone_map.error(format!(
+                                "unknown tone mapping '{}', expected 'clamp', 'reinhard' or 'aces'",
+                                tone_map.text
+                            )))
+                        }
+                    };
+                }
+                display.exposure = directive.optional_f32("exposure", display.exposure)?;
+                if let Some(srgb) = directive.take("srgb") {
+                    display.srgb = parse_bool(&srgb)?;
+                }
+                directive.finish(0)
+            }
+            "background" => {
+                let kind = directive.argument(0, "type")?;
+                self.scene.background = match kind.text {
+                    "sky" => Background::Sky,
+                    "solid" => Background::Solid(directive.required_color("color")?),
+                    _ => return Err(kind.error(format!("unknown background type '{}'", kind.text))),
+                };
+                directive.finish(1)
+            }
+            "camera" => {
+                if self.camera.is_some() {
+                    return Err(directive
+                        .name
+                        .error(String::from("camera defined more than once")));
+                }
+                self.camera = Some(CameraDescription {
+                    position: directive.required_vec3("position")?,
+                    look_at: directive.required_vec3("look_at")?,
+                    up: directive.optional_vec3("up", Vec3A::new(0.0, 1.0, 0.0))?,
+                    field_of_view: directive.required_f32("fov")?,
+                    aperture: directive.optional_f32("aperture", 0.0)?,
+                    shutter: directive.optional_f32("shutter", 0.0)?,
+                });
+                directive.finish(0)
+            }
+            "texture" => {
+                let name = directive.argument(0, "name")?;
+                let kind = directive.argument(1, "type")?;
+
+                if self.textures.contains_key(name.text) {
+                    return Err(
+                        name.error(format!("texture '{}' defined more than once", name.text))
+                    );
+                }
+
+                let texture = match kind.text {
+                    "image" => {
+                        let path = directive.take_required("path")?;
+                        let wrap = match directive.take("wrap") {
+                            Some(wrap) => WrapMode::from_name(wrap.text).ok_or_else(|| {
+                                wrap.error(format!(
+                                    "unknown wrap mode '{}', expected 'repeat' or 'clamp'",
+                                    wrap.text
+                                ))
+                            })?,
+                            None => WrapMode::Repeat,
+                        };
+                        let srgb = match directive.take("srgb") {
+                            Some(srgb) => parse_bool(&srgb)?,
+                            None => true,
+                        };
+
+                        let image =
+                            ImageTexture::load(&self.directory.join(path.text), wrap, srgb)
+                                .map_err(|error| {
+                                    path.error(format!(
+                                        "could not load texture '{}': {}",
+                                        path.text, error
+                                    ))
+                                })?;
+                        Texture::Image(image)
+                    }
+                    "checker" => {
+                        let even = match directive.take("even") {
+                            Some(even) => self.texture(&even)?,
+                            None => Arc::new(Texture::Constant(Color::one())),
+                        };
+                        let odd = match directive.take("odd") {
+                            Some(odd) => self.texture(&odd)?,
+                            None => Arc::new(Texture::Constant(Color::zero())),
+                        };
+                        let size = directive.optional_f32("size", 1.0)?;
+                        if size <= 0.0 {
+                            return Err(directive
+                                .name
+                                .error(String::from("checker size must be above zero")));
+                        }
+                        Texture::Checker(CheckerTexture { even, odd, size })
+                    }
+                    pattern_name => match NoisePattern::from_name(pattern_name) {
+                        Some(pattern) => {
+                            let seed = match directive.take("seed") {
+                                Some(seed) => seed.text.parse::<u64>().map_err(|_| {
+                                    seed.error(format!(
+                                        "expected a whole number, found '{}'",
+                                        seed.text
+                                    ))
+                                })?,
+                                None => DEFAULT_SEED,
+                            };
+                            let mut noise = NoiseTexture::new(pattern, seed);
+                            noise.scale = directive.optional_f32("scale", noise.scale)?;
+                            noise.octaves = directive.optional_u32("octaves", noise.octaves)?;
+                            noise.strength = directive.optional_f32("strength", noise.strength)?;
+                            if let Some(low) = directive.take("low") {
+                                noise.low = parse_color(&low)?;
+                            }
+                            if let Some(high) = directive.take("high") {
+                                noise.high = parse_color(&high)?;
+                            }
+                            Texture::Noise(noise)
+                        }
+                        None => {
+                            return Err(kind.error(format!(
+                                "unknown texture type '{}', expected 'image', 'checker', \
+                                 'noise', 'turbulence', 'marble' or 'wood'",
+                                kind.text
+                            )))
+                        }
+                    },
+                };
+
+                self.textures
+                    .insert(String::from(name.text), Arc::new(texture));
+                directive.finish(2)
+            }
+            "material" => {
+                let name = directive.argument(0, "name")?;
+                let kind = directive.argument(1, "type")?;
+
+                if self.materials.contains_key(name.text) {
+                    return Err(
+                        name.error(format!("material '{}' defined more than once", name.text))
+                    );
+                }
+
+                let material = match kind.text {
+                    "lambertian" => MaterialLambertian(Lambertian::with_texture(
+                        self.texture(&directive.take_required("albedo")?)?,
+                    )),
+                    "metal" => MaterialMetal(Metal::with_texture(
+                        self.texture(&directive.take_required("albedo")?)?,
+                        directive.optional_f32("smoothness", 1.0)?,
+                    )),
+                    "emissive" => MaterialEmissive(Emissive::new(
+                        directive.required_color("color")?,
+                        directive.optional_f32("strength", 1.0)?,
+                    )),
+                    "dielectric" => MaterialDielectric(Dielectric::new(
+                        directive.required_f32("refraction_index")?,
+                        directive.optional_f32("transparency", 1.0)?,
+                    )),
+                    "conductor" => {
+                        let ior = match directive.take("metal") {
+                            Some(metal) => {
+                                ConductorIor::from_name(metal.text).ok_or_else(|| {
+                                    metal.error(format!(
+                                        "unknown metal '{}', expected {}",
+                                        metal.text, METAL_NAMES
+                                    ))
+                                })?
+                            }
+                            None => ConductorIor {
+                                eta: directive.required_color("eta")?,
+                                k: directive.required_color("k")?,
+                            },
+                        };
+                        let (roughness_u, roughness_v) = directive.roughness()?;
+                        MaterialConductor(Conductor::new(ior, roughness_u, roughness_v))
+                    }
+                    "principled" => {
+                        let base_color = match directive.take("base_color") {
+                            Some(base_color) => self.texture(&base_color)?,
+                            None => Arc::new(Texture::Constant(Color::new(0.8, 0.8, 0.8))),
+                        };
+                        let mut principled = Principled::new(base_color);
+                        for (key, parameter) in [
+                            ("metallic", &mut principled.metallic),
+                            ("roughness", &mut principled.roughness),
+                            ("specular", &mut principled.specular),
+                            ("sheen", &mut principled.sheen),
+                            ("clearcoat", &mut principled.clearcoat),
+                            ("clearcoat_roughness", &mut principled.clearcoat_roughness),
+                            ("transmission", &mut principled.transmission),
+                            ("ior", &mut principled.ior),
+                        ] {
+                            if let Some(token) = directive.take(key) {
+                                *parameter = self.scalar_texture(&token)?;
+                            }
+                        }
+                        MaterialPrincipled(principled)
+                    }
+                    "rough_dielectric" => {
+                        let refraction_index = directive.required_f32("refraction_index")?;
+                        let tint = directive.optional_color("tint", Color::one())?;
+                        let (roughness_u, roughness_v) = directive.roughness()?;
+                        MaterialRoughDielectric(RoughDielectric::new(
+                            refraction_index,
+                            tint,
+                            roughness_u,
+                            roughness_v,
+                        ))
+                    }
+                    _ => return Err(kind.error(format!("unknown material type '{}'", kind.text))),
+                };
+
+                self.materials
+                    .insert(String::from(name.text), Arc::new(material));
+                directive.finish(2)
+            }
+            "sphere" => {
+                let centre = directive.required_vec3("centre")?;
+                let radius = directive.required_f32("radius")?;
+                let material = self.material(&directive.take_required("material")?)?;
+                let velocity = directive.optional_vec3("velocity", Vec3A::ZERO)?;
+
+                let sphere = Sphere::new(centre.x, centre.y, centre.z, radius, material, velocity);
+                self.add_shape(Arc::new(SphereCollider(sphere)));
+                directive.finish(0)
+            }
+            "plane" => {
+                let point = directive.required_vec3("point")?;
+                let normal_token = directive.take_required("normal")?;
+                let normal = parse_vec3(&normal_token)?;
+                let material = self.material(&directive.take_required("material")?)?;
+
+                if normal.length_squared() == 0.0 {
+                    return Err(normal_token.error(String::from("plane normal can't be zero")));
+                }
+
+                let plane = Plane::new(point, normal, material);
+                self.add_shape(Arc::new(PlaneCollider(plane)));
+                directive.finish(0)
+            }
+            "quad" => {
+                let corner = directive.required_vec3("corner")?;
+                let u = directive.required_vec3("u")?;
+                let v_token = directive.take_required("v")?;
+                let v = parse_vec3(&v_token)?;
+                let material = self.material(&directive.take_required("material")?)?;
+
+                if u.cross(v).length_squared() == 0.0 {
+                    return Err(v_token.error(String::from(
+                        "quad sides 'u' and 'v' must be non-zero and not parallel",
+                    )));
+                }
+
+                let quad = Quad::new(corner, u, v, material);
+                self.add_shape(Arc::new(QuadCollider(quad)));
+                directive.finish(0)
+            }
+            "disk" => {
+                let centre = directive.required_vec3("centre")?;
+                let normal_token = directive.take_required("normal")?;
+                let normal = parse_vec3(&normal_token)?;
+                let radius_token = directive.take_required("radius")?;
+                let radius = parse_f32(&radius_token)?;
+                let material = self.material(&directive.take_required("material")?)?;
+
+                if normal.length_squared() == 0.0 {
+                    return Err(normal_token.error(String::from("disk normal can't be zero")));
+                }
+                if radius <= 0.0 {
+                    return Err(radius_token.error(String::from("disk radius must be above zero")));
+                }
+
+                let disk = Disk::new(centre, normal, radius, material);
+                self.add_shape(Arc::new(DiskCollider(disk)));
+                directive.finish(0)
+            }
+            "box" => {
+                let minimum = directive.required_vec3("min")?;
+                let maximum_token = directive.take_required("max")?;
+                let maximum = parse_vec3(&maximum_token)?;
+                let material = self.material(&directive.take_required("material")?)?;
+
+                if minimum.cmpeq(maximum).any() {
+                    return Err(maximum_token
+                        .error(String::from("box corners must differ along every axis")));
+                }
+
+                let cuboid = Cuboid::new(minimum, maximum, material);
+                self.add_shape(Arc::new(CuboidCollider(cuboid)));
+                directive.finish(0)
+            }
+            "cylinder" | "cone" => {
+                let base = directive.required_vec3("base")?;
+                let end_key = if directive.name.text == "cylinder" {
+                    "top"
+                } else {
+                    "apex"
+                };
+                let end_token = directive.take_required(end_key)?;
+                let end = parse_vec3(&end_token)?;
+                let radius_token = directive.take_required("radius")?;
+                let radius = parse_f32(&radius_token)?;
+                let capped = match directive.take("capped") {
+                    Some(capped) => parse_bool(&capped)?,
+                    None => true,
+                };
+                let material = self.material(&directive.take_required("material")?)?;
+                let velocity = directive.optional_vec3("velocity", Vec3A::ZERO)?;
+
+                if base == end {
+                    return Err(end_token.error(format!(
+                        "{} '{}' must differ from its 'base'",
+                        directive.name.text, end_key
+                    )));
+                }
+                if radius <= 0.0 {
+                    return Err(radius_token
+                        .error(format!("{} radius must be above zero", directive.name.text)));
+                }
+
+                let collider = if directive.name.text == "cylinder" {
+                    CylinderCollider(Cylinder::new(base, end, radius, capped, material, velocity))
+                } else {
+                    ConeCollider(Cone::new(base, end, radius, capped, material, velocity))
+                };
+                self.add_shape(Arc::new(collider));
+                directive.finish(0)
+            }
+            "torus" => {
+                let centre = directive.required_vec3("centre")?;
+                let axis_token = directive.take_required("axis")?;
+                let axis = parse_vec3(&axis_token)?;
+                let major_token = directive.take_required("major_radius")?;
+                let major_radius = parse_f32(&major_token)?;
+                let minor_token = directive.take_required("minor_radius")?;
+                let minor_radius = parse_f32(&minor_token)?;
+                let material = self.material(&directive.take_required("material")?)?;
+                let velocity = directive.optional_vec3("velocity", Vec3A::ZERO)?;
+
+                if axis.length_squared() == 0.0 {
+                    return Err(axis_token.error(String::from("torus axis can't be zero")));
+                }
+                if major_radius <= 0.0 {
+                    return Err(
+                        major_token.error(String::from("torus major_radius must be above zero"))
+                    );
+                }
+                if minor_radius <= 0.0 {
+                    return Err(
+                        minor_token.error(String::from("torus minor_radius must be above zero"))
+                    );
+                }
+
+                let torus =
+                    Torus::new(centre, axis, major_radius, minor_radius, material, velocity);
+                self.add_shape(Arc::new(TorusCollider(torus)));
+                directive.finish(0)
+            }
+            "quadric" => {
+                let centre = directive.optional_vec3("centre", Vec3A::ZERO)?;
+                let coefficients_token = directive.take_required("coefficients")?;
+                let coefficients = parse_f32_array::<10>(&coefficients_token)?;
+                let bounds = match (directive.take("min"), directive.take("max")) {
+                    (Some(minimum_token), Some(maximum_token)) => {
+                        let minimum = parse_vec3(&minimum_token)?;
+                        let maximum = parse_vec3(&maximum_token)?;
+                        if minimum.cmpge(maximum).any() {
+                            return Err(maximum_token.error(String::from(
+                                "quadric 'max' must be above 'min' along every axis",
+                            )));
+                        }
+                        Some(Aabb::new(minimum, maximum))
+                    }
+                    (None, None) => None,
+                    _ => {
+                        return Err(directive.missing(String::from(
+                            "'quadric' needs both 'min' and 'max' or neither of them",
+                        )))
+                    }
+                };
+                let material = self.material(&directive.take_required("material")?)?;
+                let velocity = directive.optional_vec3("velocity", Vec3A::ZERO)?;
+
+                if coefficients[..9].iter().all(|&value| value == 0.0) {
+                    return Err(coefficients_token.error(String::from(
+                        "quadric needs a non-zero coefficient besides 'j'",
+                    )));
+                }
+
+                let quadric = Quadric::new(centre, coefficients, bounds, material, velocity);
+                self.add_shape(Arc::new(QuadricCollider(quadric)));
+                directive.finish(0)
+            }
+            "mesh" => {
+                let material = self.material(&directive.take_required("material")?)?;
+                let mesh = Arc::new(parse_mesh(&mut directive, material)?);
+                let transform = parse_transform(&mut directive)?;
+                self.add_mesh(&mesh, 0..mesh.face_count(), transform);
+                directive.finish(0)
+            }
+            "obj" => {
+                let path = directive.take_required("path")?;
+                let material = match directive.take("material") {
+                    Some(material) => self.material(&material)?,
+                    None => Arc::new(MaterialLambertian(Lambertian::new(Color::new(
+                        0.5, 0.5, 0.5,
+                    )))),
+                };
+                let group = directive.take("group");
+                let transform = parse_transform(&mut directive)?;
+
+                let model = load_obj_file(&self.directory.join(path.text), material)
+                    .map_err(|error| path.error(error.to_string()))?;
+
+                match group {
+                    Some(group) => {
+                        let mut found = false;
+                        for obj_group in model.groups.iter().filter(|g| g.name == group.text) {
+                            found = true;
+                            self.add_mesh(&model.mesh, obj_group.faces.clone(), transform);
+                        }
+                        if !found {
+                            return Err(group.error(format!(
+                                "'{}' has no group named '{}'",
+                                path.text, group.text
+                            )));
+                        }
+                    }
+                    None => self.add_mesh(&model.mesh, 0..model.mesh.face_count(), transform),
+                }
+                directive.finish(0)
+            }
+            "triangle" => {
+                let vertices = [
+                    directive.required_vec3("v0")?,
+                    directive.required_vec3("v1")?,
+                    directive.required_vec3("v2")?,
+                ];
+                let material = self.material(&directive.take_required("material")?)?;
+                let normals = directive.optional_vec3_list(["n0", "n1", "n2"])?;
+                let uvs = directive.optional_vec2_list(["uv0", "uv1", "uv2"])?;
+
+                let triangle = Triangle::new(vertices, normals, uvs, material);
+                self.add_shape(Arc::new(TriangleCollider(triangle)));
+                directive.finish(0)
+            }
+            "object" => {
+                let name = directive.argument(0, "name")?;
+
+                if let Some(object) = &self.object {
+                    return Err(directive.name.error(format!(
+                        "objects can't be nested, '{}' is still missing its 'end'",
+                        object.name
+                    )));
+                }
+
+                self.object = Some(ObjectDefinition {
+                    name: String::from(name.text),
+                    line: directive.name.line,
+                    column: directive.name.column,
+                    colliders: Vec::new(),
+                });
+                directive.finish(1)
+            }
+            "end" => {
+                let object = self.object.take().ok_or_else(|| {
+                    directive
+                        .name
+                        .error(String::from("'end' without an 'object' to finish"))
+                })?;
+
+                if object.colliders.is_empty() {
+                    return Err(directive
+                        .name
+                        .error(format!("object '{}' has no shapes", object.name)));
+                }
+
+                // Built once however often the object is used, and always with the surface area
+                // heuristic as the object doesn't change between renders
+                let tree = BVHNode::build_tree(
+                    object.colliders,
+                    0.0,
+                    1.0,
+                    BVHBuildStrategy::SurfaceAreaHeuristic,
+                    DEFAULT_SEED,
+                );
+                self.objects.insert(object.name, Arc::new(tree));
+                directive.finish(0)
+            }
+            "instance" => {
+                let object = self.object(&directive.argument(0, "object name")?)?;
+                let transform = parse_transform(&mut directive)?;
+
+                let instance = Instance::new(object, transform);
+                self.add_shape(Arc::new(InstanceCollider(instance)));
+                directive.finish(1)
+            }
+            "csg" => {
+                let operation = directive.argument(0, "operation")?;
+                let operation = CsgOperation::from_name(operation.text).ok_or_else(|| {
+                    operation.error(format!(
+                        "unknown csg operation '{}', expected {}",
+                        operation.text, CSG_OPERATION_NAMES
+                    ))
+                })?;
+                let left = self.object(&directive.take_required("left")?)?;
+                let right = self.object(&directive.take_required("right")?)?;
+
+                let operand =
+                    |object| Arc::new(InstanceCollider(Instance::new(object, Mat4::IDENTITY)));
+                let csg = Csg::new(operation, operand(left), operand(right));
+                self.add_shape(Arc::new(CsgCollider(csg)));
+                directive.finish(1)
+            }
+            "generate" => {
+                let generator = directive.argument(0, "generator name")?;
+                let seed = match directive.take("seed") {
+                    Some(seed) => seed.text.parse::<u64>().map_err(|_| {
+                        seed.error(format!("expected a whole number, found '{}'", seed.text))
+                    })?,
+                    None => DEFAULT_SEED,
+                };
+                let generated = match generator.text {
+                    "random_balls" => make_random_balls_scene(seed),
+                    "procedural_textures" => make_procedural_textures_scene(seed),
+                    _ => {
+                        return Err(
+                            generator.error(format!("unknown generator '{}'", generator.text))
+                        )
+                    }
+                };
+                for collider in generated.colliders {
+                    self.add_shape(collider);
+                }
+                directive.finish(1)
+            }
+            _ => Err(directive
+                .name
+                .error(format!("unknown directive '{}'", directive.name.text))),
+        }
+    }
+
+    fn build(self, line_count: usize) -> Result<SceneDescription, SceneFileError> {
+        if let Some(object) = &self.object {
+            return Err(SceneFileError::Parse {
+                line: object.line,
+                column: object.column,
+                message: format!("object '{}' is missing its 'end'", object.name),
+            });
+        }
+
+        let camera = self.camera.ok_or(SceneFileError::Parse {
+            line: line_count.max(1),
+            column: 1,
+            message: String::from("scene has no camera"),
+        })?;
+
+        if self.scene.is_empty() {
+            return Err(SceneFileError::Parse {
+                line: line_count.max(1),
+                column: 1,
+                message: String::from("scene has no shapes"),
+            });
+        }
+
+        Ok(SceneDescription {
+            camera: Camera::new(
+                self.settings.image_width,
+                self.settings.image_height,
+                camera.position,
+                camera.look_at,
+                camera.up,
+                camera.field_of_view,
+                camera.aperture,
+                camera.shutter,
+            ),
+            scene: self.scene,
+            settings: self.settings,
+        })
+    }
+}
+
+// Paths inside the scene, such as OBJ files, are resolved relative to `directory`
+pub fn parse_scene(source: &str, directory: &Path) -> Result<SceneDescription, SceneFileError> {
+    let mut builder = SceneBuilder::new(directory);
+    let mut line_count = 0;
+
+    for (index, line) in source.lines().enumerate() {
+        line_count = index + 1;
+        if let Some(directive) = parse_directive(line_count, line)? {
+            builder.apply(directive)?;
+        }
+    }
+
+    builder.build(line_count)
+}
+
+pub fn load_scene_file(path: &str) -> Result<SceneDescription, SceneFileError> {
+    let source = std::fs::read_to_string(path).map_err(|error| SceneFileError::Io {
+        path: String::from(path),
+        error,
+    })?;
+
+    let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+    parse_scene(&source, directory)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{parse_scene, tokenize_line, SceneDescription, SceneFileError};
+
+    const CAMERA: &str = "camera position=0,1,5 look_at=0,0,0 fov=40";
+
+    fn parse(source: &str) -> Result<SceneDescription, SceneFileError> {
+        parse_scene(source, Path::new(""))
+    }
+
+    // The line, column and message of the error `source` fails with
+    fn parse_error(source: &str) -> (usize, usize, String) {
+        match parse(source) {
+            Ok(_) => panic!("scene parsed:\n{}", source),
+            Err(SceneFileError::Parse {
+                line,
+                column,
+                message,
+            }) => (line, column, message),
+            Err(error) => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn tokens_keep_their_columns() {
+        let tokens = tokenize_line(3, "  sphere  radius=1 # comment");
+        let tokens: Vec<_> = tokens
+            .iter()
+            .map(|token| (token.text, token.line, token.column))
+            .collect();
+        assert_eq!(tokens, [("sphere", 3, 3), ("radius=1", 3, 11)]);
+    }
+
+    #[test]
+    fn minimal_scene() {
+        let source = format!(
+            "# A sphere on its own\n\
+             image width=64 height=48\n\
+             render samples=1,2 max_depth=8\n\
+             {}\n\
+             material red lambertian albedo=0.8,0.1,0.1\n\
+             sphere centre=0,0,0 radius=1 material=red\n",
+            CAMERA
+        );
+        let scene_description = parse(&source).unwrap();
+
+        assert_eq!(scene_description.settings.image_width, 64);
+        assert_eq!(scene_description.settings.image_height, 48);
+        assert_eq!(
+            scene_description.settings.samples_per_pixel_side_values,
+            [1, 2]
+        );
+        assert_eq!(scene_description.settings.render.max_ray_depth, 8);
+        assert_eq!(scene_description.scene.colliders.len(), 1);
+    }
+
+    #[test]
+    fn unknown_directive() {
+        let (line, column, message) = parse_error(&format!("{}\n  spehre radius=1", CAMERA));
+        assert_eq!((line, column), (2, 3));
+        assert_eq!(message, "unknown directive 'spehre'");
+    }
+
+    #[test]
+    fn missing_required_property() {
+        let (line, column, message) = parse_error(&format!(
+            "{}\nmaterial red lambertian albedo=1,0,0\nsphere centre=0,0,0 material=red",
+            CAMERA
+        ));
+        // Reported just past the end of the line
+        assert_eq!((line, column), (3, 33));
+        assert_eq!(message, "'sphere' is missing required property 'radius'");
+    }
+
+    #[test]
+    fn bad_number() {
+        let (line, column, message) = parse_error(&format!(
+            "{}\nmaterial red lambertian albedo=1,0,0\nsphere centre=0,0,0 radius=one material=red",
+            CAMERA
+        ));
+        // Points at the value, not the key
+        assert_eq!((line, column), (3, 28));
+        assert_eq!(message, "expected a number, found 'one'");
+    }
+
+    #[test]
+    fn unknown_material() {
+        let (line, column, message) = parse_error(&format!(
+            "{}\nsphere centre=0,0,0 radius=1 material=red",
+            CAMERA
+        ));
+        assert_eq!((line, column), (2, 39));
+        assert_eq!(message, "unknown material 'red'");
+    }
+
+    #[test]
+    fn unknown_texture() {
+        let (line, column, message) = parse_error(&format!(
+            "{}\nmaterial red lambertian albedo=bricks",
+            CAMERA
+        ));
+        assert_eq!((line, column), (2, 32));
+        assert_eq!(message, "unknown texture 'bricks'");
+    }
+
+    #[test]
+    fn unknown_object() {
+        let (line, column, message) = parse_error(&format!("{}\ninstance chair", CAMERA));
+        assert_eq!((line, column), (2, 10));
+        assert_eq!(message, "unknown object 'chair'");
+    }
+
+    #[test]
+    fn missing_camera_and_shapes() {
+        let (line, _, message) = parse_error("image width=64 height=48\n");
+        assert_eq!(line, 1);
+        assert_eq!(message, "scene has no camera");
+
+        let (_, _, message) = parse_error(CAMERA);
+        assert_eq!(message, "scene has no shapes");
+    }
+}
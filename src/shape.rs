@@ -1,10 +1,10 @@
 use std::sync::Arc;
 
-use glam::Vec3A;
+use glam::{Mat4, Vec2, Vec3A};
 
-use crate::{ray::{Ray, RayCollision, RayCollider, collide_ray_with_sphere}, material::materials::Material};
+use crate::{ray::{Ray, RayCollision, collide_ray_with_plane, collide_ray_with_sphere, collide_ray_with_triangle}, material::materials::Material, collider::Collider, maths::{is_vec3_af32_near_zero, orthonormal_basis, sample_barycentric, sample_direction_in_cone, sample_unit_disk, solve_quadratic, solve_quartic}};
 
-use super::bounding_box::{build_surrounding_bounding_box, BoundingBox, Aabb};
+use super::bounding_box::{build_surrounding_bounding_box, Aabb};
 
 // pub trait Collidable {
 //     fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision>;
@@ -29,6 +29,24 @@ use super::bounding_box::{build_surrounding_bounding_box, BoundingBox, Aabb};
 //     }
 // }
 
+// A direction towards a point on a shape, `pdf` is with respect to solid angle from the origin
+pub struct ShapeSample {
+    pub direction: Vec3A,
+    pub pdf: f32,
+}
+
+// Longitude and latitude of a point on the unit sphere, u goes once around the y axis starting
+// from -x and v runs from the bottom pole to the top
+fn sphere_uv(normal: Vec3A) -> Vec2 {
+    let theta = (-normal.y).clamp(-1.0, 1.0).acos();
+    let phi = (-normal.z).atan2(normal.x) + std::f32::consts::PI;
+
+    Vec2::new(
+        phi / (2.0 * std::f32::consts::PI),
+        theta / std::f32::consts::PI,
+    )
+}
+
 pub struct Sphere {
     pub centre: Vec3A,
     pub radius: f32,
@@ -67,13 +85,54 @@ impl Sphere {
                 let hit_point = ray.at(root);
                 let normal = (hit_point - centre) / self.radius;
 
-                let hit = RayCollision::new(hit_point, normal, root, ray, self.material.clone());
+                let mut hit = RayCollision::new(hit_point, normal, root, ray, self.material.clone());
+                hit.set_uv(sphere_uv(normal));
+                // Around the y axis, the way u increases
+                hit.set_tangent(Vec3A::new(normal.z, 0.0, -normal.x));
 
                 Some(hit)
             }
         }
     }
 
+    fn cone_pdf(&self, origin: Vec3A, centre: Vec3A) -> Option<f32> {
+        let distance_squared = origin.distance_squared(centre);
+        let radius_squared = self.radius * self.radius;
+
+        if distance_squared <= radius_squared {
+            return None;
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        Some(1.0 / (2.0 * std::f32::consts::PI * (1.0 - cos_theta_max)))
+    }
+
+    // Samples the cone of directions the sphere covers as seen from `origin`
+    pub fn sample_towards(&self, origin: Vec3A, time: f32, u: Vec2) -> Option<ShapeSample> {
+        let centre = self.center_at_frame_time(time);
+        let pdf = self.cone_pdf(origin, centre)?;
+
+        let distance_squared = origin.distance_squared(centre);
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        let direction = sample_direction_in_cone((centre - origin).normalize(), cos_theta_max, u);
+
+        // Rounding can leave a direction at the edge of the cone just missing the sphere
+        let ray = Ray::new(origin, direction, time);
+        collide_ray_with_sphere(&ray, centre, self.radius, 0.0, f32::INFINITY)?;
+
+        Some(ShapeSample { direction, pdf })
+    }
+
+    pub fn pdf_towards(&self, origin: Vec3A, direction: Vec3A, time: f32) -> f32 {
+        let centre = self.center_at_frame_time(time);
+        let ray = Ray::new(origin, direction, time);
+
+        match collide_ray_with_sphere(&ray, centre, self.radius, 0.0, f32::INFINITY) {
+            Some(_) => self.cone_pdf(origin, centre).unwrap_or(0.0),
+            None => 0.0,
+        }
+    }
+
     pub fn get_bounding_box(&self, frame_start_time: f32, frame_end_time: f32) -> Aabb {
         let radius_vec = Vec3A::new(self.radius, self.radius, self.radius);
         let centre_start = self.center_at_frame_time(frame_start_time);
@@ -85,3 +144,1172 @@ impl Sphere {
         build_surrounding_bounding_box(box_start, box_end)
     }
 }
+
+// Padding added to triangle bounds so axis aligned triangles don't produce a zero-width box
+const TRIANGLE_BOUNDING_BOX_PADDING: f32 = 0.0001;
+
+#[derive(Clone, Copy, Debug)]
+pub struct MeshVertex {
+    pub position: u32,
+    pub normal: Option<u32>,
+    pub uv: Option<u32>,
+}
+
+impl MeshVertex {
+    pub fn new(position: u32, normal: Option<u32>, uv: Option<u32>) -> MeshVertex {
+        MeshVertex {
+            position,
+            normal,
+            uv,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MeshFace {
+    pub vertices: [MeshVertex; 3],
+    pub material: u32,
+}
+
+pub struct Mesh {
+    positions: Vec<Vec3A>,
+    normals: Vec<Vec3A>,
+    uvs: Vec<Vec2>,
+    materials: Vec<Arc<Material>>,
+    faces: Vec<MeshFace>,
+}
+
+impl Mesh {
+    pub fn new(
+        positions: Vec<Vec3A>,
+        normals: Vec<Vec3A>,
+        uvs: Vec<Vec2>,
+        materials: Vec<Arc<Material>>,
+    ) -> Mesh {
+        Mesh {
+            positions,
+            normals,
+            uvs,
+            materials,
+            faces: Vec::new(),
+        }
+    }
+    pub fn add_face(&mut self, face: MeshFace) {
+        for vertex in face.vertices {
+            assert!(
+                (vertex.position as usize) < self.positions.len(),
+                "Position index out of range"
+            );
+            if let Some(normal) = vertex.normal {
+                assert!((normal as usize) < self.normals.len(), "Normal index out of range");
+            }
+            if let Some(uv) = vertex.uv {
+                assert!((uv as usize) < self.uvs.len(), "UV index out of range");
+            }
+        }
+        assert!(
+            (face.material as usize) < self.materials.len(),
+            "Material index out of range"
+        );
+        self.faces.push(face);
+    }
+    pub fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+    #[cfg(test)]
+    pub fn faces(&self) -> &[MeshFace] {
+        &self.faces
+    }
+    // Copies of `faces` moved by `transform`, as a mesh of their own. Vertices aren't shared
+    // between the copied faces, this is meant for the few faces that have to be in world space.
+    pub fn transformed(&self, faces: &[usize], transform: Mat4) -> Mesh {
+        let normal_matrix = transform.inverse().transpose();
+        let mut mesh = Mesh::new(Vec::new(), Vec::new(), Vec::new(), self.materials.clone());
+
+        for &face in faces {
+            let face = self.faces[face];
+            let vertices = face.vertices.map(|vertex| {
+                let position = self.positions[vertex.position as usize];
+                mesh.positions.push(transform.transform_point3a(position));
+
+                let normal = vertex.normal.map(|normal| {
+                    let normal = self.normals[normal as usize];
+                    mesh.normals
+                        .push(normal_matrix.transform_vector3a(normal).normalize());
+                    mesh.normals.len() as u32 - 1
+                });
+                let uv = vertex.uv.map(|uv| {
+                    mesh.uvs.push(self.uvs[uv as usize]);
+                    mesh.uvs.len() as u32 - 1
+                });
+
+                MeshVertex::new(mesh.positions.len() as u32 - 1, normal, uv)
+            });
+            mesh.faces.push(MeshFace {
+                vertices,
+                material: face.material,
+            });
+        }
+
+        mesh
+    }
+    pub fn is_face_emissive(&self, face: usize) -> bool {
+        self.materials[self.faces[face].material as usize].is_emissive()
+    }
+    pub fn triangle_colliders(
+        mesh: &Arc<Mesh>,
+        faces: impl IntoIterator<Item = usize>,
+    ) -> Vec<Arc<Collider>> {
+        faces
+            .into_iter()
+            .map(|face| {
+                Arc::new(Collider::TriangleCollider(Triangle {
+                    mesh: mesh.clone(),
+                    face: face as u32,
+                }))
+            })
+            .collect()
+    }
+}
+
+pub struct Triangle {
+    mesh: Arc<Mesh>,
+    face: u32,
+}
+
+impl Triangle {
+    pub fn new(
+        vertices: [Vec3A; 3],
+        normals: Option<[Vec3A; 3]>,
+        uvs: Option<[Vec2; 3]>,
+        material: Arc<Material>,
+    ) -> Triangle {
+        let mut mesh = Mesh::new(
+            vertices.to_vec(),
+            normals.map_or(Vec::new(), |normals| normals.to_vec()),
+            uvs.map_or(Vec::new(), |uvs| uvs.to_vec()),
+            vec![material],
+        );
+        mesh.add_face(MeshFace {
+            vertices: [0, 1, 2].map(|index| {
+                MeshVertex::new(
+                    index,
+                    normals.map(|_| index),
+                    uvs.map(|_| index),
+                )
+            }),
+            material: 0,
+        });
+
+        Triangle {
+            mesh: Arc::new(mesh),
+            face: 0,
+        }
+    }
+    pub fn material(&self) -> &Material {
+        self.mesh.materials[self.mesh_face().material as usize].as_ref()
+    }
+    pub fn mesh(&self) -> &Arc<Mesh> {
+        &self.mesh
+    }
+    pub fn area(&self) -> f32 {
+        let [v0, v1, v2] = self.vertices();
+        0.5 * (v1 - v0).cross(v2 - v0).length()
+    }
+    fn mesh_face(&self) -> &MeshFace {
+        &self.mesh.faces[self.face as usize]
+    }
+    pub fn vertices(&self) -> [Vec3A; 3] {
+        let face = self.mesh_face();
+        face.vertices
+            .map(|vertex| self.mesh.positions[vertex.position as usize])
+    }
+    pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
+        let face = self.mesh_face();
+        let vertices = self.vertices();
+        let hit = collide_ray_with_triangle(ray, vertices, t_min, t_max)?;
+        let weights = hit.barycentric;
+
+        let geometric_normal = (vertices[1] - vertices[0])
+            .cross(vertices[2] - vertices[0])
+            .normalize();
+
+        let mut collision = RayCollision::new(
+            ray.at(hit.time),
+            geometric_normal,
+            hit.time,
+            ray,
+            self.mesh.materials[face.material as usize].clone(),
+        );
+
+        if let [Some(n0), Some(n1), Some(n2)] = face.vertices.map(|vertex| vertex.normal) {
+            let shading_normal = self.mesh.normals[n0 as usize] * weights.x
+                + self.mesh.normals[n1 as usize] * weights.y
+                + self.mesh.normals[n2 as usize] * weights.z;
+            if shading_normal.length_squared() > 0.0 {
+                collision.set_shading_normal(shading_normal.normalize());
+            }
+        }
+
+        let uv = match face.vertices.map(|vertex| vertex.uv) {
+            [Some(uv0), Some(uv1), Some(uv2)] => {
+                self.mesh.uvs[uv0 as usize] * weights.x
+                    + self.mesh.uvs[uv1 as usize] * weights.y
+                    + self.mesh.uvs[uv2 as usize] * weights.z
+            }
+            _ => Vec2::new(weights.y, weights.z),
+        };
+        collision.set_uv(uv);
+        collision.set_tangent(self.tangent(vertices));
+
+        Some(collision)
+    }
+
+    // The way u increases across the face, or along its first edge without UVs
+    fn tangent(&self, vertices: [Vec3A; 3]) -> Vec3A {
+        let edge1 = vertices[1] - vertices[0];
+        let edge2 = vertices[2] - vertices[0];
+
+        if let [Some(uv0), Some(uv1), Some(uv2)] = self.mesh_face().vertices.map(|vertex| vertex.uv) {
+            let uv0 = self.mesh.uvs[uv0 as usize];
+            let delta1 = self.mesh.uvs[uv1 as usize] - uv0;
+            let delta2 = self.mesh.uvs[uv2 as usize] - uv0;
+            let determinant = delta1.x * delta2.y - delta1.y * delta2.x;
+
+            if determinant.abs() > 1.0e-8 {
+                return (edge1 * delta2.y - edge2 * delta1.y) / determinant;
+            }
+        }
+
+        edge1
+    }
+
+    fn solid_angle_pdf(&self, direction: Vec3A, distance: f32) -> f32 {
+        let [v0, v1, v2] = self.vertices();
+        let area_normal = (v1 - v0).cross(v2 - v0);
+        let area = 0.5 * area_normal.length();
+        let cos_theta = (area_normal.normalize().dot(direction)).abs();
+
+        if area == 0.0 || cos_theta < 1.0e-6 {
+            return 0.0;
+        }
+
+        distance * distance / (cos_theta * area)
+    }
+
+    // Samples a point uniformly over the triangle's area
+    pub fn sample_towards(&self, origin: Vec3A, _time: f32, u: Vec2) -> Option<ShapeSample> {
+        let [v0, v1, v2] = self.vertices();
+        let weights = sample_barycentric(u);
+        let point = v0 * weights.x + v1 * weights.y + v2 * weights.z;
+
+        let distance = origin.distance(point);
+        if distance == 0.0 {
+            return None;
+        }
+
+        let direction = (point - origin) / distance;
+        let pdf = self.solid_angle_pdf(direction, distance);
+
+        if pdf == 0.0 {
+            return None;
+        }
+
+        Some(ShapeSample { direction, pdf })
+    }
+
+    pub fn pdf_towards(&self, origin: Vec3A, direction: Vec3A, time: f32) -> f32 {
+        let direction = direction.normalize();
+        let ray = Ray::new(origin, direction, time);
+
+        match collide_ray_with_triangle(&ray, self.vertices(), 0.0, f32::INFINITY) {
+            Some(hit) => self.solid_angle_pdf(direction, hit.time),
+            None => 0.0,
+        }
+    }
+
+    pub fn get_bounding_box(&self, _frame_start_time: f32, _frame_end_time: f32) -> Aabb {
+        let [v0, v1, v2] = self.vertices();
+        let padding = Vec3A::splat(TRIANGLE_BOUNDING_BOX_PADDING);
+
+        Aabb::new(v0.min(v1).min(v2) - padding, v0.max(v1).max(v2) + padding)
+    }
+}
+
+// Padding added along the normal of flat shapes so their bounds never have zero width
+const PLANAR_BOUNDING_BOX_PADDING: f32 = 0.0001;
+
+// Turns the chance of picking a point on a flat shape per unit area into a chance per solid angle
+// seen from a point `distance` away along `direction`
+fn area_to_solid_angle_pdf(normal: Vec3A, area: f32, direction: Vec3A, distance: f32) -> f32 {
+    let cos_theta = normal.dot(direction).abs();
+
+    if area == 0.0 || cos_theta < 1.0e-6 {
+        return 0.0;
+    }
+
+    distance * distance / (cos_theta * area)
+}
+
+// An infinite plane through `point`. It has no finite bounds, so the BVH keeps it out of the tree
+// and tests it against every ray.
+pub struct Plane {
+    pub point: Vec3A,
+    pub normal: Vec3A,
+    pub material: Arc<Material>,
+    tangent: Vec3A,
+    bitangent: Vec3A,
+}
+
+impl Plane {
+    pub fn new(point: Vec3A, normal: Vec3A, material: Arc<Material>) -> Plane {
+        let normal = normal.normalize();
+        let (tangent, bitangent) = orthonormal_basis(normal);
+
+        Plane {
+            point,
+            normal,
+            material,
+            tangent,
+            bitangent,
+        }
+    }
+    pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
+        let time = collide_ray_with_plane(ray, self.point, self.normal, t_min, t_max)?;
+        let hit_point = ray.at(time);
+
+        let mut hit = RayCollision::new(hit_point, self.normal, time, ray, self.material.clone());
+        // One unit of UV per unit of distance, so repeating textures tile the plane
+        let offset = hit_point - self.point;
+        hit.set_uv(Vec2::new(
+            offset.dot(self.tangent),
+            offset.dot(self.bitangent),
+        ));
+        hit.set_tangent(self.tangent);
+
+        Some(hit)
+    }
+    // Infinite along every axis the plane isn't perpendicular to
+    pub fn get_bounding_box(&self, _frame_start_time: f32, _frame_end_time: f32) -> Aabb {
+        let mut minimum = Vec3A::splat(f32::NEG_INFINITY);
+        let mut maximum = Vec3A::splat(f32::INFINITY);
+
+        for axis in 0..3 {
+            if self.normal[axis].abs() > 1.0 - 1.0e-6 {
+                minimum[axis] = self.point[axis] - PLANAR_BOUNDING_BOX_PADDING;
+                maximum[axis] = self.point[axis] + PLANAR_BOUNDING_BOX_PADDING;
+            }
+        }
+
+        Aabb::new(minimum, maximum)
+    }
+}
+
+// A parallelogram with one corner at `corner` and sides along `u` and `v`, facing along u x v
+pub struct Quad {
+    pub corner: Vec3A,
+    pub u: Vec3A,
+    pub v: Vec3A,
+    pub material: Arc<Material>,
+    normal: Vec3A,
+    // Scaled normal that turns a point on the plane into its coordinates along `u` and `v`
+    w: Vec3A,
+    area: f32,
+}
+
+impl Quad {
+    pub fn new(corner: Vec3A, u: Vec3A, v: Vec3A, material: Arc<Material>) -> Quad {
+        let cross = u.cross(v);
+
+        Quad {
+            corner,
+            u,
+            v,
+            material,
+            normal: cross.normalize(),
+            w: cross / cross.length_squared(),
+            area: cross.length(),
+        }
+    }
+    // Where along `u` and `v` the ray crosses the quad, both from 0 to 1 inside it
+    fn collide(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vec2)> {
+        let time = collide_ray_with_plane(ray, self.corner, self.normal, t_min, t_max)?;
+        let offset = ray.at(time) - self.corner;
+        let alpha = self.w.dot(offset.cross(self.v));
+        let beta = self.w.dot(self.u.cross(offset));
+
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        Some((time, Vec2::new(alpha, beta)))
+    }
+    pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
+        let (time, uv) = self.collide(ray, t_min, t_max)?;
+
+        let mut hit =
+            RayCollision::new(ray.at(time), self.normal, time, ray, self.material.clone());
+        hit.set_uv(uv);
+        hit.set_tangent(self.u);
+
+        Some(hit)
+    }
+    // Samples a point uniformly over the quad's area
+    pub fn sample_towards(&self, origin: Vec3A, _time: f32, u: Vec2) -> Option<ShapeSample> {
+        let point = self.corner + self.u * u.x + self.v * u.y;
+
+        let distance = origin.distance(point);
+        if distance == 0.0 {
+            return None;
+        }
+
+        let direction = (point - origin) / distance;
+        let pdf = area_to_solid_angle_pdf(self.normal, self.area, direction, distance);
+
+        if pdf == 0.0 {
+            return None;
+        }
+
+        Some(ShapeSample { direction, pdf })
+    }
+    pub fn pdf_towards(&self, origin: Vec3A, direction: Vec3A, time: f32) -> f32 {
+        let direction = direction.normalize();
+        let ray = Ray::new(origin, direction, time);
+
+        match self.collide(&ray, 0.0, f32::INFINITY) {
+            Some((distance, _)) => {
+                area_to_solid_angle_pdf(self.normal, self.area, direction, distance)
+            }
+            None => 0.0,
+        }
+    }
+    pub fn get_bounding_box(&self, _frame_start_time: f32, _frame_end_time: f32) -> Aabb {
+        let [a, b, c, d] = [
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ];
+        let minimum = a.min(b).min(c).min(d);
+        let maximum = a.max(b).max(c).max(d);
+        let padding = Vec3A::splat(PLANAR_BOUNDING_BOX_PADDING);
+
+        Aabb::new(minimum - padding, maximum + padding)
+    }
+}
+
+// How far the rim of a circle of `radius` facing along `normal` reaches along each axis
+fn disk_extent(normal: Vec3A, radius: f32) -> Vec3A {
+    let extent = (Vec3A::ONE - normal * normal).max(Vec3A::ZERO);
+    Vec3A::new(extent.x.sqrt(), extent.y.sqrt(), extent.z.sqrt()) * radius
+}
+
+// A flat circle around `centre` facing along `normal`
+pub struct Disk {
+    pub centre: Vec3A,
+    pub normal: Vec3A,
+    pub radius: f32,
+    pub material: Arc<Material>,
+    tangent: Vec3A,
+    bitangent: Vec3A,
+}
+
+impl Disk {
+    pub fn new(centre: Vec3A, normal: Vec3A, radius: f32, material: Arc<Material>) -> Disk {
+        let normal = normal.normalize();
+        let (tangent, bitangent) = orthonormal_basis(normal);
+
+        Disk {
+            centre,
+            normal,
+            radius,
+            material,
+            tangent,
+            bitangent,
+        }
+    }
+    fn area(&self) -> f32 {
+        std::f32::consts::PI * self.radius * self.radius
+    }
+    fn collide(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
+        let time = collide_ray_with_plane(ray, self.centre, self.normal, t_min, t_max)?;
+
+        if ray.at(time).distance_squared(self.centre) > self.radius * self.radius {
+            return None;
+        }
+
+        Some(time)
+    }
+    pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
+        let time = self.collide(ray, t_min, t_max)?;
+        let hit_point = ray.at(time);
+
+        let mut hit = RayCollision::new(hit_point, self.normal, time, ray, self.material.clone());
+        // The square the disk fits in maps onto 0 to 1, like a label stuck on it
+        let offset = (hit_point - self.centre) / self.radius;
+        hit.set_uv(Vec2::new(
+            0.5 + 0.5 * offset.dot(self.tangent),
+            0.5 + 0.5 * offset.dot(self.bitangent),
+        ));
+        hit.set_tangent(self.tangent);
+
+        Some(hit)
+    }
+    // Samples a point uniformly over the disk's area
+    pub fn sample_towards(&self, origin: Vec3A, _time: f32, u: Vec2) -> Option<ShapeSample> {
+        let offset = sample_unit_disk(u) * self.radius;
+        let point = self.centre + self.tangent * offset.x + self.bitangent * offset.y;
+
+        let distance = origin.distance(point);
+        if distance == 0.0 {
+            return None;
+        }
+
+        let direction = (point - origin) / distance;
+        let pdf = area_to_solid_angle_pdf(self.normal, self.area(), direction, distance);
+
+        if pdf == 0.0 {
+            return None;
+        }
+
+        Some(ShapeSample { direction, pdf })
+    }
+    pub fn pdf_towards(&self, origin: Vec3A, direction: Vec3A, time: f32) -> f32 {
+        let direction = direction.normalize();
+        let ray = Ray::new(origin, direction, time);
+
+        match self.collide(&ray, 0.0, f32::INFINITY) {
+            Some(distance) => {
+                area_to_solid_angle_pdf(self.normal, self.area(), direction, distance)
+            }
+            None => 0.0,
+        }
+    }
+    pub fn get_bounding_box(&self, _frame_start_time: f32, _frame_end_time: f32) -> Aabb {
+        let extent =
+            disk_extent(self.normal, self.radius) + Vec3A::splat(PLANAR_BOUNDING_BOX_PADDING);
+
+        Aabb::new(self.centre - extent, self.centre + extent)
+    }
+}
+
+// An axis aligned box made of six quads facing outwards
+pub struct Cuboid {
+    pub minimum: Vec3A,
+    pub maximum: Vec3A,
+    sides: Vec<Quad>,
+}
+
+impl Cuboid {
+    // Any two opposite corners
+    pub fn new(corner_a: Vec3A, corner_b: Vec3A, material: Arc<Material>) -> Cuboid {
+        let minimum = corner_a.min(corner_b);
+        let maximum = corner_a.max(corner_b);
+        let size = maximum - minimum;
+        let dx = Vec3A::new(size.x, 0.0, 0.0);
+        let dy = Vec3A::new(0.0, size.y, 0.0);
+        let dz = Vec3A::new(0.0, 0.0, size.z);
+
+        let side = |corner: Vec3A, u: Vec3A, v: Vec3A| Quad::new(corner, u, v, material.clone());
+        let sides = vec![
+            // Front, right, back, left, top and bottom
+            side(Vec3A::new(minimum.x, minimum.y, maximum.z), dx, dy),
+            side(Vec3A::new(maximum.x, minimum.y, maximum.z), -dz, dy),
+            side(Vec3A::new(maximum.x, minimum.y, minimum.z), -dx, dy),
+            side(minimum, dz, dy),
+            side(Vec3A::new(minimum.x, maximum.y, maximum.z), dx, -dz),
+            side(minimum, dx, dz),
+        ];
+
+        Cuboid {
+            minimum,
+            maximum,
+            sides,
+        }
+    }
+    pub fn material(&self) -> &Material {
+        self.sides[0].material.as_ref()
+    }
+    pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
+        let mut closest: Option<RayCollision> = None;
+        let mut closest_time = t_max;
+
+        for side in &self.sides {
+            if let Some(collision) = side.collide_ray(ray, t_min, closest_time) {
+                closest_time = collision.time();
+                closest = Some(collision);
+            }
+        }
+
+        closest
+    }
+    fn area(&self) -> f32 {
+        self.sides.iter().map(|side| side.area).sum()
+    }
+    // Picks a side in proportion to its area, then a point on it
+    pub fn sample_towards(&self, origin: Vec3A, time: f32, u: Vec2) -> Option<ShapeSample> {
+        let total_area = self.area();
+        let mut choice = u.x * total_area;
+        let mut index = 0;
+        while index < self.sides.len() - 1 && choice >= self.sides[index].area {
+            choice -= self.sides[index].area;
+            index += 1;
+        }
+        let side = &self.sides[index];
+        let u = Vec2::new((choice / side.area).clamp(0.0, 1.0), u.y);
+
+        let sample = side.sample_towards(origin, time, u)?;
+        let pdf = self.pdf_towards(origin, sample.direction, time);
+
+        if pdf == 0.0 {
+            return None;
+        }
+
+        Some(ShapeSample {
+            direction: sample.direction,
+            pdf,
+        })
+    }
+    // A direction can pass through two sides, either of which could have been sampled
+    pub fn pdf_towards(&self, origin: Vec3A, direction: Vec3A, time: f32) -> f32 {
+        let total_area = self.area();
+        if total_area == 0.0 {
+            return 0.0;
+        }
+
+        self.sides
+            .iter()
+            .map(|side| side.pdf_towards(origin, direction, time) * side.area / total_area)
+            .sum()
+    }
+    pub fn get_bounding_box(&self, _frame_start_time: f32, _frame_end_time: f32) -> Aabb {
+        let padding = Vec3A::splat(PLANAR_BOUNDING_BOX_PADDING);
+        Aabb::new(self.minimum - padding, self.maximum + padding)
+    }
+}
+
+// Axes of a shape built around `axis`. Rays are moved into them so the shape's equation only
+// has to deal with the z axis.
+struct LocalFrame {
+    tangent: Vec3A,
+    bitangent: Vec3A,
+    axis: Vec3A,
+}
+
+impl LocalFrame {
+    fn new(axis: Vec3A) -> LocalFrame {
+        let axis = axis.normalize();
+        let (tangent, bitangent) = orthonormal_basis(axis);
+
+        LocalFrame {
+            tangent,
+            bitangent,
+            axis,
+        }
+    }
+    fn to_local(&self, vector: Vec3A) -> Vec3A {
+        Vec3A::new(
+            vector.dot(self.tangent),
+            vector.dot(self.bitangent),
+            vector.dot(self.axis),
+        )
+    }
+    fn to_world(&self, vector: Vec3A) -> Vec3A {
+        self.tangent * vector.x + self.bitangent * vector.y + self.axis * vector.z
+    }
+    // Builds the collision for a hit worked out in this frame
+    fn collision(
+        &self,
+        ray: &Ray,
+        time: f32,
+        normal: Vec3A,
+        uv: Vec2,
+        tangent: Vec3A,
+        material: &Arc<Material>,
+    ) -> RayCollision {
+        let mut hit = RayCollision::new(
+            ray.at(time),
+            self.to_world(normal).normalize(),
+            time,
+            ray,
+            material.clone(),
+        );
+        hit.set_uv(uv);
+        hit.set_tangent(self.to_world(tangent));
+        hit
+    }
+}
+
+// How far round the z axis a local point is, from 0 to 1
+fn angle_around_axis(point: Vec3A) -> f32 {
+    point.y.atan2(point.x) / (2.0 * std::f32::consts::PI) + 0.5
+}
+
+// Where a ray in local coordinates crosses the plane z = `height`, if it's within `radius` of the
+// axis there
+fn collide_local_cap(
+    origin: Vec3A,
+    direction: Vec3A,
+    height: f32,
+    radius: f32,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, Vec3A)> {
+    if direction.z == 0.0 {
+        return None;
+    }
+
+    let time = (height - origin.z) / direction.z;
+    if time < t_min || time >= t_max {
+        return None;
+    }
+
+    let point = origin + direction * time;
+    if point.x * point.x + point.y * point.y > radius * radius {
+        return None;
+    }
+
+    Some((time, point))
+}
+
+fn cap_uv(point: Vec3A, radius: f32) -> Vec2 {
+    Vec2::new(0.5 + 0.5 * point.x / radius, 0.5 + 0.5 * point.y / radius)
+}
+
+// A cylinder from the centre of `base` up its axis by `height`, optionally closed at both ends
+pub struct Cylinder {
+    pub base: Vec3A,
+    pub height: f32,
+    pub radius: f32,
+    pub capped: bool,
+    pub velocity: Vec3A,
+    pub material: Arc<Material>,
+    frame: LocalFrame,
+}
+
+impl Cylinder {
+    pub fn new(
+        base: Vec3A,
+        top: Vec3A,
+        radius: f32,
+        capped: bool,
+        material: Arc<Material>,
+        velocity: Vec3A,
+    ) -> Cylinder {
+        Cylinder {
+            base,
+            height: base.distance(top),
+            radius,
+            capped,
+            velocity,
+            material,
+            frame: LocalFrame::new(top - base),
+        }
+    }
+    pub fn base_at_frame_time(&self, time_delta: f32) -> Vec3A {
+        self.base + self.velocity * time_delta
+    }
+    pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
+        let origin = self
+            .frame
+            .to_local(ray.origin - self.base_at_frame_time(ray.time));
+        let direction = self.frame.to_local(ray.direction);
+
+        let mut closest = None;
+        let mut closest_time = t_max;
+
+        let (ox, oy) = (origin.x as f64, origin.y as f64);
+        let (dx, dy) = (direction.x as f64, direction.y as f64);
+        let roots = solve_quadratic(
+            dx * dx + dy * dy,
+            2.0 * (ox * dx + oy * dy),
+            ox * ox + oy * oy - (self.radius * self.radius) as f64,
+        );
+
+        for root in roots {
+            let time = root as f32;
+            if time < t_min || time >= closest_time {
+                continue;
+            }
+
+            let point = origin + direction * time;
+            if (0.0..=self.height).contains(&point.z) {
+                let normal = Vec3A::new(point.x, point.y, 0.0);
+                let uv = Vec2::new(angle_around_axis(point), point.z / self.height);
+                closest = Some((time, normal, uv, Vec3A::new(-point.y, point.x, 0.0)));
+                closest_time = time;
+                break;
+            }
+        }
+
+        if self.capped {
+            for (height, normal) in [(0.0, -1.0), (self.height, 1.0)] {
+                if let Some((time, point)) =
+                    collide_local_cap(origin, direction, height, self.radius, t_min, closest_time)
+                {
+                    let normal = Vec3A::new(0.0, 0.0, normal);
+                    let uv = cap_uv(point, self.radius);
+                    closest = Some((time, normal, uv, Vec3A::X));
+                    closest_time = time;
+                }
+            }
+        }
+
+        let (time, normal, uv, tangent) = closest?;
+        Some(
+            self.frame
+                .collision(ray, time, normal, uv, tangent, &self.material),
+        )
+    }
+    pub fn get_bounding_box(&self, frame_start_time: f32, frame_end_time: f32) -> Aabb {
+        let extent = disk_extent(self.frame.axis, self.radius);
+        let bounds = |time: f32| {
+            let base = self.base_at_frame_time(time);
+            let top = base + self.frame.axis * self.height;
+            Aabb::new(base.min(top) - extent, base.max(top) + extent)
+        };
+
+        build_surrounding_bounding_box(bounds(frame_start_time), bounds(frame_end_time))
+    }
+}
+
+// A cone narrowing from a circle of `radius` around `base` to a point at `apex`, optionally
+// closed at the base
+pub struct Cone {
+    pub base: Vec3A,
+    pub height: f32,
+    pub radius: f32,
+    pub capped: bool,
+    pub velocity: Vec3A,
+    pub material: Arc<Material>,
+    frame: LocalFrame,
+}
+
+impl Cone {
+    pub fn new(
+        base: Vec3A,
+        apex: Vec3A,
+        radius: f32,
+        capped: bool,
+        material: Arc<Material>,
+        velocity: Vec3A,
+    ) -> Cone {
+        Cone {
+            base,
+            height: base.distance(apex),
+            radius,
+            capped,
+            velocity,
+            material,
+            frame: LocalFrame::new(apex - base),
+        }
+    }
+    pub fn base_at_frame_time(&self, time_delta: f32) -> Vec3A {
+        self.base + self.velocity * time_delta
+    }
+    pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
+        let origin = self
+            .frame
+            .to_local(ray.origin - self.base_at_frame_time(ray.time));
+        let direction = self.frame.to_local(ray.direction);
+
+        let mut closest = None;
+        let mut closest_time = t_max;
+
+        // x^2 + y^2 = (k (h - z))^2, the slope k being how fast the radius shrinks
+        let slope = (self.radius / self.height) as f64;
+        let slope2 = slope * slope;
+        let (ox, oy, dx, dy, dz) = (
+            origin.x as f64,
+            origin.y as f64,
+            direction.x as f64,
+            direction.y as f64,
+            direction.z as f64,
+        );
+        let below_apex = (self.height - origin.z) as f64;
+        let roots = solve_quadratic(
+            dx * dx + dy * dy - slope2 * dz * dz,
+            2.0 * (ox * dx + oy * dy + slope2 * below_apex * dz),
+            ox * ox + oy * oy - slope2 * below_apex * below_apex,
+        );
+
+        for root in roots {
+            let time = root as f32;
+            if time < t_min || time >= closest_time {
+                continue;
+            }
+
+            // The equation also has a second cone mirrored through the apex
+            let point = origin + direction * time;
+            if (0.0..=self.height).contains(&point.z) {
+                let mut normal =
+                    Vec3A::new(point.x, point.y, (slope2 as f32) * (self.height - point.z));
+                if is_vec3_af32_near_zero(&normal) {
+                    normal = Vec3A::Z;
+                }
+                let uv = Vec2::new(angle_around_axis(point), point.z / self.height);
+                closest = Some((time, normal, uv, Vec3A::new(-point.y, point.x, 0.0)));
+                closest_time = time;
+                break;
+            }
+        }
+
+        if self.capped {
+            if let Some((time, point)) =
+                collide_local_cap(origin, direction, 0.0, self.radius, t_min, closest_time)
+            {
+                let uv = cap_uv(point, self.radius);
+                closest = Some((time, -Vec3A::Z, uv, Vec3A::X));
+            }
+        }
+
+        let (time, normal, uv, tangent) = closest?;
+        Some(
+            self.frame
+                .collision(ray, time, normal, uv, tangent, &self.material),
+        )
+    }
+    pub fn get_bounding_box(&self, frame_start_time: f32, frame_end_time: f32) -> Aabb {
+        let extent = disk_extent(self.frame.axis, self.radius);
+        let bounds = |time: f32| {
+            let base = self.base_at_frame_time(time);
+            let apex = base + self.frame.axis * self.height;
+            Aabb::new((base - extent).min(apex), (base + extent).max(apex))
+        };
+
+        build_surrounding_bounding_box(bounds(frame_start_time), bounds(frame_end_time))
+    }
+}
+
+// A ring of radius `minor_radius` swept around a circle of radius `major_radius`, like a doughnut
+// lying flat across `axis`
+pub struct Torus {
+    pub centre: Vec3A,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+    pub velocity: Vec3A,
+    pub material: Arc<Material>,
+    frame: LocalFrame,
+}
+
+impl Torus {
+    pub fn new(
+        centre: Vec3A,
+        axis: Vec3A,
+        major_radius: f32,
+        minor_radius: f32,
+        material: Arc<Material>,
+        velocity: Vec3A,
+    ) -> Torus {
+        Torus {
+            centre,
+            major_radius,
+            minor_radius,
+            velocity,
+            material,
+            frame: LocalFrame::new(axis),
+        }
+    }
+    pub fn center_at_frame_time(&self, time_delta: f32) -> Vec3A {
+        self.centre + self.velocity * time_delta
+    }
+    pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
+        let origin = self
+            .frame
+            .to_local(ray.origin - self.center_at_frame_time(ray.time));
+        let direction = self.frame.to_local(ray.direction);
+        let length = direction.length();
+        if length == 0.0 {
+            return None;
+        }
+
+        // Solving from the point on the ray nearest the centre, along a unit direction, keeps the
+        // quartic well conditioned however far away the ray starts
+        let unit = (direction / length).as_dvec3();
+        let start = -origin.as_dvec3().dot(unit);
+        let nearest = origin.as_dvec3() + unit * start;
+
+        let major2 = (self.major_radius * self.major_radius) as f64;
+        let minor2 = (self.minor_radius * self.minor_radius) as f64;
+        let along = nearest.dot(unit);
+        let offset = nearest.length_squared() - major2 - minor2;
+
+        // (|p|^2 - R^2 - r^2)^2 = 4 R^2 (r^2 - z^2) with p = nearest + s unit
+        let roots = solve_quartic([
+            offset * offset + 4.0 * major2 * (nearest.z * nearest.z - minor2),
+            4.0 * along * offset + 8.0 * major2 * nearest.z * unit.z,
+            4.0 * along * along + 2.0 * offset + 4.0 * major2 * unit.z * unit.z,
+            4.0 * along,
+            1.0,
+        ]);
+
+        for root in roots {
+            let time = ((start + root) / length as f64) as f32;
+            if time < t_min || time >= t_max {
+                continue;
+            }
+
+            let point = origin + direction * time;
+            let distance_from_axis = (point.x * point.x + point.y * point.y).sqrt();
+            let ring = if distance_from_axis > 0.0 {
+                Vec3A::new(point.x, point.y, 0.0) * (self.major_radius / distance_from_axis)
+            } else {
+                Vec3A::ZERO
+            };
+
+            let mut normal = point - ring;
+            if is_vec3_af32_near_zero(&normal) {
+                normal = Vec3A::Z;
+            }
+            let uv = Vec2::new(
+                angle_around_axis(point),
+                point.z.atan2(distance_from_axis - self.major_radius)
+                    / (2.0 * std::f32::consts::PI)
+                    + 0.5,
+            );
+
+            return Some(self.frame.collision(
+                ray,
+                time,
+                normal,
+                uv,
+                Vec3A::new(-point.y, point.x, 0.0),
+                &self.material,
+            ));
+        }
+
+        None
+    }
+    pub fn get_bounding_box(&self, frame_start_time: f32, frame_end_time: f32) -> Aabb {
+        let extent = disk_extent(self.frame.axis, self.major_radius + self.minor_radius)
+            + self.frame.axis.abs() * self.minor_radius;
+        let bounds = |time: f32| {
+            let centre = self.center_at_frame_time(time);
+            Aabb::new(centre - extent, centre + extent)
+        };
+
+        build_surrounding_bounding_box(bounds(frame_start_time), bounds(frame_end_time))
+    }
+}
+
+// Any surface a x^2 + b y^2 + c z^2 + d xy + e yz + f xz + g x + h y + i z + j = 0 around
+// `centre`, such as ellipsoids, paraboloids and hyperboloids. Most of them go on forever, `bounds`
+// clips the surface to a box around the centre. Without one the BVH treats it like a plane.
+pub struct Quadric {
+    pub centre: Vec3A,
+    pub coefficients: [f32; 10],
+    pub bounds: Option<Aabb>,
+    pub velocity: Vec3A,
+    pub material: Arc<Material>,
+}
+
+impl Quadric {
+    pub fn new(
+        centre: Vec3A,
+        coefficients: [f32; 10],
+        bounds: Option<Aabb>,
+        material: Arc<Material>,
+        velocity: Vec3A,
+    ) -> Quadric {
+        Quadric {
+            centre,
+            coefficients,
+            bounds,
+            velocity,
+            material,
+        }
+    }
+    pub fn center_at_frame_time(&self, time_delta: f32) -> Vec3A {
+        self.centre + self.velocity * time_delta
+    }
+    fn gradient(&self, point: Vec3A) -> Vec3A {
+        let [a, b, c, d, e, f, g, h, i, _] = self.coefficients;
+        Vec3A::new(
+            2.0 * a * point.x + d * point.y + f * point.z + g,
+            2.0 * b * point.y + d * point.x + e * point.z + h,
+            2.0 * c * point.z + e * point.y + f * point.x + i,
+        )
+    }
+    fn contains(&self, point: Vec3A) -> bool {
+        match &self.bounds {
+            Some(bounds) => point.cmpge(bounds.minimum).all() && point.cmple(bounds.maximum).all(),
+            None => true,
+        }
+    }
+    pub fn collide_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayCollision> {
+        let centre = self.center_at_frame_time(ray.time);
+        let origin = ray.origin - centre;
+        let [a, b, c, d, e, f, g, h, i, j] = self.coefficients.map(|value| value as f64);
+        let (ox, oy, oz) = (origin.x as f64, origin.y as f64, origin.z as f64);
+        let (dx, dy, dz) = (
+            ray.direction.x as f64,
+            ray.direction.y as f64,
+            ray.direction.z as f64,
+        );
+
+        let roots = solve_quadratic(
+            a * dx * dx + b * dy * dy + c * dz * dz + d * dx * dy + e * dy * dz + f * dx * dz,
+            2.0 * (a * ox * dx + b * oy * dy + c * oz * dz)
+                + d * (ox * dy + oy * dx)
+                + e * (oy * dz + oz * dy)
+                + f * (ox * dz + oz * dx)
+                + g * dx
+                + h * dy
+                + i * dz,
+            a * ox * ox
+                + b * oy * oy
+                + c * oz * oz
+                + d * ox * oy
+                + e * oy * oz
+                + f * ox * oz
+                + g * ox
+                + h * oy
+                + i * oz
+                + j,
+        );
+
+        for root in roots {
+            let time = root as f32;
+            if time < t_min || time >= t_max {
+                continue;
+            }
+
+            let point = origin + ray.direction * time;
+            if !self.contains(point) {
+                continue;
+            }
+
+            let mut normal = self.gradient(point);
+            if is_vec3_af32_near_zero(&normal) {
+                normal = -ray.direction;
+            }
+
+            let mut hit = RayCollision::new(
+                ray.at(time),
+                normal.normalize(),
+                time,
+                ray,
+                self.material.clone(),
+            );
+            if !is_vec3_af32_near_zero(&point) {
+                hit.set_uv(sphere_uv(point.normalize()));
+            }
+            return Some(hit);
+        }
+
+        None
+    }
+    // Infinite without `bounds`
+    pub fn get_bounding_box(&self, frame_start_time: f32, frame_end_time: f32) -> Aabb {
+        let local = match &self.bounds {
+            Some(bounds) => *bounds,
+            None => Aabb::new(Vec3A::splat(f32::NEG_INFINITY), Vec3A::splat(f32::INFINITY)),
+        };
+        let bounds = |time: f32| {
+            let centre = self.center_at_frame_time(time);
+            Aabb::new(centre + local.minimum, centre + local.maximum)
+        };
+
+        build_surrounding_bounding_box(bounds(frame_start_time), bounds(frame_end_time))
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    io::Write,
     sync::{Arc, Mutex},
     thread,
     time::{Duration, Instant},
@@ -7,7 +8,10 @@ use std::{
 use crossbeam_channel::Sender;
 use num_format::{Locale, ToFormattedString};
 
-use crate::ui::{pixel::PixelBatchUpdate, text::render_string_at_position};
+use crate::{
+    bounding_box::bvh::BVHStats,
+    ui::{pixel::PixelBatchUpdate, text::render_string_at_position},
+};
 
 #[derive(Clone, Copy)]
 struct ChunksData {
@@ -31,6 +35,7 @@ struct FrameData {
 
 #[derive(Clone, Copy)]
 struct FramesData {
+    #[allow(dead_code)]
     total_frames: u32,
     completed_frames: u32,
 }
@@ -39,6 +44,7 @@ struct FramesData {
 pub struct StatsData {
     current_frame: FrameData,
     frames: FramesData,
+    bvh: Option<BVHStats>,
     pixel_batch_sender: Sender<PixelBatchUpdate>,
 }
 
@@ -84,7 +90,7 @@ fn init(stats: Stats) {
             let progress = completed_chunks as f32 / chunks.total_chunks.max(1) as f32;
             let remaining_chunks = chunks.total_chunks - completed_chunks;
             let per_chunk = current_frame_duration / completed_chunks.max(1);
-            let remaining_chunks_time = per_chunk * remaining_chunks as u32;
+            let remaining_chunks_time = per_chunk * remaining_chunks;
             let rays_per_second = rays.total_rays / current_frame_duration.as_secs().max(1);
 
             let run_time_string = format!(
@@ -107,9 +113,18 @@ fn init(stats: Stats) {
                 rays.total_rays.to_formatted_string(&Locale::en_GB),
                 rays_per_second.to_formatted_string(&Locale::en_GB),
             );
+            let bvh_string = match data.bvh {
+                Some(bvh) => format!(
+                    "BVH:\t{} nodes\n\tDepth : {}\n\tCost  : {:.2}",
+                    bvh.node_count.to_formatted_string(&Locale::en_GB),
+                    bvh.max_depth,
+                    bvh.cost,
+                ),
+                None => String::new(),
+            };
             let update_string = format!(
-                "{}\n{}\n{}\n{}",
-                run_time_string, settings_string, chunks_string, rays_string
+                "{}\n{}\n{}\n{}\n{}",
+                run_time_string, settings_string, chunks_string, rays_string, bvh_string
             );
 
             let pixels_update = render_string_at_position(10, 10, update_string);
@@ -128,6 +143,7 @@ fn init(stats: Stats) {
                 remaining_chunks_time.as_secs(),
                 remaining_chunks_time.subsec_millis()
             );
+            std::io::stdout().flush().ok();
         }
 
         thread::sleep(Duration::from_millis(100));
@@ -143,6 +159,7 @@ impl Stats {
                     total_frames,
                     completed_frames: 0,
                 },
+                bvh: None,
                 pixel_batch_sender,
             })),
         }
@@ -154,6 +171,7 @@ impl Stats {
         let mut data = self.data.lock().unwrap();
         data.current_frame.chunks.completed_chunks += 1;
     }
+    #[allow(dead_code)]
     pub fn create_ray(&self) {
         let mut data = self.data.lock().unwrap();
         data.current_frame.rays.total_rays += 1;
@@ -162,6 +180,10 @@ impl Stats {
         let mut data = self.data.lock().unwrap();
         data.current_frame = create_new_frame(total_chunks, samples_per_pixel);
     }
+    pub fn set_bvh_stats(self, bvh_stats: BVHStats) {
+        let mut data = self.data.lock().unwrap();
+        data.bvh = Some(bvh_stats);
+    }
     pub fn complete_frame(self) {
         let mut data = self.data.lock().unwrap();
         data.current_frame.end_time = Some(Instant::now());
@@ -0,0 +1,331 @@
+use std::{path::Path, sync::Arc};
+
+use glam::{Vec2, Vec3A};
+
+use crate::{color::Color, display::srgb_eotf};
+
+use self::noise::Perlin;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WrapMode {
+    // Tiles the image, UVs outside 0 to 1 wrap around
+    Repeat,
+    // Stretches the edge pixels out forever
+    Clamp,
+}
+
+impl WrapMode {
+    pub fn from_name(name: &str) -> Option<WrapMode> {
+        match name {
+            "repeat" => Some(WrapMode::Repeat),
+            "clamp" => Some(WrapMode::Clamp),
+            _ => None,
+        }
+    }
+
+    fn index(self, index: i64, size: u32) -> usize {
+        match self {
+            WrapMode::Repeat => index.rem_euclid(size as i64) as usize,
+            WrapMode::Clamp => index.clamp(0, size as i64 - 1) as usize,
+        }
+    }
+}
+
+// An image decoded to linear colours once when it is loaded, so lookups only need to blend
+pub struct ImageTexture {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+    wrap: WrapMode,
+}
+
+impl ImageTexture {
+    // Colour images are usually stored sRGB encoded, `srgb` should be false for data that was
+    // already linear
+    pub fn load(path: &Path, wrap: WrapMode, srgb: bool) -> image::ImageResult<ImageTexture> {
+        let image = image::open(path)?.to_rgb16();
+
+        let decode = |value: u16| {
+            let value = value as f32 / u16::MAX as f32;
+            if srgb {
+                srgb_eotf(value)
+            } else {
+                value
+            }
+        };
+
+        Ok(ImageTexture {
+            width: image.width(),
+            height: image.height(),
+            pixels: image
+                .pixels()
+                .map(|pixel| Color::new(decode(pixel[0]), decode(pixel[1]), decode(pixel[2])))
+                .collect(),
+            wrap,
+        })
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = self.wrap.index(x, self.width);
+        let y = self.wrap.index(y, self.height);
+        self.pixels[y * self.width as usize + x]
+    }
+
+    // Bilinear blend of the four texels around `uv`, with v = 0 at the bottom of the image
+    pub fn sample(&self, uv: Vec2) -> Color {
+        let uv = if uv.is_finite() { uv } else { Vec2::ZERO };
+
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f32 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let x_weight = x - x0;
+        let y_weight = y - y0;
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(x0, y0) * (1.0 - x_weight) + self.texel(x0 + 1, y0) * x_weight;
+        let bottom =
+            self.texel(x0, y0 + 1) * (1.0 - x_weight) + self.texel(x0 + 1, y0 + 1) * x_weight;
+
+        top * (1.0 - y_weight) + bottom * y_weight
+    }
+}
+
+// Cubes of `size` alternating between two textures through the whole of space, so any shape cut
+// out of it is checkered without needing UVs
+pub struct CheckerTexture {
+    pub even: Arc<Texture>,
+    pub odd: Arc<Texture>,
+    pub size: f32,
+}
+
+impl CheckerTexture {
+    pub fn value(&self, uv: Vec2, point: Vec3A) -> Color {
+        let cell = (point / self.size).floor();
+
+        if ((cell.x + cell.y + cell.z) as i64).rem_euclid(2) == 0 {
+            self.even.value(uv, point)
+        } else {
+            self.odd.value(uv, point)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoisePattern {
+    // Perlin noise on its own, a soft cloudy blend of the two colours
+    Noise,
+    // Several octaves of noise added together
+    Turbulence,
+    // Bands along z bent by turbulence
+    Marble,
+    // Rings around the y axis bent by turbulence
+    Wood,
+}
+
+impl NoisePattern {
+    pub fn from_name(name: &str) -> Option<NoisePattern> {
+        match name {
+            "noise" => Some(NoisePattern::Noise),
+            "turbulence" => Some(NoisePattern::Turbulence),
+            "marble" => Some(NoisePattern::Marble),
+            "wood" => Some(NoisePattern::Wood),
+            _ => None,
+        }
+    }
+
+    // How far turbulence bends the bands and rings
+    pub fn default_strength(self) -> f32 {
+        match self {
+            NoisePattern::Marble => 5.0,
+            NoisePattern::Wood => 0.5,
+            NoisePattern::Noise | NoisePattern::Turbulence => 0.0,
+        }
+    }
+}
+
+// Blends from `low` to `high` by a pattern built from Perlin noise at the hit point
+pub struct NoiseTexture {
+    pub pattern: NoisePattern,
+    // How many times the pattern repeats per unit, larger is finer
+    pub scale: f32,
+    pub octaves: u32,
+    pub strength: f32,
+    pub low: Color,
+    pub high: Color,
+    perlin: Perlin,
+}
+
+impl NoiseTexture {
+    // The same seed always gives the same pattern
+    pub fn new(pattern: NoisePattern, seed: u64) -> NoiseTexture {
+        NoiseTexture {
+            pattern,
+            scale: 1.0,
+            octaves: 7,
+            strength: pattern.default_strength(),
+            low: Color::zero(),
+            high: Color::one(),
+            perlin: Perlin::new(seed),
+        }
+    }
+
+    pub fn value(&self, point: Vec3A) -> Color {
+        let point = point * self.scale;
+
+        let t = match self.pattern {
+            NoisePattern::Noise => 0.5 * (1.0 + self.perlin.noise(point)),
+            NoisePattern::Turbulence => self.perlin.turbulence(point, self.octaves),
+            NoisePattern::Marble => {
+                let turbulence = self.perlin.turbulence(point, self.octaves);
+                0.5 * (1.0 + (point.z + self.strength * turbulence).sin())
+            }
+            NoisePattern::Wood => {
+                let turbulence = self.perlin.turbulence(point, self.octaves);
+                let rings =
+                    (point.x * point.x + point.z * point.z).sqrt() + self.strength * turbulence;
+                rings - rings.floor()
+            }
+        }
+        .clamp(0.0, 1.0);
+
+        self.low * (1.0 - t) + self.high * t
+    }
+}
+
+// A colour that can vary over a surface, looked up at each hit's UV coordinates and position
+pub enum Texture {
+    Constant(Color),
+    Image(ImageTexture),
+    Checker(CheckerTexture),
+    Noise(NoiseTexture),
+}
+
+impl Texture {
+    pub fn value(&self, uv: Vec2, point: Vec3A) -> Color {
+        match self {
+            Texture::Constant(color) => *color,
+            Texture::Image(image) => image.sample(uv),
+            Texture::Checker(checker) => checker.value(uv, point),
+            Texture::Noise(noise) => noise.value(point),
+        }
+    }
+
+    // For textures standing in for a single number, the average of the three channels
+    pub fn scalar(&self, uv: Vec2, point: Vec3A) -> f32 {
+        let color = self.value(uv, point);
+        (color.r() + color.g() + color.b()) / 3.0
+    }
+}
+
+// A texture that is `value` in all three channels everywhere, for scalar parameters
+pub fn constant_texture(value: f32) -> Arc<Texture> {
+    Arc::new(Texture::Constant(Color::new(value, value, value)))
+}
+
+pub mod noise {
+    use glam::Vec3A;
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    fn fade(t: f32) -> f32 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+
+    fn lerp(t: f32, a: f32, b: f32) -> f32 {
+        a + t * (b - a)
+    }
+
+    // Dot product of the offset with one of twelve gradients along the cube's edges
+    fn gradient(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+        let hash = hash & 15;
+        let u = if hash < 8 { x } else { y };
+        let v = if hash < 4 {
+            y
+        } else if hash == 12 || hash == 14 {
+            x
+        } else {
+            z
+        };
+
+        (if hash & 1 == 0 { u } else { -u }) + (if hash & 2 == 0 { v } else { -v })
+    }
+
+    // Ken Perlin's improved noise, with the permutation shuffled by a seed
+    pub struct Perlin {
+        // Two copies of the same permutation so lookups never need wrapping
+        permutation: Vec<u8>,
+    }
+
+    impl Perlin {
+        pub fn new(seed: u64) -> Perlin {
+            let mut shuffled: Vec<u8> = (0..=255).collect();
+            shuffled.shuffle(&mut StdRng::seed_from_u64(seed));
+
+            Perlin {
+                permutation: shuffled.iter().chain(shuffled.iter()).copied().collect(),
+            }
+        }
+
+        // Smooth noise from roughly -1 to 1, zero at every integer point
+        pub fn noise(&self, point: Vec3A) -> f32 {
+            let cell = point.floor();
+            let x_index = (cell.x as i64 & 255) as usize;
+            let y_index = (cell.y as i64 & 255) as usize;
+            let z_index = (cell.z as i64 & 255) as usize;
+
+            let offset = point - cell;
+            let (x, y, z) = (offset.x, offset.y, offset.z);
+            let (u, v, w) = (fade(x), fade(y), fade(z));
+
+            let p = &self.permutation;
+            let hash = |index: usize| p[index] as usize;
+            let a = hash(x_index) + y_index;
+            let aa = hash(a) + z_index;
+            let ab = hash(a + 1) + z_index;
+            let b = hash(x_index + 1) + y_index;
+            let ba = hash(b) + z_index;
+            let bb = hash(b + 1) + z_index;
+
+            lerp(
+                w,
+                lerp(
+                    v,
+                    lerp(u, gradient(p[aa], x, y, z), gradient(p[ba], x - 1.0, y, z)),
+                    lerp(
+                        u,
+                        gradient(p[ab], x, y - 1.0, z),
+                        gradient(p[bb], x - 1.0, y - 1.0, z),
+                    ),
+                ),
+                lerp(
+                    v,
+                    lerp(
+                        u,
+                        gradient(p[aa + 1], x, y, z - 1.0),
+                        gradient(p[ba + 1], x - 1.0, y, z - 1.0),
+                    ),
+                    lerp(
+                        u,
+                        gradient(p[ab + 1], x, y - 1.0, z - 1.0),
+                        gradient(p[bb + 1], x - 1.0, y - 1.0, z - 1.0),
+                    ),
+                ),
+            )
+        }
+
+        // Octaves of noise, each at twice the frequency and half the weight of the last
+        pub fn turbulence(&self, point: Vec3A, octaves: u32) -> f32 {
+            let mut total = 0.0;
+            let mut point = point;
+            let mut weight = 1.0;
+
+            for _ in 0..octaves {
+                total += weight * self.noise(point).abs();
+                point *= 2.0;
+                weight *= 0.5;
+            }
+
+            total
+        }
+    }
+}
@@ -188,7 +188,14 @@ pub mod text {
 
 pub mod window {
     use log::error;
-    use std::time::Duration;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::JoinHandle,
+        time::Duration,
+    };
 
     use pixels::{Error, Pixels, SurfaceTexture};
     use winit::{
@@ -199,6 +206,8 @@ pub mod window {
     };
     use winit_input_helper::WinitInputHelper;
 
+    use crate::display::DisplayTransform;
+
     use super::pixel::PixelBatchUpdate;
 
     pub struct Window {
@@ -206,23 +215,33 @@ pub mod window {
         window_height: f64,
         texture_width: u32,
         texture_height: u32,
+        display_transform: DisplayTransform,
         pixel_update_batch_receiver: crossbeam_channel::Receiver<PixelBatchUpdate>,
+        render_stop: Arc<AtomicBool>,
+        render_thread: JoinHandle<()>,
     }
 
     impl Window {
+        #[allow(clippy::too_many_arguments)]
         pub fn new(
             window_width: f64,
             window_height: f64,
             texture_width: u32,
             texture_height: u32,
+            display_transform: DisplayTransform,
             pixel_update_batch_receiver: crossbeam_channel::Receiver<PixelBatchUpdate>,
+            render_stop: Arc<AtomicBool>,
+            render_thread: JoinHandle<()>,
         ) -> Window {
             Window {
                 window_width,
                 window_height,
                 texture_width,
                 texture_height,
+                display_transform,
                 pixel_update_batch_receiver,
+                render_stop,
+                render_thread,
             }
         }
         pub fn init(self) -> Result<(), Error> {
@@ -245,6 +264,11 @@ pub mod window {
                 Pixels::new(self.texture_width, self.texture_height, surface_texture)?;
 
             event_loop.run(move |event, _, control_flow| {
+                if self.render_stop.load(Ordering::Relaxed) && self.render_thread.is_finished() {
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+
                 // Draw the current frame
                 if let Event::RedrawRequested(_) = event {
                     if frame_pixels
@@ -270,14 +294,10 @@ pub mod window {
                                         ((pixel_data.position().y * self.texture_width
                                             + pixel_data.position().x)
                                             * 4) as usize;
-                                    let color = pixel_data.color();
-                                    frame_pixels.get_frame()[pixel_index] =
-                                        (color.r() * 255.0) as u8;
-                                    frame_pixels.get_frame()[pixel_index + 1] =
-                                        (color.g() * 255.0) as u8;
-                                    frame_pixels.get_frame()[pixel_index + 2] =
-                                        (color.b() * 255.0) as u8;
-                                    frame_pixels.get_frame()[pixel_index + 3] = 255;
+                                    let color =
+                                        self.display_transform.rgba(pixel_data.color());
+                                    frame_pixels.get_frame()[pixel_index..pixel_index + 4]
+                                        .copy_from_slice(&color.0);
                                     pixels_updated = true;
                                 }
                             }
@@ -293,10 +313,9 @@ pub mod window {
 
                 // Handle input events
                 if input.update(&event) {
-                    // Close events
+                    // Close events, the render is asked to stop so it can save what it has so far
                     if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
-                        *control_flow = ControlFlow::Exit;
-                        return;
+                        self.render_stop.store(true, Ordering::Relaxed);
                     }
 
                     // Resize the window